@@ -0,0 +1,73 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Conversion benchmarks, with and without `x-scopes`, over generated
+//! modules and the modules in `benches/corpus`. Run with `cargo bench`.
+
+#[macro_use]
+extern crate criterion;
+extern crate dwarf_to_json;
+
+use criterion::{black_box, Criterion, Throughput};
+use dwarf_to_json::convert::{convert, ConvertOptions};
+use std::fs;
+use std::path::Path;
+
+mod generate;
+
+use generate::generate_module;
+
+fn read_corpus() -> Vec<(String, Vec<u8>)> {
+    let mut modules = vec![
+        ("generated-small".to_string(), generate_module(4, 8)),
+        ("generated-medium".to_string(), generate_module(64, 64)),
+    ];
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/corpus");
+    let mut corpus: Vec<_> = fs::read_dir(&dir)
+        .expect("failed to read benches/corpus")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "wasm"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let data = fs::read(&path).expect("failed to read corpus module");
+            (name, data)
+        })
+        .collect();
+    corpus.sort();
+    modules.extend(corpus);
+    modules
+}
+
+fn bench_convert(c: &mut Criterion) {
+    for (name, input) in read_corpus() {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function("lines", |b| {
+            let options = ConvertOptions::default();
+            b.iter(|| convert(black_box(&input), &options).unwrap())
+        });
+        group.bench_function("x-scopes", |b| {
+            let options = ConvertOptions {
+                x_scopes: true,
+                ..Default::default()
+            };
+            b.iter(|| convert(black_box(&input), &options).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_convert);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+#!/bin/sh
+# Builds the real-world modules of the benchmark corpus into this directory:
+#
+#  * emscripten-fmt.wasm -- the C++ fmt library and a program using it,
+#    built with `em++ -g -O0` (needs emsdk);
+#  * rustc-regex.wasm -- the regex crate in a cdylib, built by rustc in the
+#    debug profile (needs the wasm32-unknown-unknown target).
+#
+# The sources are pinned, so that the results stay comparable.
+
+set -eu
+
+FMT_VERSION=10.2.1
+REGEX_VERSION=1.10.2
+
+corpus=$(cd "$(dirname "$0")" && pwd)
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+git clone --quiet --depth 1 --branch "$FMT_VERSION" https://github.com/fmtlib/fmt "$work/fmt"
+cat > "$work/main.cc" <<'SOURCE'
+#include <fmt/chrono.h>
+#include <fmt/format.h>
+#include <map>
+#include <string>
+#include <vector>
+
+int main() {
+  std::map<std::string, std::vector<double>> samples{{"a", {1.5, 2.5}}, {"b", {3.25}}};
+  for (const auto& [name, values] : samples) {
+    fmt::print("{:>8}: {:.2f}\n", name, fmt::join(values, ", "));
+  }
+  fmt::print("{}\n", std::chrono::seconds(42));
+}
+SOURCE
+em++ -g -O0 -std=c++17 -I"$work/fmt/include" "$work/fmt/src/format.cc" "$work/main.cc" \
+  -o "$work/fmt.js"
+cp "$work/fmt.wasm" "$corpus/emscripten-fmt.wasm"
+
+mkdir -p "$work/regex/src"
+cat > "$work/regex/Cargo.toml" <<SOURCE
+[package]
+name = "corpus-regex"
+version = "0.1.0"
+edition = "2018"
+
+[lib]
+crate-type = ["cdylib"]
+
+[dependencies]
+regex = "=$REGEX_VERSION"
+SOURCE
+cat > "$work/regex/src/lib.rs" <<'SOURCE'
+use regex::Regex;
+use std::slice;
+use std::str;
+
+/// Counts the e-mail addresses in the UTF-8 text at `ptr`.
+#[no_mangle]
+pub unsafe extern "C" fn count_addresses(ptr: *const u8, len: usize) -> usize {
+    let text = match str::from_utf8(slice::from_raw_parts(ptr, len)) {
+        Ok(text) => text,
+        Err(_) => return 0,
+    };
+    let re = Regex::new(r"\b[\w.+-]+@[\w-]+\.[\w.-]+\b").unwrap();
+    re.find_iter(text).count()
+}
+SOURCE
+cargo build --quiet --manifest-path "$work/regex/Cargo.toml" --target wasm32-unknown-unknown
+cp "$work/regex/target/wasm32-unknown-unknown/debug/corpus_regex.wasm" "$corpus/rustc-regex.wasm"
@@ -0,0 +1,198 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Generation of C-like modules with DWARF, shared by the benchmarks and
+//! the tests.
+
+fn write_uleb(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn write_sleb(output: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn write_section(output: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    output.push(id);
+    write_uleb(output, payload.len() as u64);
+    output.extend_from_slice(payload);
+}
+
+fn write_custom_section(output: &mut Vec<u8>, name: &str, payload: &[u8]) {
+    let mut section = Vec::new();
+    write_uleb(&mut section, name.len() as u64);
+    section.extend_from_slice(name.as_bytes());
+    section.extend_from_slice(payload);
+    write_section(output, 0, &section);
+}
+
+/// Appends the section length in front of a DWARF unit or line program
+/// written after its length field.
+fn with_unit_length(body: Vec<u8>) -> Vec<u8> {
+    let mut unit = (body.len() as u32).to_le_bytes().to_vec();
+    unit.extend(body);
+    unit
+}
+
+const ABBREVIATIONS: &[u8] = &[
+    // 1: compile_unit with children: producer, language, name, stmt_list,
+    // comp_dir, low_pc, high_pc
+    1, 0x11, 1, 0x25, 0x0e, 0x13, 0x05, 0x03, 0x0e, 0x10, 0x17, 0x1b, 0x0e, 0x11, 0x01, 0x12, 0x06, 0, 0,
+    // 2: subprogram with children: low_pc, high_pc, name, decl_file,
+    // decl_line, external
+    2, 0x2e, 1, 0x11, 0x01, 0x12, 0x06, 0x03, 0x0e, 0x3a, 0x0b, 0x3b, 0x05, 0x3f, 0x19, 0, 0,
+    // 3: variable: name, decl_file, decl_line, type, location
+    3, 0x34, 0, 0x03, 0x0e, 0x3a, 0x0b, 0x3b, 0x05, 0x49, 0x13, 0x02, 0x18, 0, 0,
+    // 4: base_type: name, encoding, byte_size
+    4, 0x24, 0, 0x03, 0x0e, 0x3e, 0x0b, 0x0b, 0x0b, 0, 0,
+    // 5: lexical_block with children: low_pc, high_pc
+    5, 0x0b, 1, 0x11, 0x01, 0x12, 0x06, 0, 0,
+    0,
+];
+
+/// The instructions of each function, which have a line row each.
+const FUNCTION_SIZE: u32 = 40;
+
+/// Generates a module of `units` C-like compilation units with `functions`
+/// functions each, with a line row per instruction and a variable in a
+/// lexical block per function, so that the benchmarks and tests do not
+/// depend on modules checked in elsewhere.
+pub fn generate_module(units: u32, functions: u32) -> Vec<u8> {
+    let mut debug_str = Vec::new();
+    let mut string = |s: &str| {
+        let offset = debug_str.len() as u32;
+        debug_str.extend_from_slice(s.as_bytes());
+        debug_str.push(0);
+        offset
+    };
+    let producer = string("generated");
+    let comp_dir = string("/bench");
+    let int_name = string("int");
+    let local_name = string("local");
+
+    // Each body is its local count, nops and end; the addresses are offsets
+    // in the code section payload.
+    let count = units * functions;
+    let mut code = Vec::new();
+    write_uleb(&mut code, u64::from(count));
+    let mut bodies = Vec::new();
+    for _ in 0..count {
+        write_uleb(&mut code, u64::from(FUNCTION_SIZE));
+        bodies.push(code.len() as u32);
+        code.push(0);
+        code.extend(std::iter::repeat(0x01).take(FUNCTION_SIZE as usize - 2));
+        code.push(0x0b);
+    }
+
+    let mut debug_info = Vec::new();
+    let mut debug_line = Vec::new();
+    for unit in 0..units {
+        let unit_bodies = &bodies[(unit * functions) as usize..((unit + 1) * functions) as usize];
+        let name = string(&format!("unit{}.c", unit));
+
+        let mut line = vec![4, 0];
+        let mut header = vec![1, 1, 1, 0xfb, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+        header.extend_from_slice(b"src\0\0");
+        header.extend_from_slice(format!("unit{}.c", unit).as_bytes());
+        header.extend_from_slice(&[0, 1, 0, 0, 0]);
+        line.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        line.extend(header);
+        for (i, &body) in unit_bodies.iter().enumerate() {
+            line.extend_from_slice(&[0, 5, 2]);
+            line.extend_from_slice(&body.to_le_bytes());
+            // advance_line to the function, set_column, copy
+            line.push(3);
+            write_sleb(&mut line, 10 * i as i64);
+            line.extend_from_slice(&[5, 3, 1]);
+            for _ in 1..FUNCTION_SIZE {
+                // One address and one line forward.
+                line.push(33);
+            }
+            // advance_pc past the body, end_sequence
+            line.extend_from_slice(&[2, 1, 0, 1, 1]);
+        }
+        let stmt_list = debug_line.len() as u32;
+        debug_line.extend(with_unit_length(line));
+
+        let first = unit_bodies[0];
+        let last = unit_bodies[unit_bodies.len() - 1] + FUNCTION_SIZE;
+        let mut entries = vec![4, 0, 0, 0, 0, 0, 4, 1];
+        entries.extend_from_slice(&producer.to_le_bytes());
+        // DW_LANG_C99
+        entries.extend_from_slice(&0x0cu16.to_le_bytes());
+        for value in &[name, stmt_list, comp_dir, first, last - first] {
+            entries.extend_from_slice(&value.to_le_bytes());
+        }
+        // The type references are from the start of the unit, past the
+        // length field that is prepended.
+        let int_type = entries.len() as u32 + 4;
+        entries.push(4);
+        entries.extend_from_slice(&int_name.to_le_bytes());
+        entries.extend_from_slice(&[5, 4]);
+        for (i, &body) in unit_bodies.iter().enumerate() {
+            let function_name = string(&format!("function_{}_{}", unit, i));
+            entries.push(2);
+            entries.extend_from_slice(&body.to_le_bytes());
+            entries.extend_from_slice(&FUNCTION_SIZE.to_le_bytes());
+            entries.extend_from_slice(&function_name.to_le_bytes());
+            entries.push(1);
+            entries.extend_from_slice(&(10 * i as u16 + 1).to_le_bytes());
+            entries.push(5);
+            entries.extend_from_slice(&(body + 1).to_le_bytes());
+            entries.extend_from_slice(&(FUNCTION_SIZE - 2).to_le_bytes());
+            entries.push(3);
+            entries.extend_from_slice(&local_name.to_le_bytes());
+            entries.push(1);
+            entries.extend_from_slice(&(10 * i as u16 + 2).to_le_bytes());
+            entries.extend_from_slice(&int_type.to_le_bytes());
+            // DW_OP_fbreg 8
+            entries.extend_from_slice(&[2, 0x91, 8]);
+            // The ends of the lexical block and of the subprogram.
+            entries.extend_from_slice(&[0, 0]);
+        }
+        entries.push(0);
+        debug_info.extend(with_unit_length(entries));
+    }
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    // A single `() -> ()` type for all the functions.
+    write_section(&mut module, 1, &[1, 0x60, 0, 0]);
+    let mut function_section = Vec::new();
+    write_uleb(&mut function_section, u64::from(count));
+    function_section.extend(std::iter::repeat(0).take(count as usize));
+    write_section(&mut module, 3, &function_section);
+    write_section(&mut module, 10, &code);
+    write_custom_section(&mut module, ".debug_abbrev", ABBREVIATIONS);
+    write_custom_section(&mut module, ".debug_info", &debug_info);
+    write_custom_section(&mut module, ".debug_line", &debug_line);
+    write_custom_section(&mut module, ".debug_str", &debug_str);
+    module
+}
@@ -0,0 +1,128 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Converts whole modules. The mutator keeps the section structure valid,
+//! so that the mutations reach the DWARF rather than the module header:
+//! it changes the payload of one section, or duplicates or removes one,
+//! and writes the section sizes back.
+
+#![no_main]
+
+use dwarf_to_json::convert::{convert, ConvertOptions};
+use libfuzzer_sys::{fuzz_mutator, fuzz_target, fuzzer_mutate};
+
+const WASM_HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+fuzz_target!(|data: &[u8]| {
+    let _ = convert(
+        data,
+        &ConvertOptions {
+            x_scopes: true,
+            types: true,
+            globals: true,
+            qualified_names: true,
+            function_offsets: true,
+            column_spans: true,
+            verify: true,
+            ..Default::default()
+        },
+    );
+});
+
+/// A section: its id, and its payload, with the name of custom sections.
+type Section = (u8, Vec<u8>);
+
+fn read_leb128(data: &[u8], position: &mut usize) -> Option<usize> {
+    let mut result = 0usize;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        result |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_sections(data: &[u8]) -> Option<Vec<Section>> {
+    if !data.starts_with(WASM_HEADER) {
+        return None;
+    }
+    let mut sections = Vec::new();
+    let mut position = WASM_HEADER.len();
+    while position < data.len() {
+        let id = data[position];
+        position += 1;
+        let size = read_leb128(data, &mut position)?;
+        let payload = data.get(position..position.checked_add(size)?)?;
+        sections.push((id, payload.to_vec()));
+        position += size;
+    }
+    Some(sections)
+}
+
+fn write_sections(sections: &[Section]) -> Vec<u8> {
+    let mut out = WASM_HEADER.to_vec();
+    for (id, payload) in sections {
+        out.push(*id);
+        write_leb128(&mut out, payload.len());
+        out.extend_from_slice(payload);
+    }
+    out
+}
+
+fuzz_mutator!(|data: &mut [u8], size: usize, max_size: usize, seed: u32| {
+    let mut sections = match read_sections(&data[..size]) {
+        Some(ref sections) if !sections.is_empty() => sections.clone(),
+        _ => return fuzzer_mutate(data, size, max_size),
+    };
+    let index = seed as usize % sections.len();
+    match (seed >> 16) % 8 {
+        0 => {
+            let section = sections[index].clone();
+            sections.insert(index, section);
+        }
+        1 if sections.len() > 1 => {
+            sections.remove(index);
+        }
+        _ => {
+            let payload = &mut sections[index].1;
+            let len = payload.len();
+            payload.resize(len.max(1) * 2 + 16, 0);
+            let capacity = payload.len();
+            let len = fuzzer_mutate(payload, len, capacity);
+            payload.truncate(len);
+        }
+    }
+    let mutated = write_sections(&sections);
+    if mutated.len() > max_size {
+        return fuzzer_mutate(data, size, max_size);
+    }
+    data[..mutated.len()].copy_from_slice(&mutated);
+    mutated.len()
+});
@@ -0,0 +1,201 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#ifndef DWARF_TO_JSON_H
+#define DWARF_TO_JSON_H
+
+/* Generated with cbindgen (`make header`). Do not edit manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Version of the C ABI. Bumped whenever an exported signature or a
+// `#[repr(C)]` struct changes incompatibly.
+#define DWARF_TO_JSON_ABI_VERSION 7
+
+// Emit the `x-scopes` field with the DWARF debug information tree.
+#define DWARF_TO_JSON_X_SCOPES 1
+
+// Emit the `x-function-offsets` field with the function body extents.
+#define DWARF_TO_JSON_FUNCTION_OFFSETS 2
+
+// Emit the `x-column-spans` field with the end column of each mapping.
+#define DWARF_TO_JSON_COLUMN_SPANS 4
+
+// Emit the `types` table in `x-scopes`, referenced by the `type_id` of the
+// entries.
+#define DWARF_TO_JSON_TYPES 8
+
+// Emit the `globals` table in `x-scopes`, with the linear memory address of
+// the global and static variables, and the `types` table.
+#define DWARF_TO_JSON_GLOBALS 16
+
+// Make the typedefs of the `types` table refer to the type at the end of
+// their chain of typedefs.
+#define DWARF_TO_JSON_COLLAPSE_ALIASES 32
+
+// Qualify the names of the `types` table with the enclosing namespaces and
+// types, with the separator of their language.
+#define DWARF_TO_JSON_QUALIFIED_NAMES 64
+
+// Check that the mappings are increasing and within the code section, and
+// that the `x-scopes` ranges nest, reporting the violations as warnings.
+#define DWARF_TO_JSON_VERIFY 128
+
+// Keep the functions in code removed by the linker in `x-scopes`, marked as
+// `dead`.
+#define DWARF_TO_JSON_KEEP_DEAD_FUNCTIONS 256
+
+// Indented JSON.
+#define DWARF_TO_JSON_FORMAT_PRETTY 0
+
+// JSON without insignificant whitespace.
+#define DWARF_TO_JSON_FORMAT_COMPACT 1
+
+// Status codes returned by the C ABI entry points.
+enum DwarfToJsonStatus {
+  DwarfToJsonStatus_Ok = 0,
+  // The input is not a valid WebAssembly binary.
+  DwarfToJsonStatus_WasmError = 1,
+  // The DWARF sections are missing or malformed.
+  DwarfToJsonStatus_DataFormat = 2,
+  // The DWARF parser rejected the input.
+  DwarfToJsonStatus_GimliError = 3,
+  // The JSON serialization failed.
+  DwarfToJsonStatus_OutputError = 4,
+  // A NULL pointer or unknown flag was passed, or a `memory_budget` that
+  // this build cannot measure.
+  DwarfToJsonStatus_InvalidArgument = 5,
+  // The converter panicked; the input most likely triggered a bug.
+  DwarfToJsonStatus_Panic = 6,
+  // The host requested cancellation via `should_cancel`.
+  DwarfToJsonStatus_Cancelled = 7,
+  // The peak heap usage exceeded `memory_budget`.
+  DwarfToJsonStatus_MemoryBudgetExceeded = 8,
+  // The module keeps its DWARF in a separate file named by the
+  // `external_debug_info` section. The output holds that URL.
+  DwarfToJsonStatus_ExternalDebugInfo = 9,
+  // The input is gzip compressed and this build lacks the `gzip`
+  // feature. Brotli streams have no magic number to detect them by, so
+  // without the `brotli` feature they fail with `WasmError` instead.
+  DwarfToJsonStatus_UnsupportedCompression = 10,
+  // The compressed input decompresses to more than 1 GiB.
+  DwarfToJsonStatus_DecompressedSizeExceeded = 11,
+};
+typedef uint32_t DwarfToJsonStatus;
+
+// Conversion options. All fields are fixed-size; zero-initialized options
+// produce the plain source map.
+typedef struct DwarfToJsonOptions {
+  // Bitwise OR of the `DWARF_TO_JSON_*` flags.
+  uint32_t flags;
+  // One of the `DWARF_TO_JSON_FORMAT_*` values.
+  uint32_t format;
+  // Optional; called between compilation units with the completed
+  // percentage (0-100).
+  void (*progress)(void *user_data, uint32_t percent);
+  // Optional; polled between compilation units. A non-zero result stops
+  // the conversion with `DwarfToJsonStatus::Cancelled`.
+  uint32_t (*should_cancel)(void *user_data);
+  // Passed back unchanged to the callbacks.
+  void *user_data;
+  // Peak heap usage in bytes after which the conversion stops with
+  // `DwarfToJsonStatus::MemoryBudgetExceeded`; 0 means unlimited. It is
+  // checked between compilation units, so the actual peak may overshoot
+  // by the memory needed for one unit. The usage is only measured when
+  // the library is built with the `counting-allocator` feature;
+  // otherwise a non-zero budget is rejected with
+  // `DwarfToJsonStatus_InvalidArgument`.
+  uint64_t memory_budget;
+} DwarfToJsonOptions;
+
+// Conversion result. On success `data` points to `len` bytes of UTF-8 JSON
+// (not NUL terminated) that are owned by the caller and must be released
+// with `free_mem`. With `DwarfToJsonStatus::ExternalDebugInfo` it holds the
+// companion file URL instead. On other failures `data` is NULL and `len`
+// is 0.
+typedef struct DwarfToJsonOutput {
+  uint8_t *data;
+  uintptr_t len;
+} DwarfToJsonOutput;
+
+// A debug section provided by the host, e.g. `.debug_info`. Both the name
+// and the data are borrowed for the duration of the conversion call.
+typedef struct DwarfToJsonSection {
+  // UTF-8 section name, not NUL terminated.
+  const uint8_t *name;
+  uintptr_t name_len;
+  const uint8_t *data;
+  uintptr_t data_len;
+} DwarfToJsonSection;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns `DWARF_TO_JSON_ABI_VERSION`, so hosts can check compatibility
+// before calling any other entry point.
+uint32_t abi_version(void);
+
+// Allocates `size` bytes in the converter's memory. The block must be
+// released with `free_mem`.
+uint8_t *alloc_mem(uintptr_t size);
+
+// Releases a block returned by `alloc_mem` or by a conversion entry point.
+void free_mem(uint8_t *p);
+
+// Legacy entry point used by the JavaScript wrappers: converts the `wasm`
+// module and stores the JSON location and length in `output` and
+// `output_len`. Prefer `convert_dwarf_with_options`.
+bool convert_dwarf(const uint8_t *wasm,
+                   uintptr_t wasm_len,
+                   const uint8_t **output,
+                   uintptr_t *output_len,
+                   bool enabled_x_scopes);
+
+// Converts the `input_len` bytes of the wasm module at `input`. The input
+// is only borrowed for the duration of the call. `options` may be NULL to
+// use the defaults.
+DwarfToJsonStatus convert_dwarf_with_options(const uint8_t *input,
+                                             uintptr_t input_len,
+                                             const DwarfToJsonOptions *options,
+                                             DwarfToJsonOutput *output);
+
+// Converts debug sections that the host already extracted from a module,
+// without rescanning the binary. `sections` points to `section_count`
+// descriptors; `code_section_offset` is the offset of the code section
+// payload in the original module. `options` may be NULL. Passing the same
+// section twice, e.g. as `debug_info` and `.debug_info`, is rejected with
+// `DwarfToJsonStatus_InvalidArgument`.
+DwarfToJsonStatus convert_dwarf_sections(const DwarfToJsonSection *sections,
+                                         uintptr_t section_count,
+                                         uint64_t code_section_offset,
+                                         const DwarfToJsonOptions *options,
+                                         DwarfToJsonOutput *output);
+
+// Stores the build id of the wasm module at `input` in `output` as a
+// lowercase hex string. `output` stays empty if the module has no
+// `build_id` section.
+DwarfToJsonStatus read_module_build_id(const uint8_t *input,
+                                       uintptr_t input_len,
+                                       DwarfToJsonOutput *output);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* DWARF_TO_JSON_H */
@@ -0,0 +1,158 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::hint;
+use std::ptr;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+thread_local! {
+    /// The usage the allocations of the thread are counted in, if any.
+    static CURRENT: Cell<*const Usage> = const { Cell::new(ptr::null()) };
+}
+
+/// The heap usage of one conversion: the allocations made by the threads
+/// working on it, from `Usage::track` and `attach`, minus the ones they
+/// freed. It is only counted when `CountingAllocator` is the global
+/// allocator.
+#[derive(Default)]
+pub(crate) struct Usage {
+    allocated: AtomicIsize,
+    peak: AtomicUsize,
+}
+
+impl Usage {
+    pub fn new() -> Self {
+        Usage::default()
+    }
+
+    fn add(&self, size: usize) {
+        let allocated = self.allocated.fetch_add(size as isize, Ordering::Relaxed) + size as isize;
+        if allocated > 0 {
+            self.peak.fetch_max(allocated as usize, Ordering::Relaxed);
+        }
+    }
+
+    fn sub(&self, size: usize) {
+        self.allocated.fetch_sub(size as isize, Ordering::Relaxed);
+    }
+
+    /// Highest heap usage in bytes since the usage was created.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Counts the allocations of the current thread in this usage until the
+    /// returned guard is dropped.
+    pub fn track(&self) -> Tracking {
+        Tracking {
+            previous: CURRENT.with(|current| current.replace(self)),
+        }
+    }
+}
+
+/// Restores the usage the thread counted in before `Usage::track`.
+pub(crate) struct Tracking {
+    previous: *const Usage,
+}
+
+impl Drop for Tracking {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// The usage a thread counts in, to count the work it hands to other
+/// threads in it as well.
+#[cfg(feature = "parallel")]
+#[derive(Clone, Copy)]
+pub(crate) struct Attachment(*const Usage);
+
+// The `Tracking` guard of the thread keeps the usage alive while it is
+// current, and the work it hands out is joined within that time.
+#[cfg(feature = "parallel")]
+unsafe impl Send for Attachment {}
+#[cfg(feature = "parallel")]
+unsafe impl Sync for Attachment {}
+
+#[cfg(feature = "parallel")]
+impl Attachment {
+    /// Returns the usage of the current thread.
+    pub(crate) fn current() -> Self {
+        Attachment(CURRENT.with(|current| current.get()))
+    }
+
+    /// Runs `f` with its allocations counted in the usage.
+    pub(crate) fn run<T, F: FnOnce() -> T>(self, f: F) -> T {
+        let previous = CURRENT.with(|current| current.replace(self.0));
+        let _tracking = Tracking { previous };
+        f()
+    }
+}
+
+/// Whether the allocations are counted, i.e. `CountingAllocator` is the
+/// global allocator. Without it a memory budget cannot be enforced.
+pub(crate) fn is_counting() -> bool {
+    let usage = Usage::new();
+    {
+        let _tracking = usage.track();
+        drop(hint::black_box(Box::new(0u64)));
+    }
+    usage.peak() > 0
+}
+
+fn with_current<F: FnOnce(&Usage)>(f: F) {
+    // The thread-local may be gone while the thread exits.
+    if let Ok(usage) = CURRENT.try_with(|current| current.get()) {
+        if let Some(usage) = unsafe { usage.as_ref() } {
+            f(usage);
+        }
+    }
+}
+
+/// System allocator that counts the heap usage of the conversions, so they
+/// can be stopped once they exceed a memory budget. The binaries install it
+/// as their global allocator; the library only does with the
+/// `counting-allocator` feature, so that the programs using it can keep
+/// their own.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        if !p.is_null() {
+            with_current(|usage| usage.add(layout.size()));
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        System.dealloc(p, layout);
+        with_current(|usage| usage.sub(layout.size()));
+    }
+
+    unsafe fn realloc(&self, p: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_p = System.realloc(p, layout, new_size);
+        if !new_p.is_null() {
+            if new_size > layout.size() {
+                with_current(|usage| usage.add(new_size - layout.size()));
+            } else {
+                with_current(|usage| usage.sub(layout.size() - new_size));
+            }
+        }
+        new_p
+    }
+}
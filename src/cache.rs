@@ -0,0 +1,117 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// On-disk cache of conversion results. The key covers everything the output
+// depends on (the debug and metadata sections, the code layout and the
+// options), so converting an unchanged module again, e.g. in a rebuild loop
+// that relinks without touching the DWARF, reuses the previous output.
+// Modules that did change still reuse the line tables of their unchanged
+// compilation units, which are cached separately. Their scopes are generated
+// again: the type table and the source indices of `x-scopes` are shared by
+// all the units, so the output of a unit depends on the others.
+
+use crate::sha256::Sha256;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Version of the cached data, bumped whenever the output for the same input
+/// and options changes, or the encoding of the entries does.
+const FORMAT_VERSION: u32 = 1;
+
+pub struct CacheKey {
+    hasher: Sha256,
+    extension: &'static str,
+}
+
+impl CacheKey {
+    /// Starts the key of an entry of the given kind, which is the extension
+    /// of its file.
+    pub fn new(extension: &'static str) -> Self {
+        let mut key = CacheKey {
+            hasher: Sha256::new(),
+            extension,
+        };
+        key.add_u64(u64::from(FORMAT_VERSION));
+        key.add_bytes(env!("CARGO_PKG_VERSION").as_bytes());
+        key.add_bytes(extension.as_bytes());
+        key
+    }
+
+    /// Adds bytes, prefixed with their length so that the boundaries of the
+    /// values are part of the key.
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        self.add_u64(bytes.len() as u64);
+        self.hasher.update(bytes);
+    }
+
+    pub fn add_optional_bytes(&mut self, bytes: Option<&[u8]>) {
+        self.add_bool(bytes.is_some());
+        if let Some(bytes) = bytes {
+            self.add_bytes(bytes);
+        }
+    }
+
+    pub fn add_u64(&mut self, value: u64) {
+        self.hasher.update(&value.to_le_bytes());
+    }
+
+    pub fn add_bool(&mut self, value: bool) {
+        self.hasher.update(&[value as u8]);
+    }
+
+    /// Returns the key of another entry for the same values, e.g. the
+    /// warnings next to an output.
+    pub fn companion(&self, extension: &'static str) -> Self {
+        let mut key = CacheKey {
+            hasher: self.hasher.clone(),
+            extension,
+        };
+        key.add_bytes(extension.as_bytes());
+        key
+    }
+
+    fn path(&self, dir: &Path) -> PathBuf {
+        let digest: String = self
+            .hasher
+            .clone()
+            .digest()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        dir.join(format!("{}.{}", digest, self.extension))
+    }
+}
+
+pub fn load(dir: &Path, key: &CacheKey) -> Option<Vec<u8>> {
+    fs::read(key.path(dir)).ok()
+}
+
+/// Numbers the temporary files of the process, so that threads storing the
+/// same entry do not write to the same file.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Stores a result. It is written to a temporary file that is renamed, so
+/// that concurrent conversions never read a partial entry.
+pub fn store(dir: &Path, key: &CacheKey, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = key.path(dir);
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path = path.with_extension(format!("{}.{}.{}.tmp", key.extension, process::id(), counter));
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, &path)
+}
@@ -13,25 +13,65 @@
  * limitations under the License.
  */
 
+use crate::budget;
+use crate::cache::{self, CacheKey};
 use crate::dwarf;
-use crate::dwarf::{get_debug_loc, get_debug_scopes, LocationInfo};
+use crate::dwarf::{get_debug_loc, read_debug_scopes, DebugInfoObj, DwarfContext, FunctionBodies, LocationInfo};
+use crate::elf;
+use crate::globals::{GlobalTable, GLOBAL_ATTRS};
+use crate::input::decode_input;
+use crate::reloc::{apply_relocations, read_function_bodies, read_imported_function_count};
+use crate::split::AddressMap;
 use gimli;
 use serde_json;
-use crate::to_json::convert_debug_info_to_json;
-use crate::wasm::{WasmDecoder, WasmFormatError};
+use serde_json::{Map, Value};
+use crate::to_json::{convert_debug_info_to_json, convert_scopes_to_json, ScopesWriter};
+pub use crate::to_json::{OutputFormat, OUTPUT_SCHEMA};
+use crate::types::{TypeTable, TYPE_ATTRS};
+use crate::verify::{self, ScopesVerifier};
+use crate::wasm::{
+    write_custom_section, write_str, Context, WasmDecoder, WasmFormatError, WasmFormatErrorKind,
+};
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
 use std::str;
+use std::sync::Arc;
 
-const WASM_SECTION_CODE: u32 = 10;
 const WASM_SECTION_CUSTOM: u32 = 0;
+const WASM_SECTION_IMPORT: u32 = 2;
+const WASM_SECTION_CODE: u32 = 10;
+
+const WASM_HEADER: &[u8] = b"\x00asm\x01\x00\x00\x00";
+
+const WASM_DYLINK_MEM_INFO: u8 = 1;
+const WASM_DYLINK_NEEDED: u8 = 2;
+
+const COMPONENT_SECTION_CORE_MODULE: u32 = 1;
+const COMPONENT_SECTION_COMPONENT: u32 = 4;
 
 #[derive(Debug)]
 pub enum Error {
     GimliError(gimli::Error),
     DataFormat,
-    WasmError,
+    WasmError(WasmFormatError),
     OutputError,
+    Cancelled,
+    MemoryBudgetExceeded,
+    /// The DWARF data lives in the separate file referenced by the
+    /// `external_debug_info` section, and it could not be loaded.
+    ExternalDebugInfo(String),
+    /// The input is compressed with a format this build cannot decode.
+    UnsupportedCompression,
+    /// The text format input could not be assembled.
+    TextFormat(String),
+    /// The compressed input decompresses to more than the given number of
+    /// bytes.
+    DecompressedSizeExceeded(usize),
 }
 
 impl From<dwarf::Error> for Error {
@@ -40,99 +80,1385 @@ impl From<dwarf::Error> for Error {
             dwarf::Error::GimliError(e) => Error::GimliError(e),
             dwarf::Error::MissingDwarfEntry | dwarf::Error::MissingSection
                                             | dwarf::Error::DataFormat => Error::DataFormat,
+            dwarf::Error::Cancelled => Error::Cancelled,
+            dwarf::Error::MemoryBudgetExceeded => Error::MemoryBudgetExceeded,
         }
     }
 }
 
 impl From<WasmFormatError> for Error {
-    fn from(_: WasmFormatError) -> Self {
-        Error::WasmError
+    fn from(err: WasmFormatError) -> Self {
+        Error::WasmError(err)
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::GimliError(err) => write!(f, "invalid DWARF: {:?}", err),
+            Error::DataFormat => write!(f, "missing or malformed DWARF sections"),
+            Error::WasmError(err) => write!(f, "invalid wasm: {}", err),
+            Error::OutputError => write!(f, "failed to serialize the JSON"),
+            Error::Cancelled => write!(f, "the conversion was cancelled"),
+            Error::MemoryBudgetExceeded => write!(f, "the memory budget was exceeded"),
+            Error::ExternalDebugInfo(url) => write!(f, "the DWARF is in the external file {}", url),
+            Error::UnsupportedCompression => {
+                write!(f, "the input is compressed with an unsupported format")
+            }
+            Error::TextFormat(message) => write!(f, "invalid text format: {}", message),
+            Error::DecompressedSizeExceeded(limit) => {
+                write!(f, "the input decompresses to more than {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<std::fmt::Error> for Error {
     fn from(_: std::fmt::Error) -> Self {
         Error::OutputError
     }
 }
 
+/// Which copy of a custom section that occurs more than once is used.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DuplicateSectionPolicy {
+    First,
+    /// The default, matching older versions.
+    Last,
+    /// Fail the conversion with `Error::DataFormat`. Concatenating copies is
+    /// not an option: offsets into the later ones (e.g. `.debug_str`) would
+    /// no longer match.
+    Reject,
+}
+
+pub struct ConvertOptions<'a> {
+    /// Emit the `x-scopes` field with the DWARF debug information tree.
+    pub x_scopes: bool,
+    /// Restricts the `x-scopes` attributes to these names, as they appear in
+    /// the output (e.g. `"name"`). The `uid` and the address attributes are
+    /// always kept.
+    pub attributes: Option<&'a [&'a str]>,
+    /// Emit the `types` table in `x-scopes`, which the `type_id` of the
+    /// entries refer to.
+    pub types: bool,
+    /// Emit the `globals` table in `x-scopes`, with the linear memory
+    /// address of the variables that have one. It includes the `types`.
+    pub globals: bool,
+    /// Makes the typedefs of the `types` table refer to the type at the end
+    /// of their chain of typedefs, e.g. `unsigned long` for `size_t` even
+    /// when it is defined as another typedef.
+    pub collapse_aliases: bool,
+    /// Qualifies the names of the `types` table with the enclosing
+    /// namespaces and types, with the separator of their language, e.g.
+    /// `std::string` in C++.
+    pub qualified_names: bool,
+    /// Checks that the mappings have increasing addresses within the code
+    /// section and that the ranges of the `x-scopes` entries are within the
+    /// ones of their parent. The violations are reported as warnings.
+    pub verify: bool,
+    /// Keeps the functions in code removed by the linker in `x-scopes`,
+    /// marked as `dead`, instead of leaving them out. They are reported as
+    /// warnings either way.
+    pub keep_dead_functions: bool,
+    /// Emit the `x-function-offsets` field (see `read_function_offsets`).
+    pub function_offsets: bool,
+    /// Emit the `x-column-spans` field with the end column of each mapping.
+    pub column_spans: bool,
+    pub format: OutputFormat,
+    /// Called with the completed percentage before each compilation unit
+    /// is processed; returning `false` cancels the conversion.
+    pub progress: Option<&'a dyn Fn(u32) -> bool>,
+    /// Peak heap usage of the conversion in bytes after which it is stopped.
+    /// It is checked between compilation units and requires
+    /// `budget::CountingAllocator` to be the global allocator, which the
+    /// programs using the library install themselves; without it the
+    /// budget is not enforced and a warning says so.
+    pub memory_budget: Option<usize>,
+    /// Loads the companion file referenced by the `external_debug_info`
+    /// section (e.g. produced by emscripten's `-gseparate-dwarf`).
+    pub external_debug_info_loader: Option<&'a dyn Fn(&str) -> Option<Vec<u8>>>,
+    /// Loads the source files, which are then embedded as `sourcesContent`.
+    /// The files that are not found are `null` there and reported as
+    /// warnings.
+    pub source_loader: Option<&'a dyn Fn(&str) -> Option<Vec<u8>>>,
+    pub duplicate_sections: DuplicateSectionPolicy,
+    /// Offset of the code section payload in the original module, for
+    /// inputs without a code section such as the `-gseparate-dwarf` sidecar.
+    /// It overrides the offset found in the input.
+    pub code_section_offset: Option<u64>,
+    /// The module produced by wasm-split from the input that the map is
+    /// for. The input provides the DWARF, which describes its functions
+    /// before the split; they are matched by their names.
+    pub split_module: Option<&'a [u8]>,
+    /// Receives descriptions of recoverable problems with the input.
+    pub warning: Option<&'a dyn Fn(&str)>,
+    /// Directory of the conversion cache. A module whose debug information
+    /// and code layout did not change since an earlier conversion with the
+    /// same options gets the stored output and warnings; otherwise the
+    /// decoded line programs of the units that did not change are reused,
+    /// and the scopes are generated again.
+    pub cache_dir: Option<&'a Path>,
+}
+
+impl<'a> Default for ConvertOptions<'a> {
+    fn default() -> Self {
+        ConvertOptions {
+            x_scopes: false,
+            attributes: None,
+            types: false,
+            globals: false,
+            collapse_aliases: false,
+            qualified_names: false,
+            verify: false,
+            keep_dead_functions: false,
+            function_offsets: false,
+            column_spans: false,
+            format: OutputFormat::Pretty,
+            progress: None,
+            memory_budget: None,
+            external_debug_info_loader: None,
+            source_loader: None,
+            duplicate_sections: DuplicateSectionPolicy::Last,
+            code_section_offset: None,
+            split_module: None,
+            warning: None,
+            cache_dir: None,
+        }
+    }
+}
+
+/// The recoverable problems of a conversion, which are passed to the
+/// `warning` callback and listed in `x-warnings`, so that the map of a
+/// module whose debug information was partly left out can be told from the
+/// one of a module without debug information.
+struct Warnings<'a> {
+    callback: Option<&'a dyn Fn(&str)>,
+    messages: RefCell<Vec<String>>,
+}
+
+impl<'a> Warnings<'a> {
+    fn new(options: &ConvertOptions<'a>) -> Self {
+        Warnings {
+            callback: options.warning,
+            messages: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        if let Some(callback) = self.callback {
+            callback(message);
+        }
+        self.messages.borrow_mut().push(message.to_string());
+    }
+
+    /// Reports the warnings of an earlier conversion again.
+    fn replay(&self, messages: &[String]) {
+        for message in messages {
+            self.warn(message);
+        }
+    }
+
+    fn to_json(&self) -> Option<Value> {
+        let messages = self.messages.borrow();
+        if messages.is_empty() {
+            None
+        } else {
+            Some(json!(*messages))
+        }
+    }
+}
+
 fn is_debug_section_name(section_name: &str) -> bool {
     section_name.len() >= 7 && &section_name[0..7] == ".debug_"
 }
 
-fn is_url_prefixes_name(section_name: &str) -> bool {
-    section_name == "sourceURLPrefixes"
+/// Non-debug custom sections that carry information for the output.
+const METADATA_SECTION_NAMES: &[&str] = &[
+    "sourceURLPrefixes",
+    "producers",
+    "target_features",
+    "build_id",
+    "dylink.0",
+    "external_debug_info",
+    "sourceMappingURL",
+];
+
+fn is_metadata_section_name(section_name: &str) -> bool {
+    METADATA_SECTION_NAMES.contains(&section_name)
 }
 
-fn read_debug_sections(
-    input: &[u8],
-) -> Result<(HashMap<&str, &[u8]>, Option<usize>), WasmFormatError> {
-    let (header, sections) = input.split_at(8);
-    if header != b"\x00asm\x01\x00\x00\x00" {
-        return Err(WasmFormatError);
-    }
-    let mut decoder = WasmDecoder::new(sections);
-    let mut sections = HashMap::new();
-    let mut code_section_start = None;
+fn is_linking_section_name(section_name: &str) -> bool {
+    section_name == "linking" || section_name.starts_with("reloc.")
+}
+
+/// The parts of a wasm module used by the conversion.
+pub(crate) struct WasmSections<'a> {
+    /// Debug, metadata and linking custom sections by name.
+    pub sections: HashMap<&'a str, &'a [u8]>,
+    /// Absolute offset of the code section payload (past its id and size
+    /// fields) from the start of the module, so it does not depend on the
+    /// custom sections placed before the code section.
+    pub code_section_offset: Option<u64>,
+    pub code_section: Option<&'a [u8]>,
+    pub import_section: Option<&'a [u8]>,
+    /// Custom section names by section index, with the length of their name
+    /// field (relocation offsets include it).
+    pub custom_sections: HashMap<u32, (&'a str, usize)>,
+    /// Earlier copies of the sections that occur more than once, in order.
+    pub duplicate_sections: Vec<(&'a str, &'a [u8])>,
+}
+
+pub(crate) fn read_debug_sections(input: &[u8]) -> Result<WasmSections, WasmFormatError> {
+    if input.len() < WASM_HEADER.len() || &input[..WASM_HEADER.len()] != WASM_HEADER {
+        return Err(WasmFormatError::new(WasmFormatErrorKind::InvalidHeader, 0));
+    }
+    let mut decoder = WasmDecoder::at(&input[WASM_HEADER.len()..], WASM_HEADER.len());
+    let mut result = WasmSections {
+        sections: HashMap::new(),
+        code_section_offset: None,
+        code_section: None,
+        import_section: None,
+        custom_sections: HashMap::new(),
+        duplicate_sections: Vec::new(),
+    };
+    let mut section_index = 0;
     while !decoder.eof() {
-        let section_id = decoder.u32()?;
-        let section_len = decoder.u32()?;
+        let section_id = decoder.u32().context("section id")?;
+        let section_len = decoder.u32().context("section size")?;
+        section_index += 1;
         if section_id != WASM_SECTION_CUSTOM {
+            let section_start = decoder.offset();
+            let body = decoder.skip(section_len as usize).context("section payload")?;
+            let known_section = match section_id {
+                WASM_SECTION_CODE => &mut result.code_section,
+                WASM_SECTION_IMPORT => &mut result.import_section,
+                _ => continue,
+            };
+            // Only custom sections may repeat.
+            if known_section.is_some() {
+                return Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, section_start))
+                    .context("duplicate section");
+            }
+            *known_section = Some(body);
             if section_id == WASM_SECTION_CODE {
-                let offset_from_start = input.len() - decoder.len();
-                code_section_start = Some(offset_from_start);
+                result.code_section_offset = Some(section_start as u64);
             }
-
-            decoder.skip(section_len as usize)?;
             continue;
         }
-        let pos = decoder.len();
-        let section_name = decoder.str()?;
-        let section_name_len = pos - decoder.len();
-        let body = decoder.skip(section_len as usize - section_name_len)?;
-        if !is_debug_section_name(section_name) && !is_url_prefixes_name(section_name) {
+        let pos = decoder.offset();
+        let section_name = decoder.str().context("section name")?;
+        let section_name_len = decoder.offset() - pos;
+        let section_name = dwarf::canonical_section_name(section_name).unwrap_or(section_name);
+        let payload_len = (section_len as usize)
+            .checked_sub(section_name_len)
+            .ok_or_else(|| WasmFormatError::new(WasmFormatErrorKind::InvalidValue, pos))
+            .context("section name")?;
+        let body = decoder.skip(payload_len).context("section payload")?;
+        // The function names match the functions of split modules.
+        if !is_debug_section_name(section_name)
+            && !is_metadata_section_name(section_name)
+            && !is_linking_section_name(section_name)
+            && section_name != "name"
+        {
             continue;
         }
-        sections.insert(section_name, body);
+        result.custom_sections.insert(section_index - 1, (section_name, section_name_len));
+        if let Some(previous) = result.sections.insert(section_name, body) {
+            result.duplicate_sections.push((section_name, previous));
+        }
     }
-    Ok((sections, code_section_start))
+    Ok(result)
 }
 
-fn fix_source_urls(info: &mut LocationInfo, prefixes_bytes: &[u8]) -> Result<(), WasmFormatError> {
+fn fix_source_urls(sources: &mut [Arc<str>], prefixes_bytes: &[u8]) -> Result<(), WasmFormatError> {
     let mut prefixes_decoder = WasmDecoder::new(prefixes_bytes);
     let prefixes_pairs: Vec<Vec<String>> =
-        serde_json::from_str(prefixes_decoder.str()?).unwrap_or(vec![]);
+        serde_json::from_str(prefixes_decoder.str().context("sourceURLPrefixes section")?)
+            .unwrap_or_default();
+    // The pairs that are not exactly `[prefix, replacement]` are ignored.
+    let prefixes_pairs: Vec<(&str, &str)> = prefixes_pairs
+        .iter()
+        .filter_map(|pair| match pair.as_slice() {
+            [prefix, replacement] => Some((prefix.as_str(), replacement.as_str())),
+            _ => None,
+        }).collect();
     if prefixes_pairs.is_empty() {
         return Ok(());
     }
-    for i in 0..info.sources.len() {
-        let url = &mut info.sources[i];
-        if let Some(found) = prefixes_pairs
+    for url in sources.iter_mut() {
+        if let Some(&(prefix, replacement)) = prefixes_pairs
             .iter()
-            .find(|&x| url.starts_with(x[0].as_str()))
+            .find(|&&(prefix, _)| url.starts_with(prefix))
         {
             *url = {
-                let (_, tail) = url.split_at(found[0].len());
-                let mut result_url = String::from(found[1].as_str());
+                let (_, tail) = url.split_at(prefix.len());
+                let mut result_url = String::from(replacement);
                 result_url.push_str(tail);
-                result_url
+                result_url.into()
             };
         }
     }
     Ok(())
 }
 
-pub fn convert(input: &[u8], x_scopes: bool) -> Result<Vec<u8>, Error> {
-    let (sections, code_section_offset) = read_debug_sections(input)?;
-    let mut info = get_debug_loc(&sections)?;
+/// Loads the content of the `sources` for `sourcesContent`, trying their
+/// path as mapped by `sourceURLPrefixes` and then their path in the DWARF.
+/// The sources that are not found are `null`, and reported with the paths
+/// that were tried.
+fn read_sources_content(
+    sources: &[Arc<str>],
+    dwarf_sources: &[Arc<str>],
+    load: &dyn Fn(&str) -> Option<Vec<u8>>,
+    warnings: &Warnings,
+) -> Value {
+    let content = sources.iter().zip(dwarf_sources.iter()).map(|(source, dwarf_source)| {
+        let mut paths: Vec<&str> = vec![source];
+        if dwarf_source != source {
+            paths.push(dwarf_source);
+        }
+        match paths.iter().filter_map(|path| load(path)).next() {
+            Some(content) => json!(String::from_utf8_lossy(&content)),
+            None => {
+                let tried = paths.join(", ");
+                warnings.warn(&format!("the source {} cannot be found, tried: {}", source, tried));
+                Value::Null
+            }
+        }
+    });
+    Value::Array(content.collect())
+}
+
+/// Reads the tool-conventions `producers` section into a JSON object that maps
+/// each field (`language`, `processed-by`, `sdk`) to its name/version pairs.
+fn read_producers(producers_bytes: &[u8]) -> Result<Value, WasmFormatError> {
+    let mut decoder = WasmDecoder::new(producers_bytes);
+    let mut fields = Map::new();
+    let field_count = decoder.u32()?;
+    for _ in 0..field_count {
+        let field_name = decoder.str()?;
+        let value_count = decoder.u32()?;
+        let mut values = Vec::new();
+        for _ in 0..value_count {
+            let name = decoder.str()?;
+            let version = decoder.str()?;
+            values.push(json!({ "name": name, "version": version }));
+        }
+        fields.insert(field_name.to_string(), json!(values));
+    }
+    Ok(json!(fields))
+}
+
+/// Reads the tool-conventions `target_features` section into an array of
+/// feature names, each prefixed with `+` (used), `-` (disallowed) or `=`
+/// (required), e.g. `"+simd128"`.
+fn read_target_features(features_bytes: &[u8]) -> Result<Value, WasmFormatError> {
+    let mut decoder = WasmDecoder::new(features_bytes);
+    let count = decoder.u32()?;
+    let mut features = Vec::new();
+    for _ in 0..count {
+        let prefix_offset = decoder.offset();
+        let prefix = match decoder.u8()? {
+            prefix @ b'+' | prefix @ b'-' | prefix @ b'=' => prefix as char,
+            _ => return Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, prefix_offset)),
+        };
+        features.push(format!("{}{}", prefix, decoder.str()?));
+    }
+    Ok(json!(features))
+}
+
+/// Reads the `dylink.0` section of a dynamic-linking (side) module into a
+/// JSON object with the memory and table requirements, alignments in
+/// bytes, and the `needed` libraries.
+fn read_dylink(dylink_bytes: &[u8]) -> Result<Value, WasmFormatError> {
+    let mut decoder = WasmDecoder::new(dylink_bytes);
+    let mut fields = Map::new();
+    let alignment = |decoder: &mut WasmDecoder| -> Result<Value, WasmFormatError> {
+        let offset = decoder.offset();
+        match 1u64.checked_shl(decoder.u32()?) {
+            Some(alignment) => Ok(json!(alignment)),
+            None => Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, offset)),
+        }
+    };
+    while !decoder.eof() {
+        let subsection_type = decoder.u8()?;
+        let len = decoder.u32()?;
+        let payload_offset = decoder.offset();
+        let mut payload = WasmDecoder::at(decoder.skip(len as usize)?, payload_offset);
+        match subsection_type {
+            WASM_DYLINK_MEM_INFO => {
+                fields.insert("memorySize".to_string(), json!(payload.u32()?));
+                fields.insert("memoryAlignment".to_string(), alignment(&mut payload)?);
+                fields.insert("tableSize".to_string(), json!(payload.u32()?));
+                fields.insert("tableAlignment".to_string(), alignment(&mut payload)?);
+            }
+            WASM_DYLINK_NEEDED => {
+                let count = payload.u32()?;
+                let mut needed = Vec::new();
+                for _ in 0..count {
+                    needed.push(json!(payload.str()?));
+                }
+                fields.insert("needed".to_string(), json!(needed));
+            }
+            // Export and import infos only matter to the dynamic linker.
+            _ => (),
+        }
+    }
+    Ok(json!(fields))
+}
+
+pub(crate) fn convert_sections(
+    sections: &HashMap<&str, &[u8]>,
+    code_section_offset: u64,
+    options: &ConvertOptions,
+) -> Result<Vec<u8>, Error> {
+    let warnings = Warnings::new(options);
+    convert_sections_with_metadata(
+        sections,
+        code_section_offset,
+        None,
+        None,
+        Map::new(),
+        options,
+        &warnings,
+        None,
+    )
+}
+
+/// Reads the function body extents used to validate the DWARF ranges.
+fn read_code_layout(module: &WasmSections) -> Result<Option<FunctionBodies>, WasmFormatError> {
+    let code = match module.code_section {
+        Some(code) => code,
+        None => return Ok(None),
+    };
+    let first_index = match module.import_section {
+        Some(imports) => read_imported_function_count(imports)?,
+        None => 0,
+    };
+    let extents = read_function_bodies(code)?
+        .into_iter()
+        .map(|body| body.offset as u64..body.end as u64)
+        .collect();
+    Ok(Some(FunctionBodies { first_index, extents }))
+}
+
+fn attribute_set(options: &ConvertOptions) -> Option<HashSet<String>> {
+    options.attributes.map(|names| {
+        let mut set: HashSet<String> = names.iter().map(|name| name.to_string()).collect();
+        // The normalized locations and the parameters are made from other
+        // attributes.
+        if set.contains("locations") || set.contains("parameters") {
+            set.insert("location".to_string());
+        }
+        if set.contains("parameters") || set.contains("template_parameters") {
+            set.extend(["name", "type"].iter().map(|name| name.to_string()));
+        }
+        if set.contains("template_parameters") {
+            set.insert("const_value".to_string());
+        }
+        if options.types || options.globals {
+            set.extend(TYPE_ATTRS.iter().map(|name| name.to_string()));
+        }
+        if options.globals {
+            set.extend(GLOBAL_ATTRS.iter().map(|name| name.to_string()));
+        }
+        set
+    })
+}
+
+/// The options of the tables of `x-scopes`, kept by `LazyScopes`.
+#[derive(Clone, Copy)]
+struct TableOptions {
+    types: bool,
+    globals: bool,
+    collapse_aliases: bool,
+    qualified_names: bool,
+}
+
+impl TableOptions {
+    fn new(options: &ConvertOptions) -> Self {
+        TableOptions {
+            types: options.types,
+            globals: options.globals,
+            collapse_aliases: options.collapse_aliases,
+            qualified_names: options.qualified_names,
+        }
+    }
+}
+
+/// The tables written next to the `debug_info` of `x-scopes`. The globals
+/// refer to the types, which are included with them.
+struct ScopeTables {
+    types: Option<TypeTable>,
+    globals: Option<GlobalTable>,
+    collapse_aliases: bool,
+}
+
+impl ScopeTables {
+    fn new(options: TableOptions) -> Self {
+        ScopeTables {
+            types: if options.types || options.globals {
+                Some(TypeTable::new(options.qualified_names))
+            } else {
+                None
+            },
+            globals: if options.globals { Some(GlobalTable::new()) } else { None },
+            collapse_aliases: options.collapse_aliases,
+        }
+    }
+
+    fn add_unit(&mut self, items: &mut Vec<DebugInfoObj>) {
+        if let Some(ref mut types) = self.types {
+            types.add_unit(items);
+            if let Some(ref mut globals) = self.globals {
+                globals.add_unit(items, types);
+            }
+        }
+    }
+
+    fn into_json(self) -> Map<String, Value> {
+        let mut tables = Map::new();
+        if let Some(types) = self.types {
+            tables.insert("types".to_string(), types.to_json(self.collapse_aliases));
+        }
+        if let Some(globals) = self.globals {
+            tables.insert("globals".to_string(), globals.to_json());
+        }
+        tables
+    }
+}
+
+fn has_attribute(attributes: Option<&HashSet<String>>, name: &str) -> bool {
+    attributes.map_or(true, |set| set.contains(name))
+}
+
+/// Same as `convert_sections`, adding the `metadata` fields to the output.
+/// When the function `bodies` are known, they replace the heuristic used
+/// to detect removed code and limit the ranges to their function. The
+/// `address_map` moves the addresses to a split module. With `map_sources`,
+/// the scopes are left out and the sources, as they are before applying
+/// `sourceURLPrefixes`, are stored there for `LazyScopes`. The data that is
+/// left out is reported to `warnings`.
+#[allow(clippy::too_many_arguments)]
+fn convert_sections_with_metadata(
+    sections: &HashMap<&str, &[u8]>,
+    code_section_offset: u64,
+    bodies: Option<&FunctionBodies>,
+    address_map: Option<&AddressMap>,
+    mut metadata: Map<String, Value>,
+    options: &ConvertOptions,
+    warnings: &Warnings,
+    map_sources: Option<&mut Vec<Arc<str>>>,
+) -> Result<Vec<u8>, Error> {
+    let x_scopes = options.x_scopes && map_sources.is_none();
+    let passes = if x_scopes { 2 } else { 1 };
+    let total_units = cmp::max(dwarf::count_units(sections) * passes, 1);
+    let mut processed_units = 0;
+    if options.memory_budget.is_some() && !budget::is_counting() {
+        warnings.warn(
+            "the memory budget is not enforced: budget::CountingAllocator is not the global allocator",
+        );
+    }
+    let usage = budget::Usage::new();
+    let _tracking = usage.track();
+    let within_budget = || match options.memory_budget {
+        Some(budget) => usage.peak() <= budget,
+        None => true,
+    };
+    let mut on_unit = || {
+        if !within_budget() {
+            return Err(dwarf::Error::MemoryBudgetExceeded);
+        }
+        if let Some(progress) = options.progress {
+            if !progress((processed_units * 100 / total_units) as u32) {
+                return Err(dwarf::Error::Cancelled);
+            }
+        }
+        processed_units += 1;
+        Ok(())
+    };
+
+    // The abbreviations and line program headers are parsed once for the
+    // mappings and the scopes.
+    let context = DwarfContext::new(sections)?;
+    let mut info = get_debug_loc(&context, bodies, options.cache_dir, &mut on_unit)?;
+    for warning in info.warnings.iter() {
+        warnings.warn(warning);
+    }
+    if options.verify {
+        let code_end = bodies.and_then(|bodies| bodies.extents.last()).map(|extent| extent.end);
+        for violation in verify::verify_locations(&info, code_section_offset, code_end) {
+            warnings.warn(&violation);
+        }
+    }
     let scopes = if x_scopes {
-        Some(get_debug_scopes(&sections, &mut info.sources)?)
+        // The scopes are serialized unit by unit as they are read.
+        let attributes = attribute_set(options);
+        let with_locations = has_attribute(attributes.as_ref(), "locations");
+        let with_parameters = has_attribute(attributes.as_ref(), "parameters");
+        let with_template_parameters = has_attribute(attributes.as_ref(), "template_parameters");
+        let mut writer = ScopesWriter::new(options.format);
+        let mut tables = ScopeTables::new(TableOptions::new(options));
+        let mut scope_warnings = Vec::new();
+        let mut verifier = if options.verify { Some(ScopesVerifier::new()) } else { None };
+        read_debug_scopes(
+            &context,
+            &mut info.sources,
+            &mut scope_warnings,
+            bodies,
+            options.keep_dead_functions,
+            attributes.as_ref(),
+            None,
+            &mut on_unit,
+            &mut |mut items| {
+                if let Some(ref mut verifier) = verifier {
+                    verifier.add_unit(&items);
+                }
+                if let Some(address_map) = address_map {
+                    address_map.remap_scopes(&mut items);
+                }
+                tables.add_unit(&mut items);
+                if with_locations {
+                    dwarf::add_locations(&mut items, code_section_offset as i64);
+                }
+                if with_parameters {
+                    dwarf::add_parameters(&mut items, code_section_offset as i64);
+                }
+                if with_template_parameters {
+                    dwarf::add_template_parameters(&mut items);
+                }
+                writer.write(&items);
+                Ok(())
+            },
+        )?;
+        for warning in scope_warnings.iter() {
+            warnings.warn(warning);
+        }
+        for violation in verifier.map_or(Vec::new(), ScopesVerifier::finish) {
+            warnings.warn(&violation);
+        }
+        Some((writer.finish(), tables.into_json()))
     } else {
         None
     };
+    if let Some(map_sources) = map_sources {
+        map_sources.clone_from(&info.sources);
+    }
+    if let Some(address_map) = address_map {
+        address_map.remap_locations(&mut info);
+    }
+    let dwarf_sources = options.source_loader.map(|_| info.sources.clone());
     if let Some(ref prefixes) = sections.get("sourceURLPrefixes") {
-        fix_source_urls(&mut info, prefixes)?;
+        fix_source_urls(&mut info.sources, prefixes)?;
+    }
+    if let (Some(load), Some(dwarf_sources)) = (options.source_loader, dwarf_sources) {
+        metadata.insert(
+            "sourcesContent".to_string(),
+            read_sources_content(&info.sources, &dwarf_sources, load, warnings),
+        );
+    }
+    if let Some(producers) = sections.get("producers") {
+        metadata.insert(
+            "x-producers".to_string(),
+            read_producers(producers).context("producers section")?,
+        );
+    }
+    if let Some(features) = sections.get("target_features") {
+        metadata.insert(
+            "x-target-features".to_string(),
+            read_target_features(features).context("target_features section")?,
+        );
+    }
+    if let Some(dylink) = sections.get("dylink.0") {
+        metadata.insert(
+            "x-dylink".to_string(),
+            read_dylink(dylink).context("dylink.0 section")?,
+        );
+    }
+    if let Some(id) = build_id(sections)? {
+        metadata.insert("x-build-id".to_string(), json!(id));
+    }
+    if let Some(url) = source_mapping_url(sections)? {
+        metadata.insert("x-source-mapping-url".to_string(), json!(url));
+    }
+    if options.column_spans {
+        metadata.insert("x-column-spans".to_string(), column_spans_to_json(&info));
+    }
+    if let Some(warnings) = warnings.to_json() {
+        metadata.insert("x-warnings".to_string(), warnings);
+    }
+    if !within_budget() {
+        return Err(Error::MemoryBudgetExceeded);
+    }
+    let json = convert_debug_info_to_json(
+        &info,
+        scopes.as_ref().map(|(debug_info, _)| debug_info.as_slice()),
+        scopes.as_ref().map_or(&Map::new(), |(_, tables)| tables),
+        code_section_offset as i64,
+        metadata,
+        options.format,
+    )?;
+    if let Some(progress) = options.progress {
+        progress(100);
     }
-    let json = convert_debug_info_to_json(&info, scopes, code_section_offset.unwrap_or(0) as i64)?;
     Ok(json)
 }
+
+fn read_string_section(sections: &HashMap<&str, &[u8]>, name: &'static str) -> Result<Option<String>, Error> {
+    match sections.get(name) {
+        Some(bytes) => Ok(Some(WasmDecoder::new(bytes).str().context(name)?.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Returns the companion file location stored in the `external_debug_info`
+/// section, if any.
+fn external_debug_info_url(sections: &HashMap<&str, &[u8]>) -> Result<Option<String>, Error> {
+    read_string_section(sections, "external_debug_info")
+}
+
+/// Returns the source map location already stored in the module's
+/// `sourceMappingURL` section, if any.
+fn source_mapping_url(sections: &HashMap<&str, &[u8]>) -> Result<Option<String>, Error> {
+    read_string_section(sections, "sourceMappingURL")
+}
+
+/// Returns the content of the tool-conventions `build_id` section as a
+/// lowercase hex string, if any.
+fn build_id(sections: &HashMap<&str, &[u8]>) -> Result<Option<String>, Error> {
+    let bytes = match sections.get("build_id") {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let mut decoder = WasmDecoder::new(bytes);
+    let len = decoder.u32().context("build_id section")?;
+    let id = decoder.skip(len as usize).context("build_id section")?;
+    Ok(Some(id.iter().map(|b| format!("{:02x}", b)).collect()))
+}
+
+/// Returns the build id of the module, if any (see `build_id`).
+pub fn read_build_id(input: &[u8]) -> Result<Option<String>, Error> {
+    build_id(&read_debug_sections(&decode_input(input)?)?.sections)
+}
+
+/// Returns the module's existing `sourceMappingURL`, if any.
+pub fn read_source_mapping_url(input: &[u8]) -> Result<Option<String>, Error> {
+    source_mapping_url(&read_debug_sections(&decode_input(input)?)?.sections)
+}
+
+/// Returns a copy of the module with all `sourceMappingURL` sections removed
+/// and a new one pointing to `url` appended. Compressed or text format input
+/// results in a plain binary module.
+pub fn replace_source_mapping_url(input: &[u8], url: &str) -> Result<Vec<u8>, Error> {
+    let input = &decode_input(input)?[..];
+    let mut output = WASM_HEADER.to_vec();
+    for (name, bytes) in read_raw_sections(input)? {
+        if name != Some("sourceMappingURL") {
+            output.extend_from_slice(bytes);
+        }
+    }
+    let mut payload = Vec::new();
+    write_str(&mut payload, url);
+    write_custom_section(&mut output, "sourceMappingURL", &payload);
+    Ok(output)
+}
+
+/// Returns the encoded sections of the module, including their id and size
+/// fields, with the names of the custom sections.
+fn read_raw_sections(input: &[u8]) -> Result<Vec<(Option<&str>, &[u8])>, Error> {
+    if input.len() < WASM_HEADER.len() || &input[..WASM_HEADER.len()] != WASM_HEADER {
+        return Err(WasmFormatError::new(WasmFormatErrorKind::InvalidHeader, 0).into());
+    }
+    let mut sections = Vec::new();
+    let mut decoder = WasmDecoder::at(&input[WASM_HEADER.len()..], WASM_HEADER.len());
+    while !decoder.eof() {
+        let section_start = decoder.offset();
+        let section_id = decoder.u32().context("section id")?;
+        let section_len = decoder.u32().context("section size")?;
+        let body_offset = decoder.offset();
+        let body = decoder.skip(section_len as usize).context("section payload")?;
+        let name = if section_id == WASM_SECTION_CUSTOM {
+            Some(WasmDecoder::at(body, body_offset).str().context("section name")?)
+        } else {
+            None
+        };
+        sections.push((name, &input[section_start..decoder.offset()]));
+    }
+    Ok(sections)
+}
+
+/// Splits the DWARF out of the module, like emscripten's `-gseparate-dwarf`:
+/// returns a copy of the module without its debug sections that refers to
+/// `url` in an `external_debug_info` section, and a companion module with
+/// only the debug sections.
+pub fn separate_debug_info(input: &[u8], url: &str) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let input = &decode_input(input)?[..];
+    let mut stripped = WASM_HEADER.to_vec();
+    let mut companion = WASM_HEADER.to_vec();
+    let mut has_debug_info = false;
+    for (name, bytes) in read_raw_sections(input)? {
+        match name {
+            Some(name) if is_debug_section_name(dwarf::canonical_section_name(name).unwrap_or(name)) => {
+                companion.extend_from_slice(bytes);
+                has_debug_info = true;
+            }
+            Some("external_debug_info") => (),
+            _ => stripped.extend_from_slice(bytes),
+        }
+    }
+    if !has_debug_info {
+        return Err(Error::DataFormat);
+    }
+    let mut payload = Vec::new();
+    write_str(&mut payload, url);
+    write_custom_section(&mut stripped, "external_debug_info", &payload);
+    Ok((stripped, companion))
+}
+
+fn apply_duplicate_section_policy(
+    module: &mut WasmSections,
+    options: &ConvertOptions,
+    warnings: &Warnings,
+) -> Result<(), Error> {
+    let mut seen = HashSet::new();
+    for &(name, data) in module.duplicate_sections.iter() {
+        if !seen.insert(name) {
+            continue;
+        }
+        let action = match options.duplicate_sections {
+            DuplicateSectionPolicy::First => "using the first one",
+            DuplicateSectionPolicy::Last => "using the last one",
+            DuplicateSectionPolicy::Reject => "rejecting the module",
+        };
+        warnings.warn(&format!("the module has several {} sections, {}", name, action));
+        match options.duplicate_sections {
+            DuplicateSectionPolicy::First => {
+                module.sections.insert(name, data);
+            }
+            DuplicateSectionPolicy::Last => (),
+            DuplicateSectionPolicy::Reject => return Err(Error::DataFormat),
+        }
+    }
+    Ok(())
+}
+
+/// The extent of a function body in the module.
+pub struct FunctionOffsets {
+    /// Index in the function index space, which includes the imports.
+    pub index: u32,
+    /// Offset of the body, past its size field, from the start of the module.
+    pub start: u64,
+    pub end: u64,
+}
+
+fn module_function_offsets(module: &WasmSections) -> Result<Vec<FunctionOffsets>, WasmFormatError> {
+    let (code, code_section_offset) = match (module.code_section, module.code_section_offset) {
+        (Some(code), Some(offset)) => (code, offset),
+        _ => return Ok(Vec::new()),
+    };
+    let first_index = match module.import_section {
+        Some(imports) => read_imported_function_count(imports).context("import section")?,
+        None => 0,
+    };
+    let bodies = read_function_bodies(code).context("code section")?;
+    Ok(bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| FunctionOffsets {
+            index: first_index + i as u32,
+            start: code_section_offset + body.body_offset as u64,
+            end: code_section_offset + body.end as u64,
+        })
+        .collect())
+}
+
+/// Returns the function body extents of the module, e.g. to translate the
+/// `wasm-function[N]` frames reported by engines into module offsets.
+pub fn read_function_offsets(input: &[u8]) -> Result<Vec<FunctionOffsets>, Error> {
+    Ok(module_function_offsets(&read_debug_sections(&decode_input(input)?)?)?)
+}
+
+/// Serializes the extents as `[index, start, end]` triples.
+fn function_offsets_to_json(offsets: &[FunctionOffsets]) -> Value {
+    json!(offsets
+        .iter()
+        .map(|f| json!([f.index, f.start, f.end]))
+        .collect::<Vec<_>>())
+}
+
+/// Serializes the end column of the statement starting at each mapping, in
+/// the order of the `mappings` segments: the next column where a statement
+/// starts on the same line, 0-based and exclusive, or `null` when the
+/// statement extends to the end of the line or its column is unknown.
+fn column_spans_to_json(info: &LocationInfo) -> Value {
+    let mut columns: HashMap<(u32, u32), Vec<u32>> = HashMap::new();
+    for loc in info.locations.iter().filter(|loc| loc.line != 0 && loc.column != 0) {
+        columns.entry((loc.source_id, loc.line)).or_insert_with(Vec::new).push(loc.column);
+    }
+    for line_columns in columns.values_mut() {
+        line_columns.sort();
+        line_columns.dedup();
+    }
+    let spans = info
+        .locations
+        .iter()
+        .filter(|loc| loc.line != 0)
+        .map(|loc| {
+            if loc.column == 0 {
+                return Value::Null;
+            }
+            let line_columns = &columns[&(loc.source_id, loc.line)];
+            match line_columns.iter().find(|&&column| column > loc.column) {
+                Some(column) => json!(column - 1),
+                None => Value::Null,
+            }
+        }).collect();
+    Value::Array(spans)
+}
+
+/// The debug information of a module kept by `convert_lazy`, to produce its
+/// `x-scopes` on demand.
+pub struct LazyScopes {
+    sections: HashMap<&'static str, Vec<u8>>,
+    source_url_prefixes: Option<Vec<u8>>,
+    sources: Vec<Arc<str>>,
+    bodies: Option<FunctionBodies>,
+    address_map: Option<AddressMap>,
+    attributes: Option<HashSet<String>>,
+    tables: TableOptions,
+    keep_dead_functions: bool,
+    code_section_offset: u64,
+    format: OutputFormat,
+}
+
+impl LazyScopes {
+    /// Produces the scopes of the compilation units with code in `range`,
+    /// which holds addresses relative to the code section like the ones of
+    /// `x-scopes`. The result is a JSON object with the `x-scopes` field and
+    /// the `sources` its file ids refer to, which start with the `sources`
+    /// of the map. With a split module all units are produced, as their
+    /// ranges no longer match the functions that moved.
+    pub fn scopes_for_range(&self, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        let sections: HashMap<&str, &[u8]> = self
+            .sections
+            .iter()
+            .map(|(name, data)| (*name, data.as_slice()))
+            .collect();
+        let mut sources = self.sources.clone();
+        let address_map = self.address_map.as_ref();
+        let with_locations = has_attribute(self.attributes.as_ref(), "locations");
+        let with_parameters = has_attribute(self.attributes.as_ref(), "parameters");
+        let with_template_parameters =
+            has_attribute(self.attributes.as_ref(), "template_parameters");
+        let mut writer = ScopesWriter::new(self.format);
+        let mut tables = ScopeTables::new(self.tables);
+        // The warnings are for the whole module, which the scopes of a range
+        // are only a part of.
+        read_debug_scopes(
+            &DwarfContext::new(&sections)?,
+            &mut sources,
+            &mut Vec::new(),
+            self.bodies.as_ref(),
+            self.keep_dead_functions,
+            self.attributes.as_ref(),
+            if address_map.is_some() { None } else { Some(&range) },
+            &mut || Ok(()),
+            &mut |mut items| {
+                if let Some(address_map) = address_map {
+                    address_map.remap_scopes(&mut items);
+                }
+                tables.add_unit(&mut items);
+                if with_locations {
+                    dwarf::add_locations(&mut items, self.code_section_offset as i64);
+                }
+                if with_parameters {
+                    dwarf::add_parameters(&mut items, self.code_section_offset as i64);
+                }
+                if with_template_parameters {
+                    dwarf::add_template_parameters(&mut items);
+                }
+                writer.write(&items);
+                Ok(())
+            },
+        )?;
+        if let Some(ref prefixes) = self.source_url_prefixes {
+            fix_source_urls(&mut sources, prefixes)?;
+        }
+        Ok(convert_scopes_to_json(
+            &sources,
+            &writer.finish(),
+            &tables.into_json(),
+            self.code_section_offset as i64,
+            self.format,
+        )?)
+    }
+}
+
+/// Hashes everything the output of a module conversion depends on.
+fn cache_key(
+    sections: &HashMap<&str, &[u8]>,
+    code_section_offset: u64,
+    bodies: Option<&FunctionBodies>,
+    metadata: &Map<String, Value>,
+    options: &ConvertOptions,
+) -> CacheKey {
+    let mut key = CacheKey::new("json");
+    let mut names: Vec<_> = sections.keys().collect();
+    names.sort();
+    key.add_u64(names.len() as u64);
+    for name in names {
+        key.add_bytes(name.as_bytes());
+        key.add_bytes(sections[name]);
+    }
+    key.add_u64(code_section_offset);
+    key.add_bool(bodies.is_some());
+    if let Some(bodies) = bodies {
+        key.add_u64(u64::from(bodies.first_index));
+        key.add_u64(bodies.extents.len() as u64);
+        for extent in bodies.extents.iter() {
+            key.add_u64(extent.start);
+            key.add_u64(extent.end);
+        }
+    }
+    key.add_optional_bytes(options.split_module);
+    key.add_bytes(serde_json::to_string(metadata).unwrap_or_default().as_bytes());
+    key.add_bool(options.x_scopes);
+    key.add_bool(options.attributes.is_some());
+    if let Some(attributes) = options.attributes {
+        key.add_u64(attributes.len() as u64);
+        for attribute in attributes {
+            key.add_bytes(attribute.as_bytes());
+        }
+    }
+    key.add_bool(options.types);
+    key.add_bool(options.globals);
+    key.add_bool(options.collapse_aliases);
+    key.add_bool(options.qualified_names);
+    key.add_bool(options.verify);
+    key.add_bool(options.keep_dead_functions);
+    key.add_bool(options.column_spans);
+    key.add_bool(options.format == OutputFormat::Compact);
+    key
+}
+
+fn convert_module(
+    input: &[u8],
+    metadata: Map<String, Value>,
+    options: &ConvertOptions,
+) -> Result<Vec<u8>, Error> {
+    Ok(convert_module_with_scopes(input, metadata, options, false)?.0)
+}
+
+/// Converts a module; with `lazy`, the scopes are left out of the map and
+/// returned as `LazyScopes` instead.
+fn convert_module_with_scopes(
+    input: &[u8],
+    mut metadata: Map<String, Value>,
+    options: &ConvertOptions,
+    lazy: bool,
+) -> Result<(Vec<u8>, Option<LazyScopes>), Error> {
+    let external_debug_info;
+    let relocated_sections;
+    let warnings = Warnings::new(options);
+    let mut module = read_debug_sections(input)?;
+    apply_duplicate_section_policy(&mut module, options, &warnings)?;
+    let mut sections = module.sections.clone();
+    if module.sections.contains_key("linking") {
+        // Object files: the debug sections still need relocations.
+        relocated_sections = apply_relocations(&module).context("relocations")?;
+        for (name, data) in relocated_sections.iter() {
+            sections.insert(name, data.as_slice());
+        }
+    }
+    if !sections.contains_key(".debug_info") {
+        if let Some(url) = external_debug_info_url(&sections)? {
+            external_debug_info = match options.external_debug_info_loader.and_then(|load| load(&url)) {
+                Some(data) => data,
+                None => return Err(Error::ExternalDebugInfo(url)),
+            };
+            sections.extend(read_debug_sections(&external_debug_info)?.sections);
+        }
+    }
+    let bodies = read_code_layout(&module).context("code section")?;
+    let split_module = options.split_module.map(read_debug_sections).transpose()?;
+    let address_map = match split_module {
+        Some(ref split_module) => {
+            let mut map_warnings = Vec::new();
+            let address_map = AddressMap::new(&module, split_module, &mut map_warnings)?;
+            for message in map_warnings.iter() {
+                warnings.warn(message);
+            }
+            if address_map.is_empty() {
+                warnings.warn("no function of the split module was found by name in the input");
+            }
+            module.code_section_offset = split_module.code_section_offset;
+            Some(address_map)
+        }
+        None => None,
+    };
+    if options.function_offsets {
+        // The offsets describe the module that actually runs.
+        let layout = split_module.as_ref().unwrap_or(&module);
+        metadata.insert(
+            "x-function-offsets".to_string(),
+            function_offsets_to_json(&module_function_offsets(layout)?),
+        );
+    }
+    let code_section_offset = match (options.code_section_offset, module.code_section_offset) {
+        (Some(offset), _) | (None, Some(offset)) => offset,
+        (None, None) => {
+            warnings.warn("the input has no code section, the addresses are not adjusted");
+            0
+        }
+    };
+    // The sources can change without the module changing, so their content
+    // is not cached.
+    let cache_key = match options.cache_dir {
+        Some(_) if !lazy && options.source_loader.is_none() => Some(cache_key(
+            &sections,
+            code_section_offset,
+            bodies.as_ref(),
+            &metadata,
+            options,
+        )),
+        _ => None,
+    };
+    // The warnings are stored next to the output, so that a cache hit still
+    // passes them to the callback.
+    if let (Some(dir), Some(key)) = (options.cache_dir, cache_key.as_ref()) {
+        let stored_warnings = cache::load(dir, &key.companion("warnings"))
+            .and_then(|data| serde_json::from_slice::<Vec<String>>(&data).ok());
+        if let Some(stored_warnings) = stored_warnings {
+            if let Some(json) = cache::load(dir, key) {
+                warnings.replay(&stored_warnings);
+                if let Some(progress) = options.progress {
+                    progress(100);
+                }
+                return Ok((json, None));
+            }
+        }
+    }
+    let mut map_sources = Vec::new();
+    let json = convert_sections_with_metadata(
+        &sections,
+        code_section_offset,
+        bodies.as_ref(),
+        address_map.as_ref(),
+        metadata,
+        options,
+        &warnings,
+        if lazy { Some(&mut map_sources) } else { None },
+    )?;
+    if let (Some(dir), Some(key)) = (options.cache_dir, cache_key.as_ref()) {
+        let stored_warnings =
+            serde_json::to_vec(&*warnings.messages.borrow()).map_err(|_| Error::OutputError)?;
+        let stored = cache::store(dir, &key.companion("warnings"), &stored_warnings)
+            .and_then(|_| cache::store(dir, key, &json));
+        if let Err(err) = stored {
+            warnings.warn(&format!("failed to store the result in the cache: {}", err));
+        }
+    }
+    if !lazy {
+        return Ok((json, None));
+    }
+    let scopes = LazyScopes {
+        sections: sections
+            .iter()
+            .filter_map(|(name, data)| {
+                dwarf::canonical_section_name(name).map(|name| (name, data.to_vec()))
+            }).collect(),
+        source_url_prefixes: sections.get("sourceURLPrefixes").map(|data| data.to_vec()),
+        sources: map_sources,
+        bodies,
+        address_map,
+        attributes: attribute_set(options),
+        tables: TableOptions::new(options),
+        keep_dead_functions: options.keep_dead_functions,
+        code_section_offset,
+        format: options.format,
+    };
+    Ok((json, Some(scopes)))
+}
+
+/// Component model binaries share the magic with core modules but have
+/// layer 1 in the upper half of the version field.
+fn is_component(input: &[u8]) -> bool {
+    input.len() >= 8 && &input[0..4] == b"\x00asm" && input[6..8] == [1, 0]
+}
+
+/// Collects the core modules embedded in a component, including nested
+/// components, with their offsets in the outermost binary.
+fn read_component_core_modules<'a>(
+    input: &'a [u8],
+    base_offset: usize,
+    modules: &mut Vec<(usize, &'a [u8])>,
+) -> Result<(), WasmFormatError> {
+    let mut decoder = WasmDecoder::at(&input[8..], base_offset + 8);
+    while !decoder.eof() {
+        let section_id = decoder.u32().context("component section id")?;
+        let section_len = decoder.u32().context("component section size")?;
+        let offset = decoder.offset();
+        let body = decoder.skip(section_len as usize).context("component section payload")?;
+        match section_id {
+            COMPONENT_SECTION_CORE_MODULE => modules.push((offset, body)),
+            COMPONENT_SECTION_COMPONENT if is_component(body) => {
+                read_component_core_modules(body, offset, modules)?
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Converts every core module of a component that has debug information
+/// into its own map, producing a JSON array. The addresses in each map are
+/// relative to the start of its core module.
+fn convert_component(input: &[u8], options: &ConvertOptions) -> Result<Vec<u8>, Error> {
+    let mut modules = Vec::new();
+    read_component_core_modules(input, 0, &mut modules)?;
+    let mut json = vec![b'['];
+    let mut converted = 0;
+    for (index, (offset, module)) in modules.into_iter().enumerate() {
+        if !read_debug_sections(module)?.sections.keys().any(|name| is_debug_section_name(name)) {
+            continue;
+        }
+        let mut metadata = Map::new();
+        metadata.insert(
+            "x-core-module".to_string(),
+            json!({ "index": index, "offset": offset }),
+        );
+        if converted > 0 {
+            json.push(b',');
+        }
+        json.extend(convert_module(module, metadata, options)?);
+        converted += 1;
+    }
+    if converted == 0 {
+        return Err(Error::DataFormat);
+    }
+    json.push(b']');
+    Ok(json)
+}
+
+/// Converts the debug sections of an ELF file. The addresses are used as
+/// they are, since there is no wasm code section to relate them to.
+fn convert_elf(input: &[u8], options: &ConvertOptions) -> Result<Vec<u8>, Error> {
+    let warnings = Warnings::new(options);
+    let sections = elf::read_debug_sections(input, &|message| warnings.warn(message))?;
+    convert_sections_with_metadata(
+        &sections,
+        options.code_section_offset.unwrap_or(0),
+        None,
+        None,
+        Map::new(),
+        options,
+        &warnings,
+        None,
+    )
+}
+
+/// Converts a core module, or all core modules of a component (see
+/// `convert_component`), or an ELF file with wasm32 DWARF. Compressed and
+/// text format input is decoded first.
+pub fn convert(input: &[u8], options: &ConvertOptions) -> Result<Vec<u8>, Error> {
+    let input = decode_input(input)?;
+    if elf::is_elf(&input) {
+        return convert_elf(&input, options);
+    }
+    if is_component(&input) {
+        return convert_component(&input, options);
+    }
+    convert_module(&input, Map::new(), options)
+}
+
+/// Converts a core module like `convert`, without `x-scopes` in the map.
+/// The returned `LazyScopes` produce them later, for the code that is
+/// actually inspected. Components and ELF files are not supported.
+pub fn convert_lazy(input: &[u8], options: &ConvertOptions) -> Result<(Vec<u8>, LazyScopes), Error> {
+    let input = decode_input(input)?;
+    if elf::is_elf(&input) || is_component(&input) {
+        return Err(Error::DataFormat);
+    }
+    let (json, scopes) = convert_module_with_scopes(&input, Map::new(), options, true)?;
+    Ok((json, scopes.unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::write_u32_leb128;
+
+    /// A code section with a single empty function body.
+    const CODE: &[u8] = &[1, 2, 0, 0x0b];
+
+    fn write_code_section(output: &mut Vec<u8>) {
+        output.push(WASM_SECTION_CODE as u8);
+        write_u32_leb128(output, CODE.len() as u32);
+        output.extend_from_slice(CODE);
+    }
+
+    #[test]
+    fn code_section_after_custom_sections() {
+        let mut module = WASM_HEADER.to_vec();
+        write_custom_section(&mut module, ".debug_info", &[1, 2, 3]);
+        write_custom_section(&mut module, ".debug_line", &[0; 200]);
+        let code_offset = module.len() + 2;
+        write_code_section(&mut module);
+        let sections = read_debug_sections(&module).unwrap();
+        assert_eq!(sections.code_section_offset, Some(code_offset as u64));
+        assert_eq!(sections.code_section, Some(CODE));
+        assert_eq!(sections.sections[".debug_info"], &[1, 2, 3]);
+        assert_eq!(sections.sections[".debug_line"].len(), 200);
+    }
+
+    #[test]
+    fn code_section_before_custom_sections() {
+        let mut module = WASM_HEADER.to_vec();
+        write_code_section(&mut module);
+        write_custom_section(&mut module, ".debug_info", &[1, 2, 3]);
+        write_custom_section(&mut module, "sourceMappingURL", b"\x0cmodule.map");
+        let sections = read_debug_sections(&module).unwrap();
+        assert_eq!(sections.code_section_offset, Some(WASM_HEADER.len() as u64 + 2));
+        assert_eq!(sections.sections[".debug_info"], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn code_section_between_custom_sections() {
+        let mut module = WASM_HEADER.to_vec();
+        write_custom_section(&mut module, "producers", &[0]);
+        write_custom_section(&mut module, "unknown", &[0; 300]);
+        // A section size padded to five bytes, as written by some linkers.
+        module.push(WASM_SECTION_CODE as u8);
+        module.extend_from_slice(&[0x84, 0x80, 0x80, 0x80, 0x00]);
+        let code_offset = module.len();
+        module.extend_from_slice(CODE);
+        write_custom_section(&mut module, ".debug_abbrev", &[0]);
+        let sections = read_debug_sections(&module).unwrap();
+        assert_eq!(sections.code_section_offset, Some(code_offset as u64));
+        assert_eq!(sections.code_section, Some(CODE));
+        assert!(sections.sections.contains_key("producers"));
+        assert!(!sections.sections.contains_key("unknown"));
+        assert_eq!(sections.sections[".debug_abbrev"], &[0]);
+    }
+
+    #[test]
+    fn no_code_section() {
+        let mut module = WASM_HEADER.to_vec();
+        write_custom_section(&mut module, ".debug_info", &[1, 2, 3]);
+        let sections = read_debug_sections(&module).unwrap();
+        assert_eq!(sections.code_section_offset, None);
+    }
+
+    #[test]
+    fn malformed_source_url_prefixes() {
+        let mut section = Vec::new();
+        let prefixes = br#"[[], ["a"], ["/src/", "https://example.com/"], ["b", "c", "d"]]"#;
+        write_u32_leb128(&mut section, prefixes.len() as u32);
+        section.extend_from_slice(prefixes);
+        let mut sources: Vec<Arc<str>> = vec!["/src/main.c".into(), "a.c".into()];
+        fix_source_urls(&mut sources, &section).unwrap();
+        assert_eq!(&*sources[0], "https://example.com/main.c");
+        assert_eq!(&*sources[1], "a.c");
+    }
+}
@@ -13,13 +13,24 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::result::Result;
 
 use gimli;
 
+#[cfg(feature = "parallel")]
+use crate::budget;
+use crate::cache::{self, CacheKey};
+use crate::wasm::{self, WasmDecoder};
 use gimli::{
-    AttributeValue, DebugAbbrev, DebugInfo, DebugLine, DebugLoc, DebugLocLists, DebugRanges,
+    Abbreviations, AttributeValue, DebugAbbrev, DebugAbbrevOffset, DebugInfo, DebugLine, DebugLineOffset, DebugLoc, DebugLocLists, DebugRanges,
     DebugRngLists, DebugStr, LittleEndian, LocationLists, RangeLists
 };
 
@@ -27,12 +38,17 @@ trait Reader: gimli::Reader<Offset = usize> {}
 
 impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where Endian: gimli::Endianity {}
 
+type Slice<'a> = gimli::EndianSlice<'a, LittleEndian>;
+type UnitHeader<'a> = gimli::CompilationUnitHeader<Slice<'a>>;
+
 #[derive(Debug)]
 pub enum Error {
     GimliError(gimli::Error),
     MissingDwarfEntry,
     MissingSection,
     DataFormat,
+    Cancelled,
+    MemoryBudgetExceeded,
 }
 
 impl From<gimli::Error> for Error {
@@ -41,6 +57,89 @@ impl From<gimli::Error> for Error {
     }
 }
 
+const DEBUG_SECTION_NAMES: &[&str] = &[
+    ".debug_abbrev",
+    ".debug_addr",
+    ".debug_aranges",
+    ".debug_frame",
+    ".debug_info",
+    ".debug_line",
+    ".debug_line_str",
+    ".debug_loc",
+    ".debug_loclists",
+    ".debug_macinfo",
+    ".debug_macro",
+    ".debug_pubnames",
+    ".debug_pubtypes",
+    ".debug_ranges",
+    ".debug_rnglists",
+    ".debug_str",
+    ".debug_str_offsets",
+    ".debug_types",
+];
+
+/// Maps the variants of the DWARF section names some producers use, i.e.
+/// without the leading dot (`debug_info`) or with the split DWARF suffix
+/// (`.debug_info.dwo`), to the standard name.
+pub fn canonical_section_name(name: &str) -> Option<&'static str> {
+    let name = name.trim_end_matches(".dwo");
+    let name = if name.starts_with('.') { &name[1..] } else { name };
+    DEBUG_SECTION_NAMES
+        .iter()
+        .find(|standard| &standard[1..] == name)
+        .cloned()
+}
+
+/// The key of a `DebugInfoObj` attribute.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AttrKey {
+    /// The offset of the entry in its unit.
+    Uid,
+    /// The index of the function a subprogram was compiled to.
+    FuncIndex,
+    /// The index of the entry's type in the type table.
+    TypeId,
+    /// The normalized `location` of a variable, see `add_locations`.
+    Locations,
+    /// The summary of the parameters of a subprogram, see `add_parameters`.
+    Parameters,
+    /// The summary of the template parameters of a generic function or
+    /// type, see `add_template_parameters`.
+    TemplateParameters,
+    /// Marks the functions in removed code that are kept, see
+    /// `remove_dead_functions`.
+    Dead,
+    Dwarf(gimli::DwAt),
+}
+
+impl AttrKey {
+    pub const LOW_PC: AttrKey = AttrKey::Dwarf(gimli::DW_AT_low_pc);
+    pub const HIGH_PC: AttrKey = AttrKey::Dwarf(gimli::DW_AT_high_pc);
+    pub const RANGES: AttrKey = AttrKey::Dwarf(gimli::DW_AT_ranges);
+    pub const INLINE: AttrKey = AttrKey::Dwarf(gimli::DW_AT_inline);
+    pub const NAME: AttrKey = AttrKey::Dwarf(gimli::DW_AT_name);
+}
+
+impl AttrKey {
+    /// The key in the JSON output.
+    pub fn name(&self) -> Cow<'static, str> {
+        match self {
+            AttrKey::Uid => Cow::Borrowed("uid"),
+            AttrKey::FuncIndex => Cow::Borrowed("func_index"),
+            AttrKey::TypeId => Cow::Borrowed("type_id"),
+            AttrKey::Locations => Cow::Borrowed("locations"),
+            AttrKey::Parameters => Cow::Borrowed("parameters"),
+            AttrKey::TemplateParameters => Cow::Borrowed("template_parameters"),
+            AttrKey::Dead => Cow::Borrowed("dead"),
+            AttrKey::Dwarf(name) => match name.static_string() {
+                Some(s) => Cow::Borrowed(&s[ /*DW_AT_*/ 6..]),
+                // Vendor extensions gimli does not know about.
+                None => Cow::Owned(format!("0x{:x}", name.0)),
+            },
+        }
+    }
+}
+
 pub enum DebugAttrValue<'a> {
     I64(i64),
     Bool(bool),
@@ -48,43 +147,162 @@ pub enum DebugAttrValue<'a> {
     Ranges(Vec<(i64, i64)>),
     Expression(&'a [u8]),
     LocationList(Vec<(i64, i64, &'a [u8])>),
+    /// The expressions with the module offsets of the code they apply to,
+    /// or no range when the expression applies wherever the entry is in
+    /// scope.
+    Locations(Locations<'a>),
+    Parameters(Vec<Parameter<'a>>),
+    TemplateParameters(Vec<TemplateParameter<'a>>),
     UID(usize),
     UIDRef(usize, Option<&'a str>),
     Ignored,
     Unknown,
 }
+
+/// A `formal_parameter` of a subprogram.
+pub struct Parameter<'a> {
+    pub name: Option<&'a str>,
+    /// The `type` reference: its uid and name.
+    pub type_ref: Option<(usize, Option<&'a str>)>,
+    pub locations: Option<Locations<'a>>,
+}
+
+/// A `template_type_parameter` or `template_value_parameter`.
+pub struct TemplateParameter<'a> {
+    pub is_value: bool,
+    pub name: Option<&'a str>,
+    /// The `type` reference: the argument of a type parameter, or the type
+    /// of a value parameter.
+    pub type_ref: Option<(usize, Option<&'a str>)>,
+    /// The argument of a value parameter, when it is an integer.
+    pub value: Option<i64>,
+}
+
+/// The tags of the entries that have template parameters.
+const TEMPLATE_OWNER_TAGS: &[&str] = &["class_type", "structure_type", "subprogram", "union_type"];
+
 pub struct DebugInfoObj<'a> {
     pub tag: &'static str,
-    pub attrs: HashMap<&'static str, DebugAttrValue<'a>>,
+    pub attrs: HashMap<AttrKey, DebugAttrValue<'a>>,
     pub children: Vec<DebugInfoObj<'a>>,
 }
 
+impl<'a> Drop for DebugInfoObj<'a> {
+    fn drop(&mut self) {
+        // Flattens the subtree first, so that dropping a deep tree does not
+        // recurse per nesting level.
+        let mut pending = mem::replace(&mut self.children, Vec::new());
+        while let Some(mut item) = pending.pop() {
+            pending.append(&mut item.children);
+        }
+    }
+}
+
 fn is_out_of_range(low_pc: i64, high_pc: i64) -> bool {
-    let fn_size = (high_pc - low_pc) as u32;
-    let fn_size_field_len = ((fn_size + 1).next_power_of_two().trailing_zeros() + 6) / 7;
+    // Sizes and addresses may exceed 32 bits in wasm64 modules.
+    let fn_size = high_pc.wrapping_sub(low_pc) as u64;
+    let fn_size_field_len = (leb128_bits(fn_size) + 6) / 7;
     low_pc < i64::from(1 + fn_size_field_len)
 }
 
-fn is_subprogram(item: &DebugInfoObj) -> bool {
-    if let Some(DebugAttrValue::String("subprogram")) = item.attrs.get("tag") {
-        true
-    } else {
-        false
+/// The number of bits of the LEB128 payload of `value`.
+fn leb128_bits(value: u64) -> u32 {
+    value
+        .saturating_add(1)
+        .checked_next_power_of_two()
+        .map_or(64, |power| power.trailing_zeros())
+}
+
+/// Function body extents in the code section, in the DWARF address space
+/// (relative to the code section payload). Each extent starts at the body
+/// size field and ends past the last instruction.
+pub struct FunctionBodies {
+    /// Index of the first defined function, i.e. the imported functions count.
+    pub first_index: u32,
+    pub extents: Vec<Range<u64>>,
+}
+
+impl FunctionBodies {
+    /// Returns the position of the body containing `address`.
+    fn find(&self, address: u64) -> Option<usize> {
+        let i = match self.extents.binary_search_by(|extent| extent.end.cmp(&address)) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        match self.extents.get(i) {
+            Some(extent) if extent.start <= address => Some(i),
+            _ => None,
+        }
+    }
+}
+
+/// Checks the `low_pc..high_pc` range against the function bodies, if
+/// known, or the LEB-size heuristic otherwise. Returns `None` for removed
+/// code, or the end clamped to the containing body with the function index.
+fn validate_range(
+    low_pc: i64,
+    high_pc: i64,
+    bodies: Option<&FunctionBodies>,
+) -> Option<(i64, Option<u32>)> {
+    match bodies {
+        Some(bodies) => {
+            let i = bodies.find(low_pc as u64)?;
+            let end = bodies.extents[i].end as i64;
+            Some((cmp::min(high_pc, end), Some(bodies.first_index + i as u32)))
+        }
+        None if is_out_of_range(low_pc, high_pc) => None,
+        None => Some((high_pc, None)),
     }
 }
 
+fn is_subprogram(item: &DebugInfoObj) -> bool {
+    item.tag == "subprogram"
+}
+
 fn is_inlined_subprogram(item: &DebugInfoObj) -> bool {
-    item.attrs.get("inline").is_some()
+    item.attrs.get(&AttrKey::INLINE).is_some()
+}
+
+/// Describes a function in removed code with its name and its ranges in the
+/// DWARF address space.
+fn describe_dead_function(item: &DebugInfoObj, ranges: &[(i64, i64)]) -> String {
+    let name = [AttrKey::NAME, AttrKey::Dwarf(gimli::DW_AT_linkage_name)]
+        .iter()
+        .filter_map(|key| match item.attrs.get(key) {
+            Some(&DebugAttrValue::String(name)) => Some(name.to_string()),
+            _ => None,
+        })
+        .next()
+        .or_else(|| match item.attrs.get(&AttrKey::Uid) {
+            Some(&DebugAttrValue::UID(uid)) => Some(format!("<uid {}>", uid)),
+            _ => None,
+        })
+        .unwrap_or_else(|| "<unnamed>".to_string());
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|&(begin, end)| format!("[0x{:x}, 0x{:x})", begin, end))
+        .collect();
+    format!("{} {}", name, ranges.join(" "))
 }
 
-fn remove_dead_functions(items: &mut Vec<DebugInfoObj>) {
+/// Removes the dead functions of one level of the tree, or marks them as
+/// `dead` with `keep_dead`, and returns which of the remaining items have
+/// children to process. The removed functions are described in
+/// `dead_functions`.
+fn remove_dead_items(
+    items: &mut Vec<DebugInfoObj>,
+    bodies: Option<&FunctionBodies>,
+    keep_dead: bool,
+    dead_functions: &mut Vec<String>,
+) -> Vec<bool> {
     let mut dead = Vec::new();
+    let mut descend = vec![false; items.len()];
     for (i, item) in items.iter_mut().enumerate() {
         if is_subprogram(&item) {
             let low_and_high_pc = {
-                let low_pc = item.attrs.get("low_pc");
+                let low_pc = item.attrs.get(&AttrKey::LOW_PC);
                 if low_pc.is_some() {
-                    let high_pc = item.attrs.get("high_pc");
+                    let high_pc = item.attrs.get(&AttrKey::HIGH_PC);
                     if let (
                         Some(DebugAttrValue::I64(low_pc_val)),
                         Some(DebugAttrValue::I64(high_pc_val)),
@@ -99,26 +317,46 @@ fn remove_dead_functions(items: &mut Vec<DebugInfoObj>) {
                 }
             };
             if let Some((low_pc_val, high_pc_val)) = low_and_high_pc {
-                if is_out_of_range(low_pc_val, high_pc_val) {
-                    if is_inlined_subprogram(&item) {
-                        item.attrs.remove("low_pc");
-                        item.attrs.remove("high_pc");
-                    } else {
-                        dead.push(i);
+                match validate_range(low_pc_val, high_pc_val, bodies) {
+                    Some((high_pc_val, func_index)) => {
+                        item.attrs.insert(AttrKey::HIGH_PC, DebugAttrValue::I64(high_pc_val));
+                        if let Some(func_index) = func_index {
+                            item.attrs.insert(AttrKey::FuncIndex, DebugAttrValue::I64(i64::from(func_index)));
+                        }
+                    }
+                    None => {
+                        if is_inlined_subprogram(&item) {
+                            item.attrs.remove(&AttrKey::LOW_PC);
+                            item.attrs.remove(&AttrKey::HIGH_PC);
+                        } else {
+                            dead_functions.push(describe_dead_function(item, &[(low_pc_val, high_pc_val)]));
+                            if keep_dead {
+                                item.attrs.insert(AttrKey::Dead, DebugAttrValue::Bool(true));
+                            } else {
+                                dead.push(i);
+                            }
+                        }
+                        continue;
                     }
-                    continue;
                 }
             }
         }
 
+        let mut first_func_index = None;
+        let mut removed_ranges = Vec::new();
         let present_ranges_are_empty =
-            if let Some(DebugAttrValue::Ranges(ref mut ranges)) = item.attrs.get_mut("ranges") {
+            if let Some(DebugAttrValue::Ranges(ref mut ranges)) = item.attrs.get_mut(&AttrKey::RANGES) {
                 let mut i = 0;
                 while i != ranges.len() {
-                    if is_out_of_range(ranges[i].0, ranges[i].1) {
-                        ranges.remove(i);
-                    } else {
-                        i += 1;
+                    match validate_range(ranges[i].0, ranges[i].1, bodies) {
+                        Some((end, func_index)) => {
+                            ranges[i].1 = end;
+                            first_func_index = first_func_index.or(func_index);
+                            i += 1;
+                        }
+                        None => {
+                            removed_ranges.push(ranges.remove(i));
+                        }
                     }
                 }
                 ranges.is_empty()
@@ -127,20 +365,52 @@ fn remove_dead_functions(items: &mut Vec<DebugInfoObj>) {
             };
         if present_ranges_are_empty && is_subprogram(&item) {
             if is_inlined_subprogram(&item) {
-                item.attrs.remove("ranges");
+                item.attrs.remove(&AttrKey::RANGES);
             } else {
-                dead.push(i);
+                dead_functions.push(describe_dead_function(item, &removed_ranges));
+                if keep_dead {
+                    item.attrs.insert(AttrKey::RANGES, DebugAttrValue::Ranges(removed_ranges));
+                    item.attrs.insert(AttrKey::Dead, DebugAttrValue::Bool(true));
+                } else {
+                    dead.push(i);
+                }
             }
             continue;
         }
-
-        if !item.children.is_empty() {
-            remove_dead_functions(&mut item.children);
+        if let Some(func_index) = first_func_index.filter(|_| is_subprogram(&item)) {
+            item.attrs.insert(AttrKey::FuncIndex, DebugAttrValue::I64(i64::from(func_index)));
         }
+
+        descend[i] = !item.children.is_empty();
     }
     for i in dead.iter().rev() {
         items.remove(*i);
+        descend.remove(*i);
     }
+    descend
+}
+
+/// Removes the functions in code removed by the linker, or marks them as
+/// `dead` with `keep_dead` (their children are left as they are), and
+/// returns their descriptions.
+// Generated code may nest lexical blocks very deeply, so the trees are walked
+// with an explicit stack rather than by recursion.
+fn remove_dead_functions(
+    items: &mut Vec<DebugInfoObj>,
+    bodies: Option<&FunctionBodies>,
+    keep_dead: bool,
+) -> Vec<String> {
+    let mut dead_functions = Vec::new();
+    let mut pending = vec![items];
+    while let Some(items) = pending.pop() {
+        let descend = remove_dead_items(items, bodies, keep_dead, &mut dead_functions);
+        for (item, descend) in items.iter_mut().zip(descend) {
+            if descend {
+                pending.push(&mut item.children);
+            }
+        }
+    }
+    dead_functions
 }
 
 fn enum_to_str(s: Option<&'static str>) -> Result<DebugAttrValue, Error> {
@@ -156,16 +426,67 @@ struct UnitInfos<R: Reader> {
     line_program: Option<gimli::IncompleteLineNumberProgram<R>>,
     comp_dir: Option<R>,
     comp_name: Option<R>,
+    /// Source ids of the file indices already seen in the unit.
+    source_ids: HashMap<u64, usize>,
+}
+
+/// Source paths in the order they were first seen, with their ids. Each
+/// path is allocated once and shared by the list and the lookup table, and
+/// between the per-unit tables and the merged one.
+#[derive(Default)]
+struct Sources {
+    paths: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, usize>,
+}
+
+impl Sources {
+    fn new(paths: Vec<Arc<str>>) -> Self {
+        let mut sources = Sources::default();
+        for path in paths {
+            sources.insert(path);
+        }
+        sources
+    }
+
+    fn insert(&mut self, path: Arc<str>) -> usize {
+        if let Some(&id) = self.ids.get(&path) {
+            return id;
+        }
+        let id = self.paths.len();
+        self.ids.insert(path.clone(), id);
+        self.paths.push(path);
+        id
+    }
+
+    fn id(&mut self, path: &str) -> usize {
+        match self.ids.get(path) {
+            Some(&id) => id,
+            None => self.insert(path.into()),
+        }
+    }
+
+    /// Adds the paths of `other` that are not known yet, and returns the ids
+    /// of the `other` paths.
+    fn merge(&mut self, other: Sources) -> Vec<usize> {
+        other.paths.into_iter().map(|path| self.insert(path)).collect()
+    }
+
+    fn into_paths(self) -> Vec<Arc<str>> {
+        self.paths
+    }
 }
 
 fn get_source_id<R: Reader>(
-    sources: &mut Vec<String>,
-    unit: &UnitInfos<R>,
+    sources: &mut Sources,
+    unit: &mut UnitInfos<R>,
     file_index: u64,
 ) -> Result<Option<i64>, Error> {
     if file_index == 0 {
         return Ok(None);
     }
+    if let Some(&id) = unit.source_ids.get(&file_index) {
+        return Ok(Some(id as i64));
+    }
     let header = match unit.line_program {
         Some(ref program) => program.header(),
         None => return Err(Error::MissingDwarfEntry),
@@ -189,14 +510,9 @@ fn get_source_id<R: Reader>(
         };
         file_name = format!("{}{}/{}", prefix, directory, &file_name);
     }
-    let id = (if let Some(position) = sources.iter().position(|x| *x == file_name) {
-        position
-    } else {
-        let id = sources.len();
-        sources.push(file_name);
-        id
-    }) as i64;
-    Ok(Some(id))
+    let id = sources.id(&file_name);
+    unit.source_ids.insert(file_index, id);
+    Ok(Some(id as i64))
 }
 
 fn decode_data2(d: &[u8]) -> i64 {
@@ -207,41 +523,414 @@ fn decode_data4(d: &[u8]) -> i64 {
     i64::from(d[0]) | (i64::from(d[1]) << 8) | (i64::from(d[2]) << 16) | (i64::from(d[3]) << 24)
 }
 
-pub fn get_debug_scopes<'b>(
-    debug_sections: &'b HashMap<&str, &[u8]>,
-    sources: &mut Vec<String>,
-) -> Result<Vec<DebugInfoObj<'b>>, Error> {
-    // see https://gist.github.com/yurydelendik/802f36983d50cedb05f984d784dc5159
-    let debug_str = &DebugStr::new(&debug_sections[".debug_str"], LittleEndian);
-    let debug_abbrev = &DebugAbbrev::new(&debug_sections[".debug_abbrev"], LittleEndian);
-    let debug_info = &DebugInfo::new(&debug_sections[".debug_info"], LittleEndian);
-    let debug_line = &DebugLine::new(&debug_sections[".debug_line"], LittleEndian);
-
-    let debug_ranges = match debug_sections.get(".debug_ranges") {
-        Some(section) => DebugRanges::new(section, LittleEndian),
-        None => DebugRanges::new(&[], LittleEndian),
-    };
-    let debug_rnglists = DebugRngLists::new(&[], LittleEndian);
-    let rnglists = RangeLists::new(debug_ranges, debug_rnglists)?;
+fn decode_data8(d: &[u8]) -> i64 {
+    decode_data4(&d[0..4]) | (decode_data4(&d[4..8]) << 32)
+}
 
-    let debug_loc = match debug_sections.get(".debug_loc") {
-        Some(section) => DebugLoc::new(section, LittleEndian),
-        None => DebugLoc::new(&[], LittleEndian),
+/// Returns the number of compilation units, for progress reporting. The
+/// count stops at a unit header that cannot be read, as `read_units` does.
+pub fn count_units(debug_sections: &HashMap<&str, &[u8]>) -> usize {
+    let debug_info = match debug_sections.get(".debug_info") {
+        Some(section) => DebugInfo::new(section, LittleEndian),
+        None => return 0,
     };
-    let debug_loclists = DebugLocLists::new(&[], LittleEndian);
-    let loclists = LocationLists::new(debug_loc, debug_loclists)?;
+    let mut iter = debug_info.units();
+    let mut count = 0;
+    while let Ok(Some(_)) = iter.next() {
+        count += 1;
+    }
+    count
+}
 
+/// Reads the unit headers. The units from a header that cannot be read on
+/// are left out of the `output`, which is described in `warnings`.
+fn read_units<'a>(debug_info: &DebugInfo<Slice<'a>>, output: &str, warnings: &mut Vec<String>) -> Vec<UnitHeader<'a>> {
     let mut iter = debug_info.units();
-    let mut info = Vec::new();
-    while let Some(unit) = iter.next().unwrap_or(None) {
+    let mut units: Vec<UnitHeader> = Vec::new();
+    loop {
+        match iter.next() {
+            Ok(Some(unit)) => units.push(unit),
+            Ok(None) => break,
+            Err(err) => {
+                let offset = units
+                    .last()
+                    .map_or(0, |unit| unit.offset().0 + unit.length_including_self());
+                warnings.push(unit_failure(offset, output, err.into()));
+                break;
+            }
+        }
+    }
+    units
+}
+
+/// Describes the failure to read the unit at `offset`, which is left out of
+/// the `output` rather than failing the conversion.
+fn unit_failure(offset: usize, output: &str, err: Error) -> String {
+    format!(
+        "the unit at offset 0x{:x} of .debug_info cannot be read, it is left out of the {}: {:?}",
+        offset, output, err
+    )
+}
+
+/// Converts the units with `convert_unit` and passes the results to
+/// `consume` in the order of the units. With the `parallel` feature, the units
+/// are converted concurrently in batches; `on_unit` and `consume` still run on
+/// the calling thread, `on_unit` before each unit of a batch is started.
+#[cfg(feature = "parallel")]
+fn for_each_unit<'a, T, F>(
+    units: &[UnitHeader<'a>],
+    on_unit: &mut dyn FnMut() -> Result<(), Error>,
+    convert_unit: F,
+    consume: &mut dyn FnMut(T) -> Result<(), Error>,
+) -> Result<(), Error>
+where
+    T: Send,
+    F: Fn(&UnitHeader<'a>) -> Result<T, Error> + Sync,
+{
+    use rayon::prelude::*;
+
+    // A few units per thread, so that uneven units balance out while the
+    // progress and cancellation callbacks are still called regularly.
+    let batch_size = rayon::current_num_threads() * 4;
+    for batch in units.chunks(batch_size) {
+        for _ in batch {
+            on_unit()?;
+        }
+        // The allocations of the workers count in the conversion's budget.
+        let usage = budget::Attachment::current();
+        let converted = batch
+            .par_iter()
+            .map(|unit| usage.run(|| convert_unit(unit)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for result in converted {
+            consume(result)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "parallel"))]
+fn for_each_unit<'a, T, F>(
+    units: &[UnitHeader<'a>],
+    on_unit: &mut dyn FnMut() -> Result<(), Error>,
+    convert_unit: F,
+    consume: &mut dyn FnMut(T) -> Result<(), Error>,
+) -> Result<(), Error>
+where
+    T: Send,
+    F: Fn(&UnitHeader<'a>) -> Result<T, Error> + Sync,
+{
+    for unit in units {
+        on_unit()?;
+        consume(convert_unit(unit)?)?;
+    }
+    Ok(())
+}
+
+/// Same as `for_each_unit`, returning the results in the order of the units.
+fn map_units<'a, T, F>(
+    units: &[UnitHeader<'a>],
+    on_unit: &mut dyn FnMut() -> Result<(), Error>,
+    convert_unit: F,
+) -> Result<Vec<T>, Error>
+where
+    T: Send,
+    F: Fn(&UnitHeader<'a>) -> Result<T, Error> + Sync,
+{
+    let mut results = Vec::with_capacity(units.len());
+    for_each_unit(units, on_unit, convert_unit, &mut |result| {
+        results.push(result);
+        Ok(())
+    })?;
+    Ok(results)
+}
+
+/// The parsed abbreviation tables by their offset. Units produced by the same
+/// toolchain often share their table, which is then parsed once.
+struct AbbreviationsCache<'a> {
+    debug_abbrev: DebugAbbrev<Slice<'a>>,
+    tables: Mutex<HashMap<DebugAbbrevOffset, Arc<Abbreviations>>>,
+}
+
+impl<'a> AbbreviationsCache<'a> {
+    fn new(debug_abbrev: DebugAbbrev<Slice<'a>>) -> Self {
+        AbbreviationsCache {
+            debug_abbrev,
+            tables: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, unit: &UnitHeader<'a>) -> Result<Arc<Abbreviations>, Error> {
+        let offset = unit.debug_abbrev_offset();
+        if let Some(abbrevs) = self.tables.lock().unwrap().get(&offset) {
+            return Ok(abbrevs.clone());
+        }
+        // Parsed without holding the lock; a table parsed twice by
+        // concurrent units is harmless.
+        let abbrevs = Arc::new(self.debug_abbrev.abbreviations(offset)?);
+        self.tables.lock().unwrap().insert(offset, abbrevs.clone());
+        Ok(abbrevs)
+    }
+}
+
+/// The attributes gimli reports as `AttributeValue::FileIndex`, and that
+/// hold source ids in the scopes.
+const SOURCE_ID_ATTRS: &[AttrKey] = &[
+    AttrKey::Dwarf(gimli::DW_AT_decl_file),
+    AttrKey::Dwarf(gimli::DW_AT_call_file),
+];
+
+/// The attributes the scopes are processed with, which are read even when
+/// they are not among the requested ones.
+const REQUIRED_ATTRS: &[AttrKey] = &[AttrKey::LOW_PC, AttrKey::HIGH_PC, AttrKey::RANGES, AttrKey::INLINE];
+
+fn remap_source_ids(items: &mut Vec<DebugInfoObj>, ids: &[usize]) {
+    let mut pending: Vec<&mut DebugInfoObj> = items.iter_mut().collect();
+    while let Some(item) = pending.pop() {
+        for key in SOURCE_ID_ATTRS {
+            if let Some(DebugAttrValue::I64(ref mut id)) = item.attrs.get_mut(key) {
+                if *id >= 0 {
+                    *id = ids[*id as usize] as i64;
+                }
+            }
+        }
+        pending.extend(item.children.iter_mut());
+    }
+}
+
+type Locations<'a> = Vec<(Option<(i64, i64)>, &'a [u8])>;
+
+/// The `location` of an entry, either a single expression or a location
+/// list, as a list of expressions with the ranges moved by
+/// `code_section_offset` to module offsets.
+fn normalized_location<'a>(
+    item: &DebugInfoObj<'a>,
+    code_section_offset: i64,
+) -> Option<Locations<'a>> {
+    match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_location)) {
+        Some(&DebugAttrValue::Expression(expr)) => Some(vec![(None, expr)]),
+        Some(DebugAttrValue::LocationList(list)) => Some(
+            list.iter()
+                .map(|&(begin, end, expr)| {
+                    let begin = begin.wrapping_add(code_section_offset);
+                    (Some((begin, end.wrapping_add(code_section_offset))), expr)
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Adds the normalized `locations` of the variables and parameters.
+pub fn add_locations(items: &mut [DebugInfoObj], code_section_offset: i64) {
+    let mut pending: Vec<&mut DebugInfoObj> = items.iter_mut().collect();
+    while let Some(item) = pending.pop() {
+        if item.tag == "variable" || item.tag == "formal_parameter" {
+            if let Some(locations) = normalized_location(item, code_section_offset) {
+                item.attrs.insert(AttrKey::Locations, DebugAttrValue::Locations(locations));
+            }
+        }
+        pending.extend(item.children.iter_mut());
+    }
+}
+
+fn name_attr<'a>(item: &DebugInfoObj<'a>) -> Option<&'a str> {
+    match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_name)) {
+        Some(&DebugAttrValue::String(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn type_ref<'a>(item: &DebugInfoObj<'a>) -> Option<(usize, Option<&'a str>)> {
+    match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_type)) {
+        Some(&DebugAttrValue::UIDRef(uid, name)) => Some((uid, name)),
+        _ => None,
+    }
+}
+
+/// Adds the `parameters` of the subprograms, summarizing their
+/// `formal_parameter` children in order.
+pub fn add_parameters(items: &mut [DebugInfoObj], code_section_offset: i64) {
+    let mut pending: Vec<&mut DebugInfoObj> = items.iter_mut().collect();
+    while let Some(item) = pending.pop() {
+        if is_subprogram(item) {
+            let parameters: Vec<_> = item
+                .children
+                .iter()
+                .filter(|child| child.tag == "formal_parameter")
+                .map(|child| Parameter {
+                    name: name_attr(child),
+                    type_ref: type_ref(child),
+                    locations: normalized_location(child, code_section_offset),
+                })
+                .collect();
+            item.attrs.insert(AttrKey::Parameters, DebugAttrValue::Parameters(parameters));
+        }
+        pending.extend(item.children.iter_mut());
+    }
+}
+
+/// Adds the `template_parameters` of the generic functions and types,
+/// summarizing their template parameter children in order.
+pub fn add_template_parameters(items: &mut [DebugInfoObj]) {
+    let mut pending: Vec<&mut DebugInfoObj> = items.iter_mut().collect();
+    while let Some(item) = pending.pop() {
+        if TEMPLATE_OWNER_TAGS.contains(&item.tag) {
+            let parameters: Vec<_> = item
+                .children
+                .iter()
+                .filter(|child| {
+                    child.tag == "template_type_parameter" || child.tag == "template_value_parameter"
+                })
+                .map(|child| TemplateParameter {
+                    is_value: child.tag == "template_value_parameter",
+                    name: name_attr(child),
+                    type_ref: type_ref(child),
+                    value: match child.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_const_value)) {
+                        Some(&DebugAttrValue::I64(value)) => Some(value),
+                        _ => None,
+                    },
+                })
+                .collect();
+            if !parameters.is_empty() {
+                item.attrs.insert(
+                    AttrKey::TemplateParameters,
+                    DebugAttrValue::TemplateParameters(parameters),
+                );
+            }
+        }
+        pending.extend(item.children.iter_mut());
+    }
+}
+
+/// The line program headers parsed by the units, by their offset and the
+/// unit properties they are parsed with.
+type LineProgramKey<'a> = (usize, u8, Option<&'a [u8]>, Option<&'a [u8]>);
+
+/// The sections needed to read the line programs and the debug information
+/// tree, with the abbreviation tables and the line program headers that are
+/// parsed once for both.
+pub struct DwarfContext<'a> {
+    debug_str: DebugStr<Slice<'a>>,
+    abbreviations: AbbreviationsCache<'a>,
+    debug_info: DebugInfo<Slice<'a>>,
+    debug_line_section: &'a [u8],
+    debug_line: DebugLine<Slice<'a>>,
+    line_programs: Mutex<HashMap<LineProgramKey<'a>, gimli::IncompleteLineNumberProgram<Slice<'a>>>>,
+    rnglists: RangeLists<Slice<'a>>,
+    loclists: LocationLists<Slice<'a>>,
+}
+
+impl<'a> DwarfContext<'a> {
+    pub fn new(debug_sections: &'a HashMap<&str, &[u8]>) -> Result<Self, Error> {
+        // see https://gist.github.com/yurydelendik/802f36983d50cedb05f984d784dc5159
+        let debug_ranges = match debug_sections.get(".debug_ranges") {
+            Some(section) => DebugRanges::new(section, LittleEndian),
+            None => DebugRanges::new(&[], LittleEndian),
+        };
+        let debug_rnglists = DebugRngLists::new(&[], LittleEndian);
+
+        let debug_loc = match debug_sections.get(".debug_loc") {
+            Some(section) => DebugLoc::new(section, LittleEndian),
+            None => DebugLoc::new(&[], LittleEndian),
+        };
+        let debug_loclists = DebugLocLists::new(&[], LittleEndian);
+
+        let section = |name| debug_sections.get(name).ok_or(Error::MissingSection);
+        let debug_line_section = section(".debug_line")?;
+        Ok(DwarfContext {
+            debug_str: DebugStr::new(section(".debug_str")?, LittleEndian),
+            abbreviations: AbbreviationsCache::new(DebugAbbrev::new(section(".debug_abbrev")?, LittleEndian)),
+            debug_info: DebugInfo::new(section(".debug_info")?, LittleEndian),
+            debug_line_section,
+            debug_line: DebugLine::new(debug_line_section, LittleEndian),
+            line_programs: Mutex::new(HashMap::new()),
+            rnglists: RangeLists::new(debug_ranges, debug_rnglists)?,
+            loclists: LocationLists::new(debug_loc, debug_loclists)?,
+        })
+    }
+
+    /// Returns the line program at `offset` of a unit, whose header is only
+    /// parsed by the first unit or pass that reads it.
+    fn line_program(
+        &self,
+        offset: DebugLineOffset,
+        address_size: u8,
+        comp_dir: Option<Slice<'a>>,
+        comp_name: Option<Slice<'a>>,
+    ) -> Result<gimli::IncompleteLineNumberProgram<Slice<'a>>, Error> {
+        let key = (
+            offset.0,
+            address_size,
+            comp_dir.map(|dir| dir.slice()),
+            comp_name.map(|name| name.slice()),
+        );
+        if let Some(program) = self.line_programs.lock().unwrap().get(&key) {
+            return Ok(program.clone());
+        }
+        let program = self.debug_line.program(offset, address_size, comp_dir, comp_name)?;
+        self.line_programs.lock().unwrap().insert(key, program.clone());
+        Ok(program)
+    }
+
+    /// Returns the address ranges of the unit, or `None` when its root entry
+    /// does not describe them.
+    fn unit_ranges(&self, unit: &UnitHeader<'a>) -> Result<Option<Vec<Range<u64>>>, Error> {
+        let abbrevs = self.abbreviations.get(unit)?;
+        let mut cursor = unit.entries(&abbrevs);
+        cursor.next_dfs()?;
+        let root = cursor.current().ok_or(Error::MissingDwarfEntry)?;
+        if let Some(AttributeValue::RangeListsRef(r)) = root.attr_value(gimli::DW_AT_ranges)? {
+            let mut ranges = self.rnglists.ranges(r, unit.version(), unit.address_size(), 0)?;
+            let mut result = Vec::new();
+            while let Some(range) = ranges.next()? {
+                result.push(range.begin..range.end);
+            }
+            return Ok(Some(result));
+        }
+        let low_pc = match root.attr_value(gimli::DW_AT_low_pc)? {
+            Some(AttributeValue::Addr(address)) => address,
+            _ => return Ok(None),
+        };
+        Ok(match root.attr_value(gimli::DW_AT_high_pc)? {
+            Some(AttributeValue::Addr(address)) => Some(vec![low_pc..address]),
+            Some(AttributeValue::Udata(size)) => Some(vec![low_pc..low_pc.wrapping_add(size)]),
+            _ => None,
+        })
+    }
+
+    /// Reads the entries of the unit, with the `attributes` only when they
+    /// are set. The source ids of the result refer to the returned sources.
+    fn unit_scopes(
+        &self,
+        unit: &UnitHeader<'a>,
+        attributes: Option<&HashSet<String>>,
+    ) -> Result<(Vec<DebugInfoObj<'a>>, Sources, Vec<String>), Error> {
+        let DwarfContext {
+            ref debug_str,
+            ref abbreviations,
+            ref rnglists,
+            ref loclists,
+            ..
+        } = *self;
+        let mut unit_sources = Sources::default();
         let mut unit_infos = UnitInfos {
             address_size: unit.address_size(),
             base_address: 0,
             comp_dir: None,
             comp_name: None,
             line_program: None,
+            source_ids: HashMap::new(),
         };
-        let abbrevs = unit.abbreviations(debug_abbrev)?;
+        let abbrevs = abbreviations.get(unit)?;
+        // The names of the referenced entries, by offset; the popular types
+        // are referenced many times.
+        let mut ref_names = HashMap::new();
+        // The attributes left out as `???` and `<ignored>`, and the tags
+        // written as `unknown`.
+        let mut unknown_attrs = BTreeSet::new();
+        let mut ignored_refs = 0;
+        let mut unknown_tags = BTreeSet::new();
 
         let mut stack: Vec<DebugInfoObj> = Vec::new();
         stack.push(DebugInfoObj {
@@ -264,8 +953,8 @@ pub fn get_debug_scopes<'b>(
                     .attr(gimli::DW_AT_name)?
                     .and_then(|attr| attr.string_value(debug_str));
                 unit_infos.line_program = match entry.attr_value(gimli::DW_AT_stmt_list)? {
-                    Some(AttributeValue::DebugLineRef(offset)) => debug_line
-                        .program(
+                    Some(AttributeValue::DebugLineRef(offset)) => self
+                        .line_program(
                             offset,
                             unit_infos.address_size,
                             unit_infos.comp_dir,
@@ -276,22 +965,34 @@ pub fn get_debug_scopes<'b>(
             }
 
             let mut attrs_values = HashMap::new();
-            attrs_values.insert("uid", DebugAttrValue::UID(entry.offset().0));
+            attrs_values.insert(AttrKey::Uid, DebugAttrValue::UID(entry.offset().0));
 
-            let tag_value = &entry.tag().static_string().unwrap()[ /*DW_TAG_*/ 7..];
+            let tag_value = match entry.tag().static_string() {
+                Some(tag) => &tag[ /*DW_TAG_*/ 7..],
+                // Vendor extensions gimli does not know about.
+                None => {
+                    unknown_tags.insert(entry.tag().0);
+                    "unknown"
+                }
+            };
             let mut attrs = entry.attrs();
             while let Some(attr) = attrs.next()? {
-                let attr_name = &attr.name().static_string().unwrap()[ /*DW_AT_*/ 6 ..];
+                let key = AttrKey::Dwarf(attr.name());
+                if let Some(attributes) = attributes {
+                    if !REQUIRED_ATTRS.contains(&key) && !attributes.contains(&*key.name()) {
+                        continue;
+                    }
+                }
                 let attr_value = match attr.value() {
                     AttributeValue::Addr(u) => DebugAttrValue::I64(u as i64),
                     AttributeValue::Udata(u) => {
-                        if attr_name != "high_pc" {
+                        if attr.name() != gimli::DW_AT_high_pc {
                             DebugAttrValue::I64(u as i64)
                         } else {
                             DebugAttrValue::I64(
-                                u as i64
-                                    + (if let Some(DebugAttrValue::I64(low_pc)) =
-                                        attrs_values.get("low_pc")
+                                (u as i64).wrapping_add(
+                                    if let Some(DebugAttrValue::I64(low_pc)) =
+                                        attrs_values.get(&AttrKey::LOW_PC)
                                     {
                                         *low_pc
                                     } else {
@@ -303,11 +1004,12 @@ pub fn get_debug_scopes<'b>(
                     AttributeValue::Data1(u) => DebugAttrValue::I64(i64::from(u[0])),
                     AttributeValue::Data2(u) => DebugAttrValue::I64(decode_data2(&u.0)),
                     AttributeValue::Data4(u) => DebugAttrValue::I64(decode_data4(&u.0)),
+                    AttributeValue::Data8(u) => DebugAttrValue::I64(decode_data8(&u.0)),
                     AttributeValue::Sdata(i) => DebugAttrValue::I64(i),
                     AttributeValue::DebugLineRef(o) => DebugAttrValue::I64(o.0 as i64),
                     AttributeValue::Flag(f) => DebugAttrValue::Bool(f),
                     AttributeValue::FileIndex(i) => DebugAttrValue::I64(
-                        get_source_id(sources, &unit_infos, i)?.unwrap_or(-1), // FIXME do we need -1?
+                        get_source_id(&mut unit_sources, &mut unit_infos, i)?.unwrap_or(-1), // FIXME do we need -1?
                     ),
                     AttributeValue::DebugStrRef(str_offset) => {
                         DebugAttrValue::String(debug_str.get_str(str_offset)?.to_string()?)
@@ -318,7 +1020,9 @@ pub fn get_debug_scopes<'b>(
                             rnglists.ranges(r, unit.version(), unit.address_size(), low_pc)?;
                         let mut result = Vec::new();
                         while let Some(range) = ranges.next()? {
-                            assert!(range.begin <= range.end);
+                            if range.begin > range.end {
+                                return Err(Error::DataFormat);
+                            }
                             result.push((range.begin as i64, range.end as i64));
                         }
                         DebugAttrValue::Ranges(result)
@@ -353,32 +1057,47 @@ pub fn get_debug_scopes<'b>(
                     AttributeValue::Inline(e) => enum_to_str(e.static_string())?,
                     AttributeValue::Ordering(e) => enum_to_str(e.static_string())?,
                     AttributeValue::UnitRef(offset) => {
-                        let mut unit_entries = unit.entries_at_offset(&abbrevs, offset)?;
-                        unit_entries.next_entry()?;
-                        let entry = unit_entries.current().ok_or(Error::MissingDwarfEntry)?;
-                        let name = if let Some(AttributeValue::DebugStrRef(str_offset)) =
-                            entry.attr_value(gimli::DW_AT_linkage_name)?
-                        {
-                            Some(debug_str.get_str(str_offset)?.to_string()?)
-                        } else if let Some(AttributeValue::DebugStrRef(str_offset)) =
-                            entry.attr_value(gimli::DW_AT_name)?
-                        {
-                            Some(debug_str.get_str(str_offset)?.to_string()?)
-                        } else {
-                            None
+                        let name = match ref_names.get(&offset) {
+                            Some(&name) => name,
+                            None => {
+                                let mut unit_entries = unit.entries_at_offset(&abbrevs, offset)?;
+                                unit_entries.next_entry()?;
+                                let entry = unit_entries.current().ok_or(Error::MissingDwarfEntry)?;
+                                let name = if let Some(AttributeValue::DebugStrRef(str_offset)) =
+                                    entry.attr_value(gimli::DW_AT_linkage_name)?
+                                {
+                                    Some(debug_str.get_str(str_offset)?.to_string()?)
+                                } else if let Some(AttributeValue::DebugStrRef(str_offset)) =
+                                    entry.attr_value(gimli::DW_AT_name)?
+                                {
+                                    Some(debug_str.get_str(str_offset)?.to_string()?)
+                                } else {
+                                    None
+                                };
+                                ref_names.insert(offset, name);
+                                name
+                            }
                         };
                         DebugAttrValue::UIDRef(offset.0, name)
                     }
                     AttributeValue::DebugInfoRef(_) => {
                         // Types and stuff
+                        ignored_refs += 1;
                         DebugAttrValue::Ignored
                     }
-                    _ => DebugAttrValue::Unknown,
+                    _ => {
+                        unknown_attrs.insert(key.name());
+                        DebugAttrValue::Unknown
+                    }
                 };
-                attrs_values.insert(attr_name, attr_value);
+                attrs_values.insert(key, attr_value);
             }
-            if depth_delta <= 0 && stack.len() > 1 {
+            if depth_delta <= 0 {
+                // Malformed units may close more entries than they opened.
                 for _ in 0..1 - depth_delta {
+                    if stack.len() <= 1 {
+                        break;
+                    }
                     let past = stack.pop().unwrap();
                     stack.last_mut().unwrap().children.push(past);
                 }
@@ -394,10 +1113,119 @@ pub fn get_debug_scopes<'b>(
             let past = stack.pop().unwrap();
             stack.last_mut().unwrap().children.push(past);
         }
-        info.append(&mut stack.pop().unwrap().children);
+        let mut root = stack.pop().unwrap();
+        let unit_name = unit_infos
+            .comp_name
+            .and_then(|name| name.to_string().ok())
+            .unwrap_or("<unnamed>");
+        let mut warnings = Vec::new();
+        if !unknown_attrs.is_empty() {
+            let names: Vec<_> = unknown_attrs.into_iter().collect();
+            warnings.push(format!(
+                "the unit {} has attributes in unsupported forms, written as \"???\": {}",
+                unit_name,
+                names.join(", ")
+            ));
+        }
+        if ignored_refs > 0 {
+            warnings.push(format!(
+                "the unit {} has {} references to other units, written as \"<ignored>\"",
+                unit_name, ignored_refs
+            ));
+        }
+        if !unknown_tags.is_empty() {
+            let tags: Vec<_> = unknown_tags.iter().map(|tag| format!("0x{:x}", tag)).collect();
+            warnings.push(format!(
+                "the unit {} has entries with unknown tags, written as \"unknown\": {}",
+                unit_name,
+                tags.join(", ")
+            ));
+        }
+        Ok((mem::replace(&mut root.children, Vec::new()), unit_sources, warnings))
     }
-    remove_dead_functions(&mut info);
-    Ok(info)
+}
+
+/// Reads the scopes of the units, of the ones with code in `range` only when
+/// it is set; the units that do not describe their address ranges are always
+/// included. When `attributes` are set, the other attributes are left out,
+/// apart from the ones needed to process the scopes.
+///
+/// The entries of each unit are passed to `on_scopes` as soon as the unit is
+/// read, so that the trees of all units are not held together. Their source
+/// ids refer to `sources`, which is extended with the files of the units.
+/// The data that is left out is described in `warnings`, including the
+/// functions in removed code, which are kept and marked as `dead` with
+/// `keep_dead`.
+#[allow(clippy::too_many_arguments)]
+pub fn read_debug_scopes<'b>(
+    context: &DwarfContext<'b>,
+    sources: &mut Vec<Arc<str>>,
+    warnings: &mut Vec<String>,
+    bodies: Option<&FunctionBodies>,
+    keep_dead: bool,
+    attributes: Option<&HashSet<String>>,
+    range: Option<&Range<u64>>,
+    on_unit: &mut dyn FnMut() -> Result<(), Error>,
+    on_scopes: &mut dyn FnMut(Vec<DebugInfoObj<'b>>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut units = read_units(&context.debug_info, "scopes", warnings);
+    if let Some(range) = range {
+        let mut overlapping = Vec::new();
+        for unit in units {
+            // The units whose ranges cannot be read are included, to report
+            // their failure.
+            let overlaps = match context.unit_ranges(&unit) {
+                Ok(Some(ranges)) => ranges
+                    .iter()
+                    .any(|r| r.start < range.end && range.start < r.end),
+                _ => true,
+            };
+            if overlaps {
+                overlapping.push(unit);
+            }
+        }
+        units = overlapping;
+    }
+
+    // Each unit is converted with its own source list, which is merged into
+    // `sources` in the order of the units.
+    let mut merged_sources = Sources::new(mem::replace(sources, Vec::new()));
+    let result = for_each_unit(
+        &units,
+        on_unit,
+        |unit| {
+            let scopes = context.unit_scopes(unit, attributes);
+            Ok(scopes.map_err(|err| unit_failure(unit.offset().0, "scopes", err)))
+        },
+        &mut |result| {
+            let (mut items, unit_sources, unit_warnings) = match result {
+                Ok(unit) => unit,
+                Err(warning) => {
+                    warnings.push(warning);
+                    return Ok(());
+                }
+            };
+            warnings.extend(unit_warnings);
+            remap_source_ids(&mut items, &merged_sources.merge(unit_sources));
+            let dead_functions = remove_dead_functions(&mut items, bodies, keep_dead);
+            if !dead_functions.is_empty() {
+                let unit_name = match items.first().and_then(|unit| unit.attrs.get(&AttrKey::NAME)) {
+                    Some(&DebugAttrValue::String(name)) => name,
+                    _ => "<unnamed>",
+                };
+                warnings.push(format!(
+                    "the unit {} has {} functions in removed code{}: {}",
+                    unit_name,
+                    dead_functions.len(),
+                    if keep_dead { ", marked as dead" } else { "" },
+                    dead_functions.join(", ")
+                ));
+            }
+            on_scopes(items)
+        },
+    );
+    *sources = merged_sources.into_paths();
+    result
 }
 
 pub struct LocationRecord {
@@ -408,117 +1236,556 @@ pub struct LocationRecord {
 }
 
 pub struct LocationInfo {
-    pub sources: Vec<String>,
+    /// The source paths, shared with the tables they were collected in.
+    pub sources: Vec<Arc<str>>,
     pub locations: Vec<LocationRecord>,
+    /// Descriptions of the line information that was left out.
+    pub warnings: Vec<String>,
 }
 
-pub fn get_debug_loc(debug_sections: &HashMap<&str, &[u8]>) -> Result<LocationInfo, Error> {
-    let mut sources = Vec::new();
-    let mut locations: Vec<LocationRecord> = Vec::new();
-    let mut source_to_id_map: HashMap<u64, usize> = HashMap::new();
+pub fn get_debug_loc(
+    context: &DwarfContext,
+    bodies: Option<&FunctionBodies>,
+    cache_dir: Option<&Path>,
+    on_unit: &mut dyn FnMut() -> Result<(), Error>,
+) -> Result<LocationInfo, Error> {
+    let mut warnings = Vec::new();
+    let units = map_units(&read_units(&context.debug_info, "mappings", &mut warnings), on_unit, |unit| {
+        Ok(get_unit_loc(unit, context, bodies, cache_dir)
+            .map_err(|err| unit_failure(unit.offset().0, "mappings", err)))
+    })?;
+    let mut sources = Sources::default();
+    let mut locations: Vec<LocationRecord> = Vec::with_capacity(
+        units
+            .iter()
+            .filter_map(|unit| unit.as_ref().ok())
+            .map(|(_, unit_locations, _)| unit_locations.len())
+            .sum(),
+    );
+    for unit in units {
+        let (unit_sources, unit_locations, unit_warnings) = match unit {
+            Ok(unit) => unit,
+            Err(warning) => {
+                warnings.push(warning);
+                continue;
+            }
+        };
+        warnings.extend(unit_warnings);
+        let ids = sources.merge(unit_sources);
+        locations.extend(unit_locations.into_iter().map(|mut loc| {
+            loc.source_id = ids[loc.source_id as usize] as u32;
+            loc
+        }));
+    }
 
-    let debug_str = &DebugStr::new(&debug_sections.get(".debug_str").ok_or(Error::MissingSection)?, LittleEndian);
-    let debug_abbrev = &DebugAbbrev::new(&debug_sections.get(".debug_abbrev").ok_or(Error::MissingSection)?, LittleEndian);
-    let debug_info = &DebugInfo::new(&debug_sections.get(".debug_info").ok_or(Error::MissingSection)?, LittleEndian);
-    let debug_line = &DebugLine::new(&debug_sections.get(".debug_line").ok_or(Error::MissingSection)?, LittleEndian);
+    locations.sort_by(|a, b| a.address.cmp(&b.address));
 
-    let mut iter = debug_info.units();
-    while let Some(unit) = iter.next().unwrap_or(None) {
-        let abbrevs = unit.abbreviations(debug_abbrev)?;
-        let mut cursor = unit.entries(&abbrevs);
-        cursor.next_dfs()?;
-        let root = cursor.current().ok_or(Error::MissingDwarfEntry)?;
-        let offset = match root.attr_value(gimli::DW_AT_stmt_list)? {
-            Some(gimli::AttributeValue::DebugLineRef(offset)) => offset,
-            _ => continue,
-        };
-        let comp_dir = root
-            .attr(gimli::DW_AT_comp_dir)?
-            .and_then(|attr| attr.string_value(debug_str));
-        let comp_name = root
-            .attr(gimli::DW_AT_name)?
-            .and_then(|attr| attr.string_value(debug_str));
-        let program = debug_line.program(offset, unit.address_size(), comp_dir, comp_name);
-        let mut block_start_loc = locations.len();
-        if let Ok(program) = program {
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                let pc = row.address();
-                let line = row.line().unwrap_or(0);
-                let column = match row.column() {
-                    gimli::ColumnType::Column(column) => column,
-                    gimli::ColumnType::LeftEdge => 0,
-                };
-                let file_index = row.file_index();
-                let source_id = if !source_to_id_map.contains_key(&file_index) {
-                    let mut file_path: String = if let Some(file) = row.file(header) {
-                        if let Some(directory) = file.directory(header) {
-                            format!(
-                                "{}/{}",
-                                directory.to_string_lossy(),
-                                file.path_name().to_string_lossy()
-                            )
-                        } else {
-                            String::from(file.path_name().to_string_lossy())
-                        }
-                    } else {
-                        String::from("<unknown>")
-                    };
-                    if !file_path.starts_with('/') && comp_dir.is_some() {
-                        file_path = format!("{}/{}", comp_dir.unwrap().to_string_lossy(), file_path);
-                    }
-                    sources
-                        .iter()
-                        .position(|p| *p == file_path)
-                        .unwrap_or_else(|| {
-                            let index = sources.len();
-                            sources.push(file_path);
-                            source_to_id_map.insert(file_index, index);
-                            index
-                        })
-                } else {
-                    *source_to_id_map.get(&file_index).ok_or(Error::DataFormat)? as usize
-                };
-                let mut loc = LocationRecord {
-                    address: pc,
-                    source_id: source_id as u32,
-                    line: line as u32,
-                    column: column as u32,
-                };
-                let end_sequence = if row.end_sequence() {
-                    // end_sequence falls on the byte after function's end --
-                    // moving address one step back.
-                    loc.address -= 1;
-                    // Compacting duplicate records.
-                    if locations[locations.len() - 1].address < loc.address {
-                        locations.push(loc);
+    Ok(LocationInfo {
+        sources: sources.into_paths(),
+        locations,
+        warnings,
+    })
+}
+
+/// The rows of a line program: its sequences, and the rows past the last
+/// end_sequence. The source ids refer to `sources`.
+#[derive(Default)]
+struct LineRows {
+    sources: Sources,
+    sequences: Vec<Vec<LocationRecord>>,
+    trailing: Vec<LocationRecord>,
+}
+
+impl LineRows {
+    fn encode(&self) -> Vec<u8> {
+        fn write_records(output: &mut Vec<u8>, records: &[LocationRecord]) {
+            wasm::write_u64_leb128(output, records.len() as u64);
+            for loc in records {
+                wasm::write_u64_leb128(output, loc.address);
+                wasm::write_u32_leb128(output, loc.source_id);
+                wasm::write_u32_leb128(output, loc.line);
+                wasm::write_u32_leb128(output, loc.column);
+            }
+        }
+
+        let mut output = Vec::new();
+        wasm::write_u64_leb128(&mut output, self.sources.paths.len() as u64);
+        for path in &self.sources.paths {
+            wasm::write_str(&mut output, path);
+        }
+        wasm::write_u64_leb128(&mut output, self.sequences.len() as u64);
+        for sequence in &self.sequences {
+            write_records(&mut output, sequence);
+        }
+        write_records(&mut output, &self.trailing);
+        output
+    }
+
+    /// Decodes an entry of `encode`; a damaged entry is `None`.
+    fn decode(data: &[u8]) -> Option<LineRows> {
+        fn read_records(decoder: &mut WasmDecoder) -> Option<Vec<LocationRecord>> {
+            let len = decoder.u64().ok()?;
+            let mut records = Vec::new();
+            for _ in 0..len {
+                records.push(LocationRecord {
+                    address: decoder.u64().ok()?,
+                    source_id: decoder.u32().ok()?,
+                    line: decoder.u32().ok()?,
+                    column: decoder.u32().ok()?,
+                });
+            }
+            Some(records)
+        }
+
+        let mut decoder = WasmDecoder::new(data);
+        let mut rows = LineRows::default();
+        for _ in 0..decoder.u64().ok()? {
+            rows.sources.insert(decoder.str().ok()?.into());
+        }
+        for _ in 0..decoder.u64().ok()? {
+            let sequence = read_records(&mut decoder)?;
+            if sequence.is_empty() {
+                return None;
+            }
+            rows.sequences.push(sequence);
+        }
+        rows.trailing = read_records(&mut decoder)?;
+        let source_count = rows.sources.paths.len() as u32;
+        let valid = decoder.eof()
+            && rows
+                .sequences
+                .iter()
+                .chain(Some(&rows.trailing))
+                .flatten()
+                .all(|loc| loc.source_id < source_count);
+        if valid {
+            Some(rows)
+        } else {
+            None
+        }
+    }
+}
+
+/// The cache key of the decoded line program of a unit, which is decoded
+/// with the directory and name of the unit. The units are cached by their
+/// line program, so that a module with a few changed units decodes only
+/// theirs again.
+fn line_rows_key(program: &[u8], address_size: u8, comp_dir: Option<&[u8]>, comp_name: Option<&[u8]>) -> CacheKey {
+    let mut key = CacheKey::new("lines");
+    key.add_u64(u64::from(address_size));
+    key.add_bytes(program);
+    key.add_optional_bytes(comp_dir);
+    key.add_optional_bytes(comp_name);
+    key
+}
+
+/// The bytes of the line program at `offset`, header included.
+fn line_program_bytes(debug_line: &[u8], offset: usize) -> Option<&[u8]> {
+    let data = debug_line.get(offset..)?;
+    let (header_len, len) = match read_u32_le(data)? {
+        0xffff_ffff => (12, read_u64_le(data.get(4..)?)?),
+        len => (4, u64::from(len)),
+    };
+    let end = len.checked_add(header_len)?;
+    data.get(..usize::try_from(end).ok()?)
+}
+
+fn read_u32_le(data: &[u8]) -> Option<u32> {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(data.get(..4)?);
+    Some(u32::from_le_bytes(buf))
+}
+
+fn read_u64_le(data: &[u8]) -> Option<u64> {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(data.get(..8)?);
+    Some(u64::from_le_bytes(buf))
+}
+
+#[cfg(feature = "parallel")]
+fn skip_leb128(data: &[u8], mut pos: usize) -> Option<usize> {
+    while *data.get(pos)? & 0x80 != 0 {
+        pos += 1;
+    }
+    Some(pos + 1)
+}
+
+#[cfg(feature = "parallel")]
+/// Splits a line program at its end_sequence boundaries into about `count`
+/// programs with the same header, which decode to the same rows since the
+/// state machine is reset after each sequence. `None` when the program
+/// cannot be split: it has a single sequence, defines files among its
+/// opcodes (which the later sequences may refer to), or is not understood.
+fn split_line_program(program: &[u8], count: usize) -> Option<Vec<Vec<u8>>> {
+    let (length_size, offset_size) = match read_u32_le(program)? {
+        0xffff_ffff => (12, 8),
+        _ => (4, 4),
+    };
+    let version = u16::from_le_bytes([*program.get(length_size)?, *program.get(length_size + 1)?]);
+    if version < 2 || version > 4 {
+        return None;
+    }
+    let header_length_at = length_size + 2;
+    let header_length = match offset_size {
+        8 => read_u64_le(program.get(header_length_at..)?)?,
+        _ => u64::from(read_u32_le(program.get(header_length_at..)?)?),
+    };
+    let opcodes_start = usize::try_from(header_length)
+        .ok()?
+        .checked_add(header_length_at + offset_size)?;
+    // minimum_instruction_length, maximum_operations_per_instruction (from
+    // version 4), default_is_stmt, line_base, line_range
+    let opcode_base_at = header_length_at + offset_size + if version >= 4 { 5 } else { 4 };
+    let opcode_base = *program.get(opcode_base_at)?;
+    let opcode_lengths = program.get(opcode_base_at + 1..opcode_base_at + usize::from(opcode_base))?;
+    if opcodes_start > program.len() {
+        return None;
+    }
+
+    // The offsets past the end_sequence opcodes.
+    let mut boundaries = Vec::new();
+    let mut pos = opcodes_start;
+    while pos < program.len() {
+        let opcode = program[pos];
+        pos += 1;
+        if opcode >= opcode_base {
+            continue;
+        }
+        match opcode {
+            0 => {
+                let len_end = skip_leb128(program, pos)?;
+                let mut decoder = WasmDecoder::new(program.get(pos..len_end)?);
+                let len = usize::try_from(decoder.u64().ok()?).ok()?;
+                let end = len_end.checked_add(len)?;
+                match program.get(len_end).filter(|_| len > 0) {
+                    Some(&DW_LNE_END_SEQUENCE) => boundaries.push(end),
+                    Some(&DW_LNE_DEFINE_FILE) => return None,
+                    _ => (),
+                }
+                pos = end;
+            }
+            // The operand of DW_LNS_fixed_advance_pc is a uhalf.
+            DW_LNS_FIXED_ADVANCE_PC => pos += 2,
+            _ => {
+                for _ in 0..opcode_lengths[usize::from(opcode) - 1] {
+                    pos = skip_leb128(program, pos)?;
+                }
+            }
+        }
+    }
+    if pos > program.len() || boundaries.len() < 2 {
+        return None;
+    }
+    if boundaries.last() != Some(&program.len()) {
+        boundaries.push(program.len());
+    }
+
+    let header = &program[..opcodes_start];
+    let chunk_size = (program.len() - opcodes_start) / count.max(1) + 1;
+    let mut chunks = Vec::new();
+    let mut start = opcodes_start;
+    for &end in &boundaries {
+        if end - start < chunk_size && end != program.len() {
+            continue;
+        }
+        let mut chunk = header.to_vec();
+        chunk.extend_from_slice(&program[start..end]);
+        let unit_length = (chunk.len() - length_size) as u64;
+        match length_size {
+            12 => chunk[4..12].copy_from_slice(&unit_length.to_le_bytes()),
+            _ => chunk[..4].copy_from_slice(&u32::try_from(unit_length).ok()?.to_le_bytes()),
+        }
+        chunks.push(chunk);
+        start = end;
+    }
+    Some(chunks)
+}
+
+#[cfg(feature = "parallel")]
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+#[cfg(feature = "parallel")]
+const DW_LNE_END_SEQUENCE: u8 = 1;
+#[cfg(feature = "parallel")]
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+/// Reads the line program of one unit. The source ids of the locations
+/// refer to the returned sources; the warnings tell why a unit has no
+/// locations and which sequences were in removed code.
+fn get_unit_loc<'a>(
+    unit: &UnitHeader<'a>,
+    context: &DwarfContext<'a>,
+    bodies: Option<&FunctionBodies>,
+    cache_dir: Option<&Path>,
+) -> Result<(Sources, Vec<LocationRecord>, Vec<String>), Error> {
+    let debug_str = &context.debug_str;
+    let abbrevs = context.abbreviations.get(unit)?;
+    let mut cursor = unit.entries(&abbrevs);
+    cursor.next_dfs()?;
+    let root = cursor.current().ok_or(Error::MissingDwarfEntry)?;
+    let comp_dir = root
+        .attr(gimli::DW_AT_comp_dir)?
+        .and_then(|attr| attr.string_value(debug_str));
+    let comp_name = root
+        .attr(gimli::DW_AT_name)?
+        .and_then(|attr| attr.string_value(debug_str));
+    let unit_name = comp_name.and_then(|name| name.to_string().ok()).unwrap_or("<unnamed>");
+    let offset = match root.attr_value(gimli::DW_AT_stmt_list)? {
+        Some(gimli::AttributeValue::DebugLineRef(offset)) => offset,
+        _ => {
+            let warning = format!("the unit {} has no line information", unit_name);
+            return Ok((Sources::default(), Vec::new(), vec![warning]));
+        }
+    };
+    let mut warnings = Vec::new();
+    let program_bytes = line_program_bytes(context.debug_line_section, offset.0);
+    let fragment = cache_dir.and_then(|dir| {
+        let key = line_rows_key(
+            program_bytes?,
+            unit.address_size(),
+            comp_dir.map(|dir| dir.slice()),
+            comp_name.map(|name| name.slice()),
+        );
+        Some((dir, key))
+    });
+    let cached = fragment
+        .as_ref()
+        .and_then(|(dir, key)| cache::load(dir, key))
+        .and_then(|data| LineRows::decode(&data));
+    let rows = match cached {
+        Some(rows) => rows,
+        None => match context.line_program(offset, unit.address_size(), comp_dir, comp_name) {
+            Ok(program) => {
+                let rows = decode_line_program(program, program_bytes, unit.address_size(), comp_dir, comp_name)?;
+                if let Some((dir, key)) = fragment {
+                    if let Err(err) = cache::store(dir, &key, &rows.encode()) {
+                        warnings.push(format!(
+                            "failed to store the line program of the unit {} in the cache: {}",
+                            unit_name, err
+                        ));
                     }
-                    true
+                }
+                rows
+            }
+            Err(_) => {
+                warnings.push(format!("the line program of the unit {} cannot be read", unit_name));
+                LineRows::default()
+            }
+        },
+    };
+    let LineRows {
+        sources,
+        sequences,
+        trailing: mut sequence,
+    } = rows;
+
+    // The sequences are filtered independently, concurrently with the
+    // `parallel` feature; a single unit may hold the whole module.
+    let extents: Vec<(u64, u64)> = sequences
+        .iter()
+        .map(|sequence| {
+            let end = sequence[sequence.len() - 1].address.saturating_add(1);
+            (sequence[0].address, end)
+        })
+        .collect();
+    let sequences = map_sequences(sequences, |sequence| filter_sequence(sequence, bodies));
+    let removed: Vec<String> = extents
+        .iter()
+        .zip(sequences.iter())
+        .filter(|(_, filtered)| filtered.is_empty())
+        .map(|(&(start, end), _)| format!("[0x{:x}, 0x{:x})", start, end))
+        .collect();
+    if !removed.is_empty() {
+        warnings.push(format!(
+            "the unit {} has {} line sequences in removed code: {}",
+            unit_name,
+            removed.len(),
+            removed.join(", ")
+        ));
+    }
+    let len = sequences.iter().map(|sequence| sequence.len()).sum::<usize>() + sequence.len();
+    let mut locations = Vec::with_capacity(len);
+    for mut filtered in sequences {
+        locations.append(&mut filtered);
+    }
+    // Rows past the last end_sequence are kept as they are.
+    locations.append(&mut sequence);
+
+    Ok((sources, locations, warnings))
+}
+
+/// Decodes the rows of a line program. With the `parallel` feature, a large
+/// program is split into parts at sequence boundaries that are decoded
+/// concurrently, since a single unit may hold the whole module.
+#[cfg(feature = "parallel")]
+fn decode_line_program<'a>(
+    program: gimli::IncompleteLineNumberProgram<Slice<'a>>,
+    program_bytes: Option<&[u8]>,
+    address_size: u8,
+    comp_dir: Option<Slice<'a>>,
+    comp_name: Option<Slice<'a>>,
+) -> Result<LineRows, Error> {
+    use rayon::prelude::*;
+
+    let parts = program_bytes
+        .filter(|bytes| bytes.len() >= MIN_SPLIT_LINE_PROGRAM_SIZE)
+        .and_then(|bytes| split_line_program(bytes, rayon::current_num_threads() * 4));
+    let parts = match parts {
+        Some(parts) => parts,
+        None => return read_line_rows(program, comp_dir),
+    };
+    let usage = budget::Attachment::current();
+    let parts = parts
+        .par_iter()
+        .map(|part| {
+            usage.run(|| {
+                let debug_line = DebugLine::new(part, LittleEndian);
+                let program = debug_line.program(DebugLineOffset(0), address_size, comp_dir, comp_name)?;
+                read_line_rows(program, comp_dir)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut rows = LineRows::default();
+    for part in parts {
+        let ids = rows.sources.merge(part.sources);
+        let remap = |mut sequence: Vec<LocationRecord>| {
+            for loc in sequence.iter_mut() {
+                loc.source_id = ids[loc.source_id as usize] as u32;
+            }
+            sequence
+        };
+        rows.sequences.extend(part.sequences.into_iter().map(remap));
+        // Only the last part has rows past its last end_sequence.
+        rows.trailing = remap(part.trailing);
+    }
+    Ok(rows)
+}
+
+#[cfg(not(feature = "parallel"))]
+fn decode_line_program<'a>(
+    program: gimli::IncompleteLineNumberProgram<Slice<'a>>,
+    _program_bytes: Option<&[u8]>,
+    _address_size: u8,
+    comp_dir: Option<Slice<'a>>,
+    _comp_name: Option<Slice<'a>>,
+) -> Result<LineRows, Error> {
+    read_line_rows(program, comp_dir)
+}
+
+/// The size from which a line program is decoded in parts; the header is
+/// parsed again for each of them.
+#[cfg(feature = "parallel")]
+const MIN_SPLIT_LINE_PROGRAM_SIZE: usize = 64 * 1024;
+
+/// Decodes the rows of a line program into its sequences.
+fn read_line_rows<'a>(
+    program: gimli::IncompleteLineNumberProgram<Slice<'a>>,
+    comp_dir: Option<Slice<'a>>,
+) -> Result<LineRows, Error> {
+    let mut rows = LineRows::default();
+    let mut sequence: Vec<LocationRecord> = Vec::new();
+    let mut source_to_id_map: HashMap<u64, usize> = HashMap::new();
+    let mut program_rows = program.rows();
+    while let Some((header, row)) = program_rows.next_row()? {
+        let pc = row.address();
+        let line = row.line().unwrap_or(0);
+        let column = match row.column() {
+            gimli::ColumnType::Column(column) => column,
+            gimli::ColumnType::LeftEdge => 0,
+        };
+        let file_index = row.file_index();
+        let source_id = if let Some(&id) = source_to_id_map.get(&file_index) {
+            id
+        } else {
+            let mut file_path: String = if let Some(file) = row.file(header) {
+                if let Some(directory) = file.directory(header) {
+                    format!(
+                        "{}/{}",
+                        directory.to_string_lossy(),
+                        file.path_name().to_string_lossy()
+                    )
                 } else {
-                    locations.push(loc);
-                    false
-                };
-                if end_sequence {
-                    // Heuristic to remove dead functions.
-                    let block_end_loc = locations.len() - 1;
-                    let fn_size =
-                        locations[block_end_loc].address - locations[block_start_loc].address + 1;
-                    let fn_size_field_len =
-                        ((fn_size + 1).next_power_of_two().trailing_zeros() + 6) / 7;
-                    // Remove function if it starts at its size field location.
-                    if locations[block_start_loc].address <= u64::from(fn_size_field_len) {
-                        locations.drain(block_start_loc..);
-                    }
-                    block_start_loc = locations.len();
+                    String::from(file.path_name().to_string_lossy())
                 }
+            } else {
+                String::from("<unknown>")
+            };
+            if !file_path.starts_with('/') && comp_dir.is_some() {
+                file_path = format!("{}/{}", comp_dir.unwrap().to_string_lossy(), file_path);
             }
+            let id = rows.sources.id(&file_path);
+            source_to_id_map.insert(file_index, id);
+            id
+        };
+        let mut loc = LocationRecord {
+            address: pc,
+            source_id: source_id as u32,
+            line: line as u32,
+            column: column as u32,
+        };
+        if row.end_sequence() {
+            // end_sequence falls on the byte after function's end --
+            // moving address one step back.
+            loc.address = loc.address.saturating_sub(1);
+            // Compacting duplicate records.
+            if sequence.last().map_or(true, |last| last.address < loc.address) {
+                sequence.push(loc);
+            }
+            rows.sequences.push(mem::replace(&mut sequence, Vec::new()));
+        } else {
+            sequence.push(loc);
         }
+    }
+    rows.trailing = sequence;
+    Ok(rows)
+}
 
-        // new unit, new sources
-        source_to_id_map.clear();
+/// Removes the sequence if it is not in a function body, and the rows the
+/// linker left past the body end.
+fn filter_sequence(mut sequence: Vec<LocationRecord>, bodies: Option<&FunctionBodies>) -> Vec<LocationRecord> {
+    let block_start = sequence[0].address;
+    if let Some(bodies) = bodies {
+        match bodies.find(block_start) {
+            Some(i) => {
+                let end = bodies.extents[i].end;
+                let len = sequence
+                    .iter()
+                    .position(|loc| loc.address >= end)
+                    .unwrap_or(sequence.len());
+                sequence.truncate(len);
+            }
+            None => sequence.clear(),
+        }
+    } else {
+        // Heuristic to remove dead functions.
+        // The addresses of malformed sequences may decrease.
+        let fn_size = sequence[sequence.len() - 1]
+            .address
+            .saturating_sub(block_start)
+            .saturating_add(1);
+        let fn_size_field_len = (leb128_bits(fn_size) + 6) / 7;
+        // Remove function if it starts at its size field location.
+        if block_start <= u64::from(fn_size_field_len) {
+            sequence.clear();
+        }
     }
+    sequence
+}
 
-    locations.sort_by(|a, b| a.address.cmp(&b.address));
+#[cfg(feature = "parallel")]
+fn map_sequences<F>(sequences: Vec<Vec<LocationRecord>>, filter: F) -> Vec<Vec<LocationRecord>>
+where
+    F: Fn(Vec<LocationRecord>) -> Vec<LocationRecord> + Sync,
+{
+    use rayon::prelude::*;
+
+    let usage = budget::Attachment::current();
+    sequences.into_par_iter().map(|sequence| usage.run(|| filter(sequence))).collect()
+}
 
-    Ok(LocationInfo { sources, locations })
+#[cfg(not(feature = "parallel"))]
+fn map_sequences<F>(sequences: Vec<Vec<LocationRecord>>, filter: F) -> Vec<Vec<LocationRecord>>
+where
+    F: Fn(Vec<LocationRecord>) -> Vec<LocationRecord> + Sync,
+{
+    sequences.into_iter().map(filter).collect()
 }
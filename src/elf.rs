@@ -0,0 +1,177 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Extraction of the `.debug_*` sections from the ELF containers some
+// toolchains wrap wasm32 code in. Only little-endian files are supported,
+// like for the DWARF itself.
+
+use std::collections::HashMap;
+use std::str;
+
+use crate::dwarf::canonical_section_name;
+use crate::wasm::{Context, WasmFormatError, WasmFormatErrorKind};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const SHT_NOBITS: u32 = 8;
+const SHF_COMPRESSED: u64 = 0x800;
+
+pub fn is_elf(input: &[u8]) -> bool {
+    input.starts_with(ELF_MAGIC)
+}
+
+fn invalid(offset: usize) -> WasmFormatError {
+    WasmFormatError::new(WasmFormatErrorKind::InvalidValue, offset)
+}
+
+fn read_u64(input: &[u8], offset: usize, size: usize) -> Result<u64, WasmFormatError> {
+    let bytes = input
+        .get(offset..offset + size)
+        .ok_or_else(|| WasmFormatError::new(WasmFormatErrorKind::UnexpectedEof, offset))?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte)))
+}
+
+fn to_usize(value: u64, offset: usize) -> Result<usize, WasmFormatError> {
+    if value > usize::max_value() as u64 {
+        return Err(invalid(offset));
+    }
+    Ok(value as usize)
+}
+
+fn read_usize(input: &[u8], offset: usize, size: usize) -> Result<usize, WasmFormatError> {
+    to_usize(read_u64(input, offset, size)?, offset)
+}
+
+fn get_range(input: &[u8], offset: usize, size: usize) -> Result<&[u8], WasmFormatError> {
+    offset
+        .checked_add(size)
+        .and_then(|end| input.get(offset..end))
+        .ok_or_else(|| WasmFormatError::new(WasmFormatErrorKind::UnexpectedEof, offset))
+}
+
+struct SectionHeader {
+    name: usize,
+    kind: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+}
+
+/// Field offsets and sizes of the ELF32 and ELF64 layouts.
+struct Layout {
+    shoff: (usize, usize),
+    shentsize: usize,
+    shnum: usize,
+    shstrndx: usize,
+    sh_flags: (usize, usize),
+    sh_offset: (usize, usize),
+    sh_size: (usize, usize),
+}
+
+const ELF32_LAYOUT: Layout = Layout {
+    shoff: (0x20, 4),
+    shentsize: 0x2E,
+    shnum: 0x30,
+    shstrndx: 0x32,
+    sh_flags: (8, 4),
+    sh_offset: (16, 4),
+    sh_size: (20, 4),
+};
+
+const ELF64_LAYOUT: Layout = Layout {
+    shoff: (0x28, 8),
+    shentsize: 0x3A,
+    shnum: 0x3C,
+    shstrndx: 0x3E,
+    sh_flags: (8, 8),
+    sh_offset: (24, 8),
+    sh_size: (32, 8),
+};
+
+fn read_section_headers(input: &[u8]) -> Result<(Vec<SectionHeader>, usize), WasmFormatError> {
+    let layout = match input.get(4) {
+        Some(&ELFCLASS32) => &ELF32_LAYOUT,
+        Some(&ELFCLASS64) => &ELF64_LAYOUT,
+        _ => return Err(invalid(4)),
+    };
+    if input.get(5) != Some(&ELFDATA2LSB) {
+        return Err(invalid(5));
+    }
+    let shoff = read_usize(input, layout.shoff.0, layout.shoff.1)?;
+    let shentsize = read_usize(input, layout.shentsize, 2)?;
+    let shnum = read_usize(input, layout.shnum, 2)?;
+    let shstrndx = read_usize(input, layout.shstrndx, 2)?;
+    let mut headers = Vec::with_capacity(shnum);
+    for i in 0..shnum {
+        let base = shoff
+            .checked_add(i * shentsize)
+            .ok_or_else(|| invalid(layout.shoff.0))?;
+        let entry = get_range(input, base, shentsize)?;
+        let field = |(offset, size): (usize, usize)| {
+            read_u64(entry, offset, size).map_err(|_| invalid(base + offset))
+        };
+        let offset = field(layout.sh_offset)?;
+        let size = field(layout.sh_size)?;
+        headers.push(SectionHeader {
+            name: field((0, 4))? as usize,
+            kind: field((4, 4))? as u32,
+            flags: field(layout.sh_flags)?,
+            offset: to_usize(offset, base + layout.sh_offset.0)?,
+            size: to_usize(size, base + layout.sh_size.0)?,
+        });
+    }
+    Ok((headers, shstrndx))
+}
+
+fn section_name(strtab: &[u8], offset: usize) -> Result<&str, WasmFormatError> {
+    let tail = strtab.get(offset..).ok_or_else(|| invalid(offset))?;
+    let len = tail.iter().position(|&b| b == 0).ok_or_else(|| invalid(offset))?;
+    str::from_utf8(&tail[..len])
+        .map_err(|_| WasmFormatError::new(WasmFormatErrorKind::InvalidUtf8, offset))
+}
+
+/// Returns the `.debug_*` sections of the ELF file by name. Compressed
+/// sections are skipped and reported through `warn`.
+pub fn read_debug_sections<'a>(
+    input: &'a [u8],
+    warn: &dyn Fn(&str),
+) -> Result<HashMap<&'a str, &'a [u8]>, WasmFormatError> {
+    let (headers, shstrndx) = read_section_headers(input).context("ELF header")?;
+    let strtab = match headers.get(shstrndx) {
+        Some(header) => get_range(input, header.offset, header.size).context("ELF section names")?,
+        None => return Err(invalid(0)).context("ELF section names"),
+    };
+    let mut sections = HashMap::new();
+    for header in headers.iter() {
+        let name = section_name(strtab, header.name).context("ELF section names")?;
+        let name = canonical_section_name(name).unwrap_or(name);
+        if !name.starts_with(".debug_") || header.kind == SHT_NOBITS {
+            continue;
+        }
+        if (header.flags & SHF_COMPRESSED) != 0 {
+            warn(&format!("the compressed ELF section {} is not supported", name));
+            continue;
+        }
+        let data = get_range(input, header.offset, header.size).context("ELF section")?;
+        sections.insert(name, data);
+    }
+    Ok(sections)
+}
@@ -0,0 +1,144 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Decoding of DWARF location expressions into the textual form of
+// llvm-dwarfdump, e.g. `DW_OP_WASM_location 0x0 0x2, DW_OP_stack_value`.
+
+use crate::wasm::{Result, WasmDecoder};
+
+/// The name of an operation without operands, or with operands handled by
+/// `decode_operation`.
+fn operation_name(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        0x03 => "DW_OP_addr",
+        0x06 => "DW_OP_deref",
+        0x08 => "DW_OP_const1u",
+        0x09 => "DW_OP_const1s",
+        0x0a => "DW_OP_const2u",
+        0x0b => "DW_OP_const2s",
+        0x0c => "DW_OP_const4u",
+        0x0d => "DW_OP_const4s",
+        0x0e => "DW_OP_const8u",
+        0x0f => "DW_OP_const8s",
+        0x10 => "DW_OP_constu",
+        0x11 => "DW_OP_consts",
+        0x12 => "DW_OP_dup",
+        0x13 => "DW_OP_drop",
+        0x14 => "DW_OP_over",
+        0x15 => "DW_OP_pick",
+        0x16 => "DW_OP_swap",
+        0x17 => "DW_OP_rot",
+        0x19 => "DW_OP_abs",
+        0x1a => "DW_OP_and",
+        0x1b => "DW_OP_div",
+        0x1c => "DW_OP_minus",
+        0x1d => "DW_OP_mod",
+        0x1e => "DW_OP_mul",
+        0x1f => "DW_OP_neg",
+        0x20 => "DW_OP_not",
+        0x21 => "DW_OP_or",
+        0x22 => "DW_OP_plus",
+        0x23 => "DW_OP_plus_uconst",
+        0x24 => "DW_OP_shl",
+        0x25 => "DW_OP_shr",
+        0x26 => "DW_OP_shra",
+        0x27 => "DW_OP_xor",
+        0x28 => "DW_OP_bra",
+        0x29 => "DW_OP_eq",
+        0x2a => "DW_OP_ge",
+        0x2b => "DW_OP_gt",
+        0x2c => "DW_OP_le",
+        0x2d => "DW_OP_lt",
+        0x2e => "DW_OP_ne",
+        0x2f => "DW_OP_skip",
+        0x90 => "DW_OP_regx",
+        0x91 => "DW_OP_fbreg",
+        0x92 => "DW_OP_bregx",
+        0x93 => "DW_OP_piece",
+        0x94 => "DW_OP_deref_size",
+        0x96 => "DW_OP_nop",
+        0x9c => "DW_OP_call_frame_cfa",
+        0x9d => "DW_OP_bit_piece",
+        0x9e => "DW_OP_implicit_value",
+        0x9f => "DW_OP_stack_value",
+        0xed => "DW_OP_WASM_location",
+        _ => return None,
+    })
+}
+
+fn fixed(decoder: &mut WasmDecoder, size: usize) -> Result<u64> {
+    let bytes = decoder.skip(size)?;
+    Ok(bytes.iter().rev().fold(0, |value, &byte| value << 8 | u64::from(byte)))
+}
+
+fn decode_operation(decoder: &mut WasmDecoder) -> Result<Option<String>> {
+    let opcode = decoder.u8()?;
+    let name = match opcode {
+        0x30..=0x4f => return Ok(Some(format!("DW_OP_lit{}", opcode - 0x30))),
+        0x50..=0x6f => return Ok(Some(format!("DW_OP_reg{}", opcode - 0x50))),
+        0x70..=0x8f => return Ok(Some(format!("DW_OP_breg{} {:+}", opcode - 0x70, decoder.i64()?))),
+        _ => match operation_name(opcode) {
+            Some(name) => name,
+            None => return Ok(None),
+        },
+    };
+    let operands = match opcode {
+        // The addresses are 32-bit in wasm32 modules.
+        0x03 | 0x0c => vec![format!("0x{:x}", fixed(decoder, 4)?)],
+        0x08 | 0x94 => vec![format!("0x{:x}", fixed(decoder, 1)?)],
+        0x0a => vec![format!("0x{:x}", fixed(decoder, 2)?)],
+        0x0e => vec![format!("0x{:x}", fixed(decoder, 8)?)],
+        0x09 => vec![format!("{}", fixed(decoder, 1)? as i8)],
+        0x0b | 0x28 | 0x2f => vec![format!("{}", fixed(decoder, 2)? as i16)],
+        0x0d => vec![format!("{}", fixed(decoder, 4)? as i32)],
+        0x0f => vec![format!("{}", fixed(decoder, 8)? as i64)],
+        0x10 | 0x23 | 0x90 | 0x93 => vec![format!("0x{:x}", decoder.u64()?)],
+        0x11 | 0x91 => vec![format!("{:+}", decoder.i64()?)],
+        0x15 => vec![format!("0x{:x}", fixed(decoder, 1)?)],
+        0x92 => vec![format!("0x{:x}", decoder.u64()?), format!("{:+}", decoder.i64()?)],
+        0x9d => vec![format!("0x{:x}", decoder.u64()?), format!("0x{:x}", decoder.u64()?)],
+        0x9e => {
+            let len = decoder.u64()? as usize;
+            let value = decoder.skip(len)?;
+            let hex: Vec<String> = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+            vec![format!("0x{:x}", len), format!("0x{}", hex.concat())]
+        }
+        0xed => {
+            // The kind is a local, a global or an operand stack slot; the
+            // index of the 32-bit global kind is fixed-size.
+            let kind = decoder.u8()?;
+            let index = if kind == 3 { fixed(decoder, 4)? } else { decoder.u64()? };
+            vec![format!("0x{:x}", kind), format!("0x{:x}", index)]
+        }
+        _ => Vec::new(),
+    };
+    let mut operation = name.to_string();
+    for operand in operands {
+        operation.push(' ');
+        operation.push_str(&operand);
+    }
+    Ok(Some(operation))
+}
+
+/// Decodes the operations of a location expression. It returns `None` when
+/// the expression is malformed or has an operation that is not supported.
+pub fn decode_expression(expr: &[u8]) -> Option<Vec<String>> {
+    let mut decoder = WasmDecoder::new(expr);
+    let mut operations = Vec::new();
+    while !decoder.eof() {
+        operations.push(decode_operation(&mut decoder).ok()??);
+    }
+    Some(operations)
+}
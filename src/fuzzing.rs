@@ -0,0 +1,99 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The entry points of the fuzz targets in `fuzz/`, which reach the parsers
+// private to the crate. The converter runs on untrusted modules inside
+// devtools, so any input must give a result or an error, never a panic.
+
+use crate::convert;
+use crate::dwarf;
+use crate::to_json::{OutputFormat, ScopesWriter};
+use crate::types::TypeTable;
+use std::collections::HashMap;
+
+/// The DWARF sections of the fuzz inputs, in their order there.
+const SECTION_NAMES: &[&str] = &[
+    ".debug_info",
+    ".debug_abbrev",
+    ".debug_str",
+    ".debug_line",
+    ".debug_ranges",
+    ".debug_rnglists",
+    ".debug_loc",
+    ".debug_loclists",
+];
+
+/// Splits a fuzz input into DWARF sections: each of them is preceded by its
+/// length as a little-endian `u16`, and the last one present gets the bytes
+/// left when its length is past the end.
+pub fn split_sections(data: &[u8]) -> HashMap<&'static str, &[u8]> {
+    let mut sections = HashMap::new();
+    let mut rest = data;
+    for name in SECTION_NAMES {
+        if rest.len() < 2 {
+            break;
+        }
+        let len = usize::from(u16::from(rest[0]) | u16::from(rest[1]) << 8);
+        let (section, tail) = rest[2..].split_at(len.min(rest.len() - 2));
+        sections.insert(*name, section);
+        rest = tail;
+    }
+    sections
+}
+
+/// Reads the sections of a wasm module.
+pub fn read_debug_sections(input: &[u8]) {
+    let _ = convert::read_debug_sections(input);
+}
+
+/// Reads the line programs of the units.
+pub fn get_debug_loc(sections: &HashMap<&str, &[u8]>) {
+    if let Ok(context) = dwarf::DwarfContext::new(sections) {
+        let _ = dwarf::get_debug_loc(&context, None, None, &mut || Ok(()));
+    }
+}
+
+/// Reads the entries of the units, and summarizes and serializes them as
+/// for `x-scopes` with all the tables.
+pub fn read_debug_scopes(sections: &HashMap<&str, &[u8]>) {
+    let mut sources = Vec::new();
+    let mut warnings = Vec::new();
+    let mut types = TypeTable::new(true);
+    let mut writer = ScopesWriter::new(OutputFormat::Compact);
+    let context = match dwarf::DwarfContext::new(sections) {
+        Ok(context) => context,
+        Err(_) => return,
+    };
+    let _ = dwarf::read_debug_scopes(
+        &context,
+        &mut sources,
+        &mut warnings,
+        None,
+        false,
+        None,
+        None,
+        &mut || Ok(()),
+        &mut |mut items| {
+            types.add_unit(&mut items);
+            dwarf::add_locations(&mut items, 0);
+            dwarf::add_parameters(&mut items, 0);
+            dwarf::add_template_parameters(&mut items);
+            writer.write(&items);
+            Ok(())
+        },
+    );
+    let _ = types.to_json(true);
+    let _ = writer.finish();
+}
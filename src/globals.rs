@@ -0,0 +1,103 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The table of the variables with a fixed address in the linear memory,
+// i.e. the globals and the static locals, whose `location` is a single
+// `DW_OP_addr`, so that debuggers can show the global state without
+// walking the tree.
+
+use crate::dwarf::{AttrKey, DebugAttrValue, DebugInfoObj};
+use crate::types::TypeTable;
+use serde_json::{Map, Value};
+
+/// The attributes the table is built from, which are read even when they
+/// are not among the requested ones.
+pub const GLOBAL_ATTRS: &[&str] = &["name", "linkage_name", "location"];
+
+/// Decodes an expression that is a single `DW_OP_addr`, with the 4 or 8
+/// byte address of wasm32 or wasm64.
+fn fixed_address(expr: &[u8]) -> Option<u64> {
+    if expr.first() != Some(&0x03) || (expr.len() != 5 && expr.len() != 9) {
+        return None;
+    }
+    Some(expr[1..].iter().rev().fold(0, |address, &byte| address << 8 | u64::from(byte)))
+}
+
+fn string_attr<'a>(item: &DebugInfoObj<'a>, name: gimli::DwAt) -> Option<&'a str> {
+    match item.attrs.get(&AttrKey::Dwarf(name)) {
+        Some(DebugAttrValue::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+pub struct GlobalTable {
+    /// The globals with their address.
+    globals: Vec<(u64, Value)>,
+}
+
+impl GlobalTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds the variables of a unit that have a fixed address. The `types`
+    /// must already have the unit, for the `type_id` of the entries.
+    pub fn add_unit(&mut self, items: &[DebugInfoObj], types: &TypeTable) {
+        // The entries with the name of their enclosing function.
+        let mut pending: Vec<(&DebugInfoObj, Option<&str>)> =
+            items.iter().map(|item| (item, None)).collect();
+        while let Some((item, function)) = pending.pop() {
+            let address = match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_location)) {
+                Some(&DebugAttrValue::Expression(expr)) if item.tag == "variable" => {
+                    fixed_address(expr)
+                }
+                _ => None,
+            };
+            if let Some(address) = address {
+                let mut fields = Map::new();
+                fields.insert("address".to_string(), json!(address));
+                fields.insert("name".to_string(), json!(string_attr(item, gimli::DW_AT_name)));
+                if let Some(linkage_name) = string_attr(item, gimli::DW_AT_linkage_name) {
+                    fields.insert("linkage_name".to_string(), json!(linkage_name));
+                }
+                if let Some(function) = function {
+                    fields.insert("function".to_string(), json!(function));
+                }
+                if let Some(&DebugAttrValue::I64(id)) = item.attrs.get(&AttrKey::TypeId) {
+                    let id = id as usize;
+                    fields.insert("type".to_string(), json!(types.display_name(id)));
+                    fields.insert("type_id".to_string(), json!(id));
+                    if let Some(size) = types.type_size(id) {
+                        fields.insert("byte_size".to_string(), json!(size));
+                    }
+                }
+                self.globals.push((address, Value::Object(fields)));
+            }
+            let function = if item.tag == "subprogram" {
+                string_attr(item, gimli::DW_AT_name)
+            } else {
+                function
+            };
+            pending.extend(item.children.iter().map(|child| (child, function)));
+        }
+    }
+
+    /// Returns the `globals` array of `x-scopes`, by address.
+    pub fn to_json(mut self) -> Value {
+        self.globals.sort_by_key(|&(address, _)| address);
+        Value::Array(self.globals.into_iter().map(|(_, global)| global).collect())
+    }
+}
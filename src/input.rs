@@ -0,0 +1,121 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Decoding of the inputs that are not plain wasm binaries: modules kept
+// compressed by artifact stores and the text format. The decoders are
+// behind the `gzip`, `brotli` and `wat` features.
+
+use std::borrow::Cow;
+
+use crate::convert::Error;
+#[cfg(feature = "gzip")]
+use crate::wasm::{Context, WasmFormatError, WasmFormatErrorKind};
+
+const WASM_MAGIC: &[u8] = b"\x00asm";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// The largest decompressed input, so that a small gzip or brotli stream
+/// cannot expand without bound.
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 30;
+
+/// Reads the whole decompressed stream, or returns `None` when it is larger
+/// than `MAX_DECOMPRESSED_SIZE`.
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+fn read_limited<R: std::io::Read>(reader: R) -> std::io::Result<Option<Vec<u8>>> {
+    use std::io::Read;
+    let mut output = Vec::new();
+    reader
+        .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+        .read_to_end(&mut output)?;
+    if output.len() > MAX_DECOMPRESSED_SIZE {
+        return Ok(None);
+    }
+    Ok(Some(output))
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip(input: &[u8]) -> Result<Vec<u8>, Error> {
+    match read_limited(flate2::read::GzDecoder::new(input)) {
+        Ok(Some(output)) => Ok(output),
+        Ok(None) => Err(Error::DecompressedSizeExceeded(MAX_DECOMPRESSED_SIZE)),
+        Err(_) => Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, 0))
+            .context("gzip stream")
+            .map_err(Error::from),
+    }
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip(_input: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::UnsupportedCompression)
+}
+
+/// Returns the decompressed input, or `None` when it is not a brotli
+/// stream.
+#[cfg(feature = "brotli")]
+fn unbrotli(input: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    match read_limited(brotli_decompressor::Decompressor::new(input, 4096)) {
+        Ok(Some(output)) => Ok(Some(output)),
+        Ok(None) => Err(Error::DecompressedSizeExceeded(MAX_DECOMPRESSED_SIZE)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "brotli"))]
+fn unbrotli(_input: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    Ok(None)
+}
+
+/// Text format modules start with a comment or an s-expression.
+fn is_text(input: &[u8]) -> bool {
+    match input.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'(') | Some(b';') => true,
+        _ => false,
+    }
+}
+
+#[cfg(feature = "wat")]
+fn assemble(input: &[u8]) -> Result<Vec<u8>, Error> {
+    match wat::parse_bytes(input) {
+        Ok(binary) => Ok(binary.into_owned()),
+        Err(err) => Err(Error::TextFormat(err.to_string())),
+    }
+}
+
+#[cfg(not(feature = "wat"))]
+fn assemble(_input: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::TextFormat("the text format requires the `wat` feature".to_string()))
+}
+
+/// Returns the wasm binary, decompressing gzip or brotli input or
+/// assembling text format. Brotli streams have no magic, so anything that
+/// is neither wasm, gzip nor text is tried as brotli and accepted if it
+/// decompresses to a wasm binary. Decompressed input larger than
+/// `MAX_DECOMPRESSED_SIZE` is rejected.
+pub fn decode_input(input: &[u8]) -> Result<Cow<[u8]>, Error> {
+    if input.starts_with(WASM_MAGIC) {
+        return Ok(Cow::Borrowed(input));
+    }
+    if input.starts_with(GZIP_MAGIC) {
+        return Ok(Cow::Owned(gunzip(input)?));
+    }
+    if is_text(input) {
+        return Ok(Cow::Owned(assemble(input)?));
+    }
+    match unbrotli(input)? {
+        Some(output) if output.starts_with(WASM_MAGIC) => Ok(Cow::Owned(output)),
+        _ => Ok(Cow::Borrowed(input)),
+    }
+}
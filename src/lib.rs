@@ -13,22 +13,219 @@
  * limitations under the License.
  */
 
+use std::ffi::c_void;
 use std::mem;
+use std::panic;
+use std::ptr;
 use std::slice;
 use std::ptr::{read_unaligned, write_unaligned};
 
-use crate::convert::convert;
+use std::cmp;
+use std::collections::HashMap;
+use std::str;
+
+use crate::convert::{convert, convert_sections, read_build_id, ConvertOptions, Error};
+use crate::dwarf::canonical_section_name;
+use crate::to_json::OutputFormat;
 
 extern crate gimli;
 #[macro_use]
 extern crate serde_json;
 extern crate vlq;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "brotli")]
+extern crate brotli_decompressor;
+#[cfg(feature = "wat")]
+extern crate wat;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 
-mod convert;
+pub mod budget;
+mod cache;
+pub mod convert;
 mod dwarf;
+mod elf;
+mod expr;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod globals;
+mod input;
+pub mod lookup;
+mod reloc;
+mod sha256;
+pub mod sidecar;
+mod split;
 mod to_json;
+mod types;
+mod verify;
 mod wasm;
 
+// The programs linking the library keep their own allocator; the command
+// line tool installs the counting one itself.
+#[cfg(feature = "counting-allocator")]
+#[global_allocator]
+static ALLOCATOR: budget::CountingAllocator = budget::CountingAllocator;
+
+/// Version of the C ABI. Bumped whenever an exported signature or a
+/// `#[repr(C)]` struct changes incompatibly.
+pub const DWARF_TO_JSON_ABI_VERSION: u32 = 7;
+
+/// Status codes returned by the C ABI entry points.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DwarfToJsonStatus {
+    Ok = 0,
+    /// The input is not a valid WebAssembly binary.
+    WasmError = 1,
+    /// The DWARF sections are missing or malformed.
+    DataFormat = 2,
+    /// The DWARF parser rejected the input.
+    GimliError = 3,
+    /// The JSON serialization failed.
+    OutputError = 4,
+    /// A NULL pointer or unknown flag was passed, or a `memory_budget` that
+    /// this build cannot measure.
+    InvalidArgument = 5,
+    /// The converter panicked; the input most likely triggered a bug.
+    Panic = 6,
+    /// The host requested cancellation via `should_cancel`.
+    Cancelled = 7,
+    /// The peak heap usage exceeded `memory_budget`.
+    MemoryBudgetExceeded = 8,
+    /// The module keeps its DWARF in a separate file named by the
+    /// `external_debug_info` section. The output holds that URL.
+    ExternalDebugInfo = 9,
+    /// The input is gzip compressed and this build lacks the `gzip`
+    /// feature. Brotli streams have no magic number to detect them by, so
+    /// without the `brotli` feature they fail with `WasmError` instead.
+    UnsupportedCompression = 10,
+    /// The compressed input decompresses to more than 1 GiB.
+    DecompressedSizeExceeded = 11,
+}
+
+/// Emit the `x-scopes` field with the DWARF debug information tree.
+pub const DWARF_TO_JSON_X_SCOPES: u32 = 1;
+
+/// Emit the `x-function-offsets` field with the function body extents.
+pub const DWARF_TO_JSON_FUNCTION_OFFSETS: u32 = 2;
+
+/// Emit the `x-column-spans` field with the end column of each mapping.
+pub const DWARF_TO_JSON_COLUMN_SPANS: u32 = 4;
+
+/// Emit the `types` table in `x-scopes`, referenced by the `type_id` of the
+/// entries.
+pub const DWARF_TO_JSON_TYPES: u32 = 8;
+
+/// Emit the `globals` table in `x-scopes`, with the linear memory address of
+/// the global and static variables, and the `types` table.
+pub const DWARF_TO_JSON_GLOBALS: u32 = 16;
+
+/// Make the typedefs of the `types` table refer to the type at the end of
+/// their chain of typedefs.
+pub const DWARF_TO_JSON_COLLAPSE_ALIASES: u32 = 32;
+
+/// Qualify the names of the `types` table with the enclosing namespaces and
+/// types, with the separator of their language.
+pub const DWARF_TO_JSON_QUALIFIED_NAMES: u32 = 64;
+
+/// Check that the mappings are increasing and within the code section, and
+/// that the `x-scopes` ranges nest, reporting the violations as warnings.
+pub const DWARF_TO_JSON_VERIFY: u32 = 128;
+
+/// Keep the functions in code removed by the linker in `x-scopes`, marked as
+/// `dead`.
+pub const DWARF_TO_JSON_KEEP_DEAD_FUNCTIONS: u32 = 256;
+
+const DWARF_TO_JSON_KNOWN_FLAGS: u32 = DWARF_TO_JSON_X_SCOPES
+    | DWARF_TO_JSON_FUNCTION_OFFSETS
+    | DWARF_TO_JSON_COLUMN_SPANS
+    | DWARF_TO_JSON_TYPES
+    | DWARF_TO_JSON_GLOBALS
+    | DWARF_TO_JSON_COLLAPSE_ALIASES
+    | DWARF_TO_JSON_QUALIFIED_NAMES
+    | DWARF_TO_JSON_VERIFY
+    | DWARF_TO_JSON_KEEP_DEAD_FUNCTIONS;
+
+/// Indented JSON.
+pub const DWARF_TO_JSON_FORMAT_PRETTY: u32 = 0;
+/// JSON without insignificant whitespace.
+pub const DWARF_TO_JSON_FORMAT_COMPACT: u32 = 1;
+
+/// Conversion options. All fields are fixed-size; zero-initialized options
+/// produce the plain source map.
+#[repr(C)]
+pub struct DwarfToJsonOptions {
+    /// Bitwise OR of the `DWARF_TO_JSON_*` flags.
+    pub flags: u32,
+    /// One of the `DWARF_TO_JSON_FORMAT_*` values.
+    pub format: u32,
+    /// Optional; called between compilation units with the completed
+    /// percentage (0-100).
+    pub progress: Option<unsafe extern "C" fn(user_data: *mut c_void, percent: u32)>,
+    /// Optional; polled between compilation units. A non-zero result stops
+    /// the conversion with `DwarfToJsonStatus::Cancelled`.
+    pub should_cancel: Option<unsafe extern "C" fn(user_data: *mut c_void) -> u32>,
+    /// Passed back unchanged to the callbacks.
+    pub user_data: *mut c_void,
+    /// Peak heap usage in bytes after which the conversion stops with
+    /// `DwarfToJsonStatus::MemoryBudgetExceeded`; 0 means unlimited. It is
+    /// checked between compilation units, so the actual peak may overshoot
+    /// by the memory needed for one unit. The usage is only measured when
+    /// the library is built with the `counting-allocator` feature;
+    /// otherwise a non-zero budget is rejected with
+    /// `DwarfToJsonStatus::InvalidArgument`.
+    pub memory_budget: u64,
+}
+
+/// Conversion result. On success `data` points to `len` bytes of UTF-8 JSON
+/// (not NUL terminated) that are owned by the caller and must be released
+/// with `free_mem`. With `DwarfToJsonStatus::ExternalDebugInfo` it holds the
+/// companion file URL instead. On other failures `data` is NULL and `len`
+/// is 0.
+#[repr(C)]
+pub struct DwarfToJsonOutput {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// A debug section provided by the host, e.g. `.debug_info`. Both the name
+/// and the data are borrowed for the duration of the conversion call.
+#[repr(C)]
+pub struct DwarfToJsonSection {
+    /// UTF-8 section name, not NUL terminated.
+    pub name: *const u8,
+    pub name_len: usize,
+    pub data: *const u8,
+    pub data_len: usize,
+}
+
+impl<'a> From<&'a Error> for DwarfToJsonStatus {
+    fn from(err: &'a Error) -> Self {
+        match *err {
+            Error::GimliError(_) => DwarfToJsonStatus::GimliError,
+            Error::DataFormat => DwarfToJsonStatus::DataFormat,
+            Error::WasmError(_) | Error::TextFormat(_) => DwarfToJsonStatus::WasmError,
+            Error::OutputError => DwarfToJsonStatus::OutputError,
+            Error::Cancelled => DwarfToJsonStatus::Cancelled,
+            Error::MemoryBudgetExceeded => DwarfToJsonStatus::MemoryBudgetExceeded,
+            Error::ExternalDebugInfo(_) => DwarfToJsonStatus::ExternalDebugInfo,
+            Error::UnsupportedCompression => DwarfToJsonStatus::UnsupportedCompression,
+            Error::DecompressedSizeExceeded(_) => DwarfToJsonStatus::DecompressedSizeExceeded,
+        }
+    }
+}
+
+/// Returns `DWARF_TO_JSON_ABI_VERSION`, so hosts can check compatibility
+/// before calling any other entry point.
+#[no_mangle]
+pub extern "C" fn abi_version() -> u32 {
+    DWARF_TO_JSON_ABI_VERSION
+}
+
+/// Allocates `size` bytes in the converter's memory. The block must be
+/// released with `free_mem`.
 #[no_mangle]
 pub extern "C" fn alloc_mem(size: usize) -> *mut u8 {
     let mut m = Vec::with_capacity(mem::size_of::<usize>() + size);
@@ -41,6 +238,7 @@ pub extern "C" fn alloc_mem(size: usize) -> *mut u8 {
     }
 }
 
+/// Releases a block returned by `alloc_mem` or by a conversion entry point.
 #[no_mangle]
 pub unsafe extern "C" fn free_mem(p: *mut u8) {
     let v = p.offset(-(mem::size_of::<usize>() as isize));
@@ -49,6 +247,23 @@ pub unsafe extern "C" fn free_mem(p: *mut u8) {
     Vec::from_raw_parts(v, 0, size);
 }
 
+/// Runs `f`, translating a panic into `DwarfToJsonStatus::Panic` instead of
+/// unwinding across the `extern "C"` boundary. Builds with `panic=abort`
+/// (e.g. wasm32-unknown-unknown) still abort.
+fn catch_panic<F: FnOnce() -> DwarfToJsonStatus>(f: F) -> DwarfToJsonStatus {
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_or(DwarfToJsonStatus::Panic)
+}
+
+unsafe fn copy_to_output(json: &[u8], output: *mut *const u8, output_len: *mut usize) {
+    *output = alloc_mem(json.len()) as *const u8;
+    *output_len = json.len();
+    slice::from_raw_parts_mut(*output as *mut u8, *output_len)
+        .clone_from_slice(json);
+}
+
+/// Legacy entry point used by the JavaScript wrappers: converts the `wasm`
+/// module and stores the JSON location and length in `output` and
+/// `output_len`. Prefer `convert_dwarf_with_options`.
 #[no_mangle]
 pub unsafe extern "C" fn convert_dwarf(
     wasm: *const u8,
@@ -58,17 +273,210 @@ pub unsafe extern "C" fn convert_dwarf(
     enabled_x_scopes: bool,
 ) -> bool {
     let wasm_bytes = slice::from_raw_parts(wasm, wasm_len);
-    match convert(&wasm_bytes, enabled_x_scopes) {
-        Ok(json) =>{
-            *output = alloc_mem(json.len()) as *const u8;
-            *output_len = json.len();
-            slice::from_raw_parts_mut(*output as *mut u8, *output_len)
-                .clone_from_slice(json.as_slice());
-            true
+    let options = ConvertOptions {
+        x_scopes: enabled_x_scopes,
+        ..Default::default()
+    };
+    let status = catch_panic(|| match convert(&wasm_bytes, &options) {
+        Ok(json) => {
+            copy_to_output(&json, output, output_len);
+            DwarfToJsonStatus::Ok
+        },
+        Err(ref err) => err.into(),
+    });
+    if status != DwarfToJsonStatus::Ok {
+        *output_len = 0;
+    }
+    status == DwarfToJsonStatus::Ok
+}
+
+unsafe fn validate_options(options: *const DwarfToJsonOptions) -> Result<(), DwarfToJsonStatus> {
+    if options.is_null() {
+        return Ok(());
+    }
+    let options = &*options;
+    if options.flags & !DWARF_TO_JSON_KNOWN_FLAGS != 0 {
+        return Err(DwarfToJsonStatus::InvalidArgument);
+    }
+    // The budget would silently not be enforced.
+    if options.memory_budget > 0 && !budget::is_counting() {
+        return Err(DwarfToJsonStatus::InvalidArgument);
+    }
+    match options.format {
+        DWARF_TO_JSON_FORMAT_PRETTY | DWARF_TO_JSON_FORMAT_COMPACT => Ok(()),
+        _ => Err(DwarfToJsonStatus::InvalidArgument),
+    }
+}
+
+/// Reports progress to the host and polls it for cancellation.
+unsafe fn host_progress(options: &DwarfToJsonOptions, percent: u32) -> bool {
+    if let Some(progress) = options.progress {
+        progress(options.user_data, percent);
+    }
+    match options.should_cancel {
+        Some(should_cancel) => should_cancel(options.user_data) == 0,
+        None => true,
+    }
+}
+
+/// Runs `f` with the `ConvertOptions` equivalent of the validated `options`.
+unsafe fn with_options<F>(options: *const DwarfToJsonOptions, f: F) -> DwarfToJsonStatus
+where
+    F: FnOnce(&ConvertOptions) -> DwarfToJsonStatus,
+{
+    if options.is_null() {
+        return f(&Default::default());
+    }
+    let options = &*options;
+    let progress = |percent| host_progress(options, percent);
+    let has_callbacks = options.progress.is_some() || options.should_cancel.is_some();
+    f(&ConvertOptions {
+        x_scopes: options.flags & DWARF_TO_JSON_X_SCOPES != 0,
+        function_offsets: options.flags & DWARF_TO_JSON_FUNCTION_OFFSETS != 0,
+        column_spans: options.flags & DWARF_TO_JSON_COLUMN_SPANS != 0,
+        types: options.flags & DWARF_TO_JSON_TYPES != 0,
+        globals: options.flags & DWARF_TO_JSON_GLOBALS != 0,
+        collapse_aliases: options.flags & DWARF_TO_JSON_COLLAPSE_ALIASES != 0,
+        qualified_names: options.flags & DWARF_TO_JSON_QUALIFIED_NAMES != 0,
+        verify: options.flags & DWARF_TO_JSON_VERIFY != 0,
+        keep_dead_functions: options.flags & DWARF_TO_JSON_KEEP_DEAD_FUNCTIONS != 0,
+        format: if options.format == DWARF_TO_JSON_FORMAT_COMPACT {
+            OutputFormat::Compact
+        } else {
+            OutputFormat::Pretty
         },
-        Err(_) => {
-            *output_len = 0;
-            false
+        progress: if has_callbacks { Some(&progress) } else { None },
+        memory_budget: if options.memory_budget > 0 {
+            Some(cmp::min(options.memory_budget, usize::max_value() as u64) as usize)
+        } else {
+            None
+        },
+        ..Default::default()
+    })
+}
+
+unsafe fn store_output(
+    result: Result<Vec<u8>, Error>,
+    output: *mut DwarfToJsonOutput,
+) -> DwarfToJsonStatus {
+    match result {
+        Ok(json) => {
+            let mut data: *const u8 = ptr::null();
+            copy_to_output(&json, &mut data, &mut (*output).len);
+            (*output).data = data as *mut u8;
+            DwarfToJsonStatus::Ok
+        }
+        Err(Error::ExternalDebugInfo(url)) => {
+            let mut data: *const u8 = ptr::null();
+            copy_to_output(url.as_bytes(), &mut data, &mut (*output).len);
+            (*output).data = data as *mut u8;
+            DwarfToJsonStatus::ExternalDebugInfo
+        }
+        Err(ref err) => err.into(),
+    }
+}
+
+/// Converts the `input_len` bytes of the wasm module at `input`. The input
+/// is only borrowed for the duration of the call. `options` may be NULL to
+/// use the defaults.
+#[no_mangle]
+pub unsafe extern "C" fn convert_dwarf_with_options(
+    input: *const u8,
+    input_len: usize,
+    options: *const DwarfToJsonOptions,
+    output: *mut DwarfToJsonOutput,
+) -> DwarfToJsonStatus {
+    if output.is_null() {
+        return DwarfToJsonStatus::InvalidArgument;
+    }
+    (*output).data = ptr::null_mut();
+    (*output).len = 0;
+    if input.is_null() {
+        return DwarfToJsonStatus::InvalidArgument;
+    }
+    if let Err(status) = validate_options(options) {
+        return status;
+    }
+    let input_bytes = slice::from_raw_parts(input, input_len);
+    catch_panic(|| {
+        with_options(options, |options| store_output(convert(input_bytes, options), output))
+    })
+}
+
+/// Converts debug sections that the host already extracted from a module,
+/// without rescanning the binary. `sections` points to `section_count`
+/// descriptors; `code_section_offset` is the offset of the code section
+/// payload in the original module. `options` may be NULL. Passing the same
+/// section twice, e.g. as `debug_info` and `.debug_info`, is rejected with
+/// `DwarfToJsonStatus::InvalidArgument`.
+#[no_mangle]
+pub unsafe extern "C" fn convert_dwarf_sections(
+    sections: *const DwarfToJsonSection,
+    section_count: usize,
+    code_section_offset: u64,
+    options: *const DwarfToJsonOptions,
+    output: *mut DwarfToJsonOutput,
+) -> DwarfToJsonStatus {
+    if output.is_null() {
+        return DwarfToJsonStatus::InvalidArgument;
+    }
+    (*output).data = ptr::null_mut();
+    (*output).len = 0;
+    if sections.is_null() && section_count > 0 {
+        return DwarfToJsonStatus::InvalidArgument;
+    }
+    if let Err(status) = validate_options(options) {
+        return status;
+    }
+    let mut section_map = HashMap::new();
+    for i in 0..section_count {
+        let section = &*sections.add(i);
+        if section.name.is_null() || (section.data.is_null() && section.data_len > 0) {
+            return DwarfToJsonStatus::InvalidArgument;
         }
+        let name = match str::from_utf8(slice::from_raw_parts(section.name, section.name_len)) {
+            Ok(name) => name,
+            Err(_) => return DwarfToJsonStatus::InvalidArgument,
+        };
+        let name = canonical_section_name(name).unwrap_or(name);
+        let data = if section.data_len > 0 {
+            slice::from_raw_parts(section.data, section.data_len)
+        } else {
+            &[]
+        };
+        if section_map.insert(name, data).is_some() {
+            return DwarfToJsonStatus::InvalidArgument;
+        }
+    }
+    catch_panic(|| {
+        with_options(options, |options| {
+            let result = convert_sections(&section_map, code_section_offset, options);
+            store_output(result, output)
+        })
+    })
+}
+
+/// Stores the build id of the wasm module at `input` in `output` as a
+/// lowercase hex string. `output` stays empty if the module has no
+/// `build_id` section.
+#[no_mangle]
+pub unsafe extern "C" fn read_module_build_id(
+    input: *const u8,
+    input_len: usize,
+    output: *mut DwarfToJsonOutput,
+) -> DwarfToJsonStatus {
+    if output.is_null() {
+        return DwarfToJsonStatus::InvalidArgument;
+    }
+    (*output).data = ptr::null_mut();
+    (*output).len = 0;
+    if input.is_null() {
+        return DwarfToJsonStatus::InvalidArgument;
     }
+    let input_bytes = slice::from_raw_parts(input, input_len);
+    catch_panic(|| match read_build_id(input_bytes) {
+        Ok(Some(id)) => store_output(Ok(id.into_bytes()), output),
+        Ok(None) => DwarfToJsonStatus::Ok,
+        Err(ref err) => err.into(),
+    })
 }
@@ -0,0 +1,1084 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Lookups in the source maps produced by the converter, so that Rust tools
+// can consume them without a JavaScript source map library.
+
+use crate::expr::decode_expression;
+use crate::sidecar::write_sidecar;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+use vlq::decode;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not a JSON source map object.
+    InvalidJson,
+    /// The `mappings` do not match the `sources`, or are not valid VLQ.
+    InvalidMappings,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidJson => write!(f, "the input is not a source map"),
+            Error::InvalidMappings => write!(f, "malformed source map mappings"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A position in the sources. The line and column are 1-based; the column
+/// is 1 when DWARF does not tell it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcePosition<'a> {
+    pub source: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+struct Segment {
+    offset: u64,
+    source: u32,
+    line: u32,
+    column: u32,
+    /// From `x-column-spans`, 0-based and exclusive.
+    end_column: Option<u32>,
+}
+
+/// A subprogram of the `x-scopes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub name: Option<String>,
+    /// The name prefixed with the enclosing namespaces and types, e.g.
+    /// `ns::Type::method`.
+    pub qualified_name: Option<String>,
+    pub decl_file: Option<String>,
+    pub decl_line: Option<u32>,
+    /// The module offsets of the code of the function.
+    pub ranges: Vec<Range<u64>>,
+    inlined_calls: Vec<InlinedCall>,
+    /// The function itself, first, and its blocks and inlined calls.
+    scopes: Vec<LexicalScope>,
+    variables: Vec<ScopedVariable>,
+}
+
+/// The kind of a lexical scope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScopeKind {
+    Function,
+    /// A `lexical_block` with code.
+    Block,
+    /// The code of an inlined call: the scopes enclosing it belong to the
+    /// caller's frame.
+    InlinedCall,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LexicalScope {
+    kind: ScopeKind,
+    /// The name of the inlined function.
+    name: Option<String>,
+    /// The index of the enclosing scope.
+    parent: Option<usize>,
+    ranges: Vec<Range<u64>>,
+}
+
+/// An `inlined_subroutine` entry of a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InlinedCall {
+    /// The nesting level in the function, starting at 1.
+    depth: usize,
+    name: Option<String>,
+    ranges: Vec<Range<u64>>,
+    call_file: Option<String>,
+    call_line: u32,
+    call_column: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum VariableLocation {
+    Missing,
+    Expression(Vec<u8>),
+    /// The expressions with the module offsets of the code they apply to.
+    List(Vec<(Range<u64>, Vec<u8>)>),
+}
+
+/// A `variable` or `formal_parameter` entry of a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ScopedVariable {
+    name: Option<String>,
+    is_parameter: bool,
+    type_name: Option<String>,
+    decl_file: Option<String>,
+    decl_line: Option<u32>,
+    /// The index of the scope the variable is declared in.
+    scope: usize,
+    location: VariableLocation,
+}
+
+/// A variable or parameter in scope at a code offset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable<'a> {
+    pub name: Option<&'a str>,
+    pub is_parameter: bool,
+    /// The name of the type, when it is described in the same unit; it is
+    /// composed from the referenced types, e.g. `const char *`, when the map
+    /// has the type table.
+    pub type_name: Option<&'a str>,
+    pub decl_file: Option<&'a str>,
+    pub decl_line: Option<u32>,
+    /// The location expression at the offset, or `None` when the variable
+    /// has no location there, e.g. when it is optimized out.
+    pub expression: Option<&'a [u8]>,
+    /// The operations of the expression, in the llvm-dwarfdump syntax (e.g.
+    /// `DW_OP_WASM_location 0x0 0x2`), or `None` when they could not be
+    /// decoded.
+    pub operations: Option<Vec<String>>,
+}
+
+/// A lexical scope at a code offset, with the variables and parameters it
+/// declares; the variables of the inner scopes shadow the ones of the outer
+/// scopes with the same name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scope<'a> {
+    pub kind: ScopeKind,
+    /// The qualified name of the function, or the name of the inlined one.
+    pub name: Option<&'a str>,
+    pub variables: Vec<Variable<'a>>,
+}
+
+/// The profiler samples attributed by `SourceMapping::symbolicate`, the most
+/// sampled first. The samples of the code that is not mapped are counted
+/// with `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribution<'a> {
+    /// The samples per source file and line.
+    pub lines: Vec<(Option<(&'a str, u32)>, u64)>,
+    /// The samples per function, by qualified name; the map needs
+    /// `x-scopes` for them.
+    pub functions: Vec<(Option<&'a str>, u64)>,
+}
+
+/// The source lines covered by the executed code, per source file, as
+/// returned by `SourceMapping::coverage`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coverage<'a> {
+    pub files: Vec<FileCoverage<'a>>,
+}
+
+/// The lines of a source file that have code, in order, and whether any of
+/// their code was executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileCoverage<'a> {
+    pub source: &'a str,
+    pub lines: Vec<(u32, bool)>,
+}
+
+impl<'a> Coverage<'a> {
+    /// Formats the coverage as an lcov tracefile.
+    pub fn to_lcov(&self) -> String {
+        let mut result = String::from("TN:\n");
+        for file in &self.files {
+            result.push_str(&format!("SF:{}\n", file.source));
+            for &(line, covered) in &file.lines {
+                result.push_str(&format!("DA:{},{}\n", line, covered as u32));
+            }
+            let hit = file.lines.iter().filter(|(_, covered)| *covered).count();
+            result.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", file.lines.len(), hit));
+        }
+        result
+    }
+
+    /// Formats the coverage as the JSON of Istanbul, with one statement per
+    /// line.
+    pub fn to_istanbul(&self) -> String {
+        let mut files = serde_json::Map::new();
+        for file in &self.files {
+            let mut statements = serde_json::Map::new();
+            let mut counts = serde_json::Map::new();
+            for (i, &(line, covered)) in file.lines.iter().enumerate() {
+                statements.insert(
+                    i.to_string(),
+                    json!({
+                        "start": { "line": line, "column": 0 },
+                        "end": { "line": line, "column": 0 },
+                    }),
+                );
+                counts.insert(i.to_string(), json!(covered as u32));
+            }
+            files.insert(
+                file.source.to_string(),
+                json!({
+                    "path": file.source,
+                    "statementMap": statements,
+                    "fnMap": {},
+                    "branchMap": {},
+                    "s": counts,
+                    "f": {},
+                    "b": {},
+                }),
+            );
+        }
+        Value::Object(files).to_string()
+    }
+}
+
+/// The code size of a function, as returned by `SourceMapping::size_report`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionSize<'a> {
+    /// The qualified name.
+    pub name: Option<&'a str>,
+    pub decl_file: Option<&'a str>,
+    /// The size of all the code of the function, in bytes.
+    pub size: u64,
+    /// The size of the code inlined directly in the function, per inlined
+    /// function, the largest first; it is included in `size`.
+    pub inlined: Vec<(Option<&'a str>, u64)>,
+}
+
+/// The generated code size per function and source file, the largest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeReport<'a> {
+    /// The functions of the `x-scopes`.
+    pub functions: Vec<FunctionSize<'a>>,
+    /// The size of the code mapped to each source file by the line table,
+    /// including the code inlined from it.
+    pub files: Vec<(&'a str, u64)>,
+}
+
+/// What overlaps a code range, as returned by `SourceMapping::overlapping`,
+/// with the number of bytes of the overlap, the largest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Overlap<'a> {
+    /// The functions, by qualified name.
+    pub functions: Vec<(Option<&'a str>, u64)>,
+    /// The inlined functions, at any depth; the code of the nested inlined
+    /// calls is counted for each of the enclosing ones.
+    pub inlined: Vec<(Option<&'a str>, u64)>,
+    /// The source files and lines.
+    pub lines: Vec<((&'a str, u32), u64)>,
+}
+
+/// A logical frame of the code at an offset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// The name of the function, which is the linkage name for the inlined
+    /// ones when it is known.
+    pub function: Option<&'a str>,
+    /// The position in the function: of the code for the innermost frame,
+    /// and of the call to the inner frame for the others.
+    pub position: Option<SourcePosition<'a>>,
+}
+
+/// The mappings of a source map, for looking up the source position of
+/// code offsets, and its functions when it has `x-scopes`.
+pub struct SourceMapping {
+    sources: Vec<String>,
+    /// Sorted by offset.
+    segments: Vec<Segment>,
+    functions: Vec<Function>,
+    /// The ranges of the functions with their index, sorted by start.
+    function_ranges: Vec<(Range<u64>, usize)>,
+}
+
+impl SourceMapping {
+    /// Reads a map produced by `convert`. For a component, pass one element
+    /// of the produced array.
+    pub fn from_json(json: &[u8]) -> Result<SourceMapping, Error> {
+        let map: Value = serde_json::from_slice(json).map_err(|_| Error::InvalidJson)?;
+        SourceMapping::from_value(&map)
+    }
+
+    pub fn from_value(map: &Value) -> Result<SourceMapping, Error> {
+        let sources = map["sources"]
+            .as_array()
+            .ok_or(Error::InvalidJson)?
+            .iter()
+            .map(|source| source.as_str().map(|source| source.to_string()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::InvalidJson)?;
+        let mappings = map["mappings"].as_str().ok_or(Error::InvalidJson)?;
+        let spans = map["x-column-spans"].as_array().map(|spans| spans.as_slice());
+        let segments = decode_mappings(mappings, sources.len(), spans)?;
+        let functions = read_functions(&map["x-scopes"], &sources);
+        let mut function_ranges = Vec::new();
+        for (i, function) in functions.iter().enumerate() {
+            function_ranges.extend(function.ranges.iter().map(|range| (range.clone(), i)));
+        }
+        function_ranges.sort_by_key(|(range, _)| range.start);
+        Ok(SourceMapping {
+            sources,
+            segments,
+            functions,
+            function_ranges,
+        })
+    }
+
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    fn position(&self, segment: &Segment) -> SourcePosition {
+        SourcePosition {
+            source: &self.sources[segment.source as usize],
+            line: segment.line + 1,
+            column: segment.column + 1,
+        }
+    }
+
+    /// Returns the source position of the code at `offset` in the module,
+    /// i.e. of the last mapping at or before it.
+    pub fn lookup(&self, offset: u64) -> Option<SourcePosition> {
+        let i = match self.segments.binary_search_by(|segment| {
+            if segment.offset <= offset {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }) {
+            Ok(i) | Err(i) => i,
+        };
+        if i == 0 {
+            return None;
+        }
+        Some(self.position(&self.segments[i - 1]))
+    }
+
+    /// Returns the columns of the statement with code at `offset`, 1-based
+    /// and exclusive, when the map has `x-column-spans` and the statement
+    /// does not extend to the end of the line.
+    pub fn column_span(&self, offset: u64) -> Option<Range<u32>> {
+        let i = match self.segments.binary_search_by(|segment| {
+            if segment.offset <= offset {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }) {
+            Ok(i) | Err(i) => i,
+        };
+        let segment = self.segments[..i].last()?;
+        segment.end_column.map(|end| segment.column + 1..end + 1)
+    }
+
+    /// Returns the function with code at `offset` in the module. It is only
+    /// found when the map has `x-scopes`.
+    pub fn function_at(&self, offset: u64) -> Option<&Function> {
+        self.function_index_at(offset).map(|index| &self.functions[index])
+    }
+
+    fn function_index_at(&self, offset: u64) -> Option<usize> {
+        let i = match self.function_ranges.binary_search_by(|(range, _)| {
+            if range.start <= offset {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }) {
+            Ok(i) | Err(i) => i,
+        };
+        match self.function_ranges[..i].last() {
+            Some((range, index)) if offset < range.end => Some(*index),
+            _ => None,
+        }
+    }
+
+    /// Attributes the `(offset, count)` samples of a profiler to the source
+    /// lines and functions. Each distinct offset is looked up once.
+    pub fn symbolicate<I>(&self, samples: I) -> Attribution
+    where
+        I: IntoIterator<Item = (u64, u64)>,
+    {
+        let mut offsets: HashMap<u64, u64> = HashMap::new();
+        for (offset, count) in samples {
+            // The counts come from the client: they saturate instead of
+            // overflowing.
+            let total = offsets.entry(offset).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+        let mut lines = HashMap::new();
+        let mut functions = HashMap::new();
+        for (offset, count) in offsets {
+            let line = self.lookup(offset).map(|position| (position.source, position.line));
+            let total = lines.entry(line).or_insert(0u64);
+            *total = total.saturating_add(count);
+            let total = functions.entry(self.function_index_at(offset)).or_insert(0u64);
+            *total = total.saturating_add(count);
+        }
+        let mut lines: Vec<_> = lines.into_iter().collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut functions: Vec<_> = functions
+            .into_iter()
+            .map(|(index, count)| {
+                let name = index.and_then(|index| self.functions[index].qualified_name.as_ref());
+                (name.map(|name| name.as_str()), count)
+            }).collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Attribution { lines, functions }
+    }
+
+    /// Returns the logical frames of the code at `offset`, innermost first:
+    /// the functions inlined there, followed by the function containing it.
+    /// It is empty when the map has no `x-scopes` or no function contains
+    /// the code.
+    pub fn inline_stack(&self, offset: u64) -> Vec<Frame> {
+        let function = match self.function_at(offset) {
+            Some(function) => function,
+            None => return Vec::new(),
+        };
+        let mut calls: Vec<&InlinedCall> = function
+            .inlined_calls
+            .iter()
+            .filter(|call| call.ranges.iter().any(|range| range.contains(&offset)))
+            .collect();
+        calls.sort_by_key(|call| cmp::Reverse(call.depth));
+        let mut frames = Vec::with_capacity(calls.len() + 1);
+        let mut position = self.lookup(offset);
+        for call in calls {
+            frames.push(Frame {
+                function: call.name.as_ref().map(|name| name.as_str()),
+                position,
+            });
+            position = call.call_file.as_ref().map(|file| SourcePosition {
+                source: file,
+                line: call.call_line,
+                column: cmp::max(call.call_column, 1),
+            });
+        }
+        frames.push(Frame {
+            function: function.qualified_name.as_ref().map(|name| name.as_str()),
+            position,
+        });
+        frames
+    }
+
+    /// Returns the chain of lexical scopes at `offset`, innermost first,
+    /// ending with the function containing the code. It is empty when the
+    /// map has no `x-scopes`.
+    pub fn scope_chain(&self, offset: u64) -> Vec<Scope> {
+        let function = match self.function_at(offset) {
+            Some(function) => function,
+            None => return Vec::new(),
+        };
+        // The nested scopes have less code than the enclosing ones.
+        let innermost = function
+            .scopes
+            .iter()
+            .enumerate()
+            .filter(|(_, scope)| scope.ranges.iter().any(|range| range.contains(&offset)))
+            .min_by_key(|(_, scope)| {
+                scope
+                    .ranges
+                    .iter()
+                    .map(|range| range.end - range.start)
+                    .sum::<u64>()
+            }).map_or(0, |(index, _)| index);
+        let mut chain = Vec::new();
+        let mut index = Some(innermost);
+        while let Some(i) = index {
+            let scope = &function.scopes[i];
+            let name = match scope.kind {
+                ScopeKind::Function => function.qualified_name.as_ref(),
+                _ => scope.name.as_ref(),
+            };
+            chain.push(Scope {
+                kind: scope.kind,
+                name: name.map(|name| name.as_str()),
+                variables: function
+                    .variables
+                    .iter()
+                    .filter(|variable| variable.scope == i)
+                    .map(|variable| variable_at(variable, offset))
+                    .collect(),
+            });
+            index = scope.parent;
+        }
+        chain
+    }
+
+    /// Returns the variables and parameters in scope at `offset`, the ones
+    /// of the innermost blocks first. It is empty when the map has no
+    /// `x-scopes`.
+    pub fn variables_at(&self, offset: u64) -> Vec<Variable> {
+        self.scope_chain(offset)
+            .into_iter()
+            .flat_map(|scope| scope.variables)
+            .collect()
+    }
+
+    /// Returns the offsets of the code generated for `line` of `source`, in
+    /// order, e.g. to set a breakpoint. The `source` matches the paths that
+    /// are equal to it or end with it after a `/`, so `file.c` matches
+    /// `/src/file.c`.
+    pub fn offsets_for(&self, source: &str, line: u32) -> Vec<u64> {
+        let ids: Vec<bool> = self
+            .sources
+            .iter()
+            .map(|path| matches_source(path, source))
+            .collect();
+        let mut offsets: Vec<u64> = self
+            .segments
+            .iter()
+            .filter(|segment| ids[segment.source as usize] && segment.line + 1 == line)
+            .map(|segment| segment.offset)
+            .collect();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Writes the mappings and the functions in the binary form read by
+    /// `sidecar::Sidecar`.
+    pub fn to_sidecar(&self) -> Vec<u8> {
+        write_sidecar(
+            self.segments
+                .iter()
+                .map(|segment| (segment.offset, self.position(segment))),
+            self.function_ranges.iter().map(|(range, index)| {
+                let name = self.functions[*index].qualified_name.as_ref();
+                (range.clone(), name.map(|name| name.as_str()))
+            }),
+        )
+    }
+
+    /// Returns the code size of the functions and source files, e.g. to
+    /// find which sources grew the module.
+    pub fn size_report(&self) -> SizeReport {
+        let mut functions: Vec<FunctionSize> = self
+            .functions
+            .iter()
+            .map(|function| {
+                let mut inlined: HashMap<Option<&str>, u64> = HashMap::new();
+                for call in function.inlined_calls.iter().filter(|call| call.depth == 1) {
+                    let name = call.name.as_ref().map(|name| name.as_str());
+                    *inlined.entry(name).or_insert(0) += ranges_size(&call.ranges);
+                }
+                let mut inlined: Vec<_> = inlined.into_iter().collect();
+                inlined.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                FunctionSize {
+                    name: function.qualified_name.as_ref().map(|name| name.as_str()),
+                    decl_file: function.decl_file.as_ref().map(|file| file.as_str()),
+                    size: ranges_size(&function.ranges),
+                    inlined,
+                }
+            }).collect();
+        functions.sort_by(|a, b| b.size.cmp(&a.size).then(a.name.cmp(&b.name)));
+
+        // The code of a mapping extends to the next one, except for the
+        // last mapping, whose end is unknown.
+        let mut files = vec![0u64; self.sources.len()];
+        for pair in self.segments.windows(2) {
+            files[pair[0].source as usize] += pair[1].offset - pair[0].offset;
+        }
+        let mut files: Vec<(&str, u64)> = self
+            .sources
+            .iter()
+            .map(|source| source.as_str())
+            .zip(files)
+            .filter(|(_, size)| *size > 0)
+            .collect();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        SizeReport { functions, files }
+    }
+
+    /// Returns the functions, inlined functions and source lines with code in
+    /// `range` of module offsets, e.g. a hot region found by a profiler.
+    pub fn overlapping(&self, range: Range<u64>) -> Overlap {
+        let mut functions = HashMap::new();
+        let mut inlined = HashMap::new();
+        for (function_range, index) in self.function_ranges.iter() {
+            let size = overlap_size(function_range, &range);
+            if size == 0 {
+                continue;
+            }
+            let function = &self.functions[*index];
+            let name = function.qualified_name.as_ref().map(|name| name.as_str());
+            *functions.entry(name).or_insert(0) += size;
+            // The code of the function's range within the queried one.
+            let part = cmp::max(function_range.start, range.start)
+                ..cmp::min(function_range.end, range.end);
+            for call in function.inlined_calls.iter() {
+                let size: u64 = call.ranges.iter().map(|call_range| overlap_size(call_range, &part)).sum();
+                if size > 0 {
+                    let name = call.name.as_ref().map(|name| name.as_str());
+                    *inlined.entry(name).or_insert(0) += size;
+                }
+            }
+        }
+        // The code of a mapping extends to the next one; the last mapping is
+        // assumed to extend to the end of the range.
+        let mut lines = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            let end = self.segments.get(i + 1).map_or(u64::max_value(), |next| next.offset);
+            let size = overlap_size(&(segment.offset..end), &range);
+            if size > 0 {
+                let source: &str = &self.sources[segment.source as usize];
+                *lines.entry((source, segment.line + 1)).or_insert(0) += size;
+            }
+        }
+        fn sorted<K: Ord>(map: HashMap<K, u64>) -> Vec<(K, u64)> {
+            let mut items: Vec<_> = map.into_iter().collect();
+            items.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            items
+        }
+        Overlap {
+            functions: sorted(functions),
+            inlined: sorted(inlined),
+            lines: sorted(lines),
+        }
+    }
+
+    /// Exports the mappings as a table with one row per mapping: the module
+    /// offset, the source file, line and column, and the qualified name of
+    /// the function, which is empty without `x-scopes`. The `separator` is
+    /// `,` for CSV, whose fields are quoted when needed, or `\t` for TSV.
+    pub fn to_table(&self, separator: char) -> String {
+        let mut result = String::with_capacity(self.segments.len() * 48);
+        let header = ["address", "file", "line", "column", "function"];
+        result.push_str(&header.join(&separator.to_string()));
+        result.push('\n');
+        for segment in self.segments.iter() {
+            let position = self.position(segment);
+            let function = self
+                .function_index_at(segment.offset)
+                .and_then(|index| self.functions[index].qualified_name.as_ref())
+                .map_or("", |name| name.as_str());
+            result.push_str(&format!(
+                "{}{sep}{}{sep}{}{sep}{}{sep}{}\n",
+                segment.offset,
+                table_field(position.source, separator),
+                position.line,
+                position.column,
+                table_field(function, separator),
+                sep = separator
+            ));
+        }
+        result
+    }
+
+    /// Returns the source lines covered by the executed `ranges` of module
+    /// offsets. The code of a mapping extends to the next mapping, and a line
+    /// is covered when any of its code overlaps a range.
+    pub fn coverage<I>(&self, ranges: I) -> Coverage
+    where
+        I: IntoIterator<Item = Range<u64>>,
+    {
+        let mut executed: Vec<Range<u64>> = ranges
+            .into_iter()
+            .filter(|range| range.start < range.end)
+            .collect();
+        executed.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(executed.len());
+        for range in executed {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = cmp::max(last.end, range.end),
+                _ => merged.push(range),
+            }
+        }
+        let mut lines: BTreeMap<u32, BTreeMap<u32, bool>> = BTreeMap::new();
+        // The code of the segments extends to `end`, the next offset.
+        let (mut start, mut end) = (u64::max_value(), u64::max_value());
+        for segment in self.segments.iter().rev() {
+            if segment.offset != start {
+                end = start;
+                start = segment.offset;
+            }
+            // The first range ending after the start of the code.
+            let i = match merged.binary_search_by(|range| {
+                if range.end <= segment.offset {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }) {
+                Ok(i) | Err(i) => i,
+            };
+            let covered = merged.get(i).map_or(false, |range| range.start < end);
+            *lines
+                .entry(segment.source)
+                .or_insert_with(BTreeMap::new)
+                .entry(segment.line + 1)
+                .or_insert(false) |= covered;
+        }
+        let files = lines
+            .into_iter()
+            .map(|(source, lines)| FileCoverage {
+                source: &self.sources[source as usize],
+                lines: lines.into_iter().collect(),
+            }).collect();
+        Coverage { files }
+    }
+
+    /// Rewrites the wasm frames of a stack trace printed by a browser, e.g.
+    /// `at wasm-function[42]:0x1b3c` or `at <module>+0x1b3c`, whose offsets
+    /// are module offsets, with their logical frames and source positions.
+    /// The other lines, and the frames that are not mapped, are kept.
+    pub fn rewrite_stack_trace(&self, trace: &str) -> String {
+        let mut result = String::with_capacity(trace.len());
+        for line in trace.lines() {
+            let offset = match frame_offset(line) {
+                Some(offset) => offset,
+                None => {
+                    result.push_str(line);
+                    result.push('\n');
+                    continue;
+                }
+            };
+            let mut frames = self.inline_stack(offset);
+            if frames.is_empty() {
+                frames.push(Frame {
+                    function: None,
+                    position: self.lookup(offset),
+                });
+            }
+            if frames.iter().all(|frame| frame.function.is_none() && frame.position.is_none()) {
+                result.push_str(line);
+                result.push('\n');
+                continue;
+            }
+            let indent = &line[..line.len() - line.trim_start().len()];
+            for frame in frames {
+                result.push_str(indent);
+                result.push_str("at ");
+                match (frame.function, frame.position) {
+                    (Some(function), Some(p)) => {
+                        result.push_str(&format!("{} ({}:{}:{})", function, p.source, p.line, p.column))
+                    }
+                    (None, Some(p)) => result.push_str(&format!("{}:{}:{}", p.source, p.line, p.column)),
+                    (Some(function), None) => result.push_str(function),
+                    (None, None) => result.push_str("<unknown>"),
+                }
+                result.push('\n');
+            }
+        }
+        result
+    }
+}
+
+/// Returns the module offset of a wasm frame of a stack trace, which follows
+/// `wasm-function[N]:` or `+`.
+fn frame_offset(line: &str) -> Option<u64> {
+    let start = match line.find("wasm-function[") {
+        Some(i) => {
+            let rest = &line[i + line[i..].find(']')? + 1..];
+            if !rest.starts_with(":0x") {
+                return None;
+            }
+            &rest[3..]
+        }
+        None => &line[line.find("+0x")? + 3..],
+    };
+    let digits = start.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(start.len());
+    u64::from_str_radix(&start[..digits], 16).ok()
+}
+
+/// Quotes a text field of a CSV table when it has separators, quotes or line
+/// breaks; the TSV fields cannot have tabs or line breaks, which are
+/// replaced with spaces.
+fn table_field(s: &str, separator: char) -> Cow<str> {
+    if separator == '\t' {
+        if s.contains(|c| c == '\t' || c == '\n' || c == '\r') {
+            return Cow::Owned(s.replace(|c| c == '\t' || c == '\n' || c == '\r', " "));
+        }
+        return Cow::Borrowed(s);
+    }
+    if s.contains(|c| c == separator || c == '"' || c == '\n' || c == '\r') {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+fn overlap_size(a: &Range<u64>, b: &Range<u64>) -> u64 {
+    cmp::min(a.end, b.end).saturating_sub(cmp::max(a.start, b.start))
+}
+
+fn ranges_size(ranges: &[Range<u64>]) -> u64 {
+    ranges.iter().map(|range| range.end - range.start).sum()
+}
+
+fn variable_at(variable: &ScopedVariable, offset: u64) -> Variable {
+    let expression = match variable.location {
+        VariableLocation::Missing => None,
+        VariableLocation::Expression(ref expr) => Some(expr.as_slice()),
+        VariableLocation::List(ref list) => list
+            .iter()
+            .find(|(range, _)| range.contains(&offset))
+            .map(|(_, expr)| expr.as_slice()),
+    };
+    Variable {
+        name: variable.name.as_ref().map(|name| name.as_str()),
+        is_parameter: variable.is_parameter,
+        type_name: variable.type_name.as_ref().map(|name| name.as_str()),
+        decl_file: variable.decl_file.as_ref().map(|file| file.as_str()),
+        decl_line: variable.decl_line,
+        expression,
+        operations: expression.and_then(decode_expression),
+    }
+}
+
+/// The tags of the entries whose name qualifies the names of their children.
+const SCOPE_TAGS: &[&str] = &["namespace", "class_type", "structure_type", "union_type"];
+
+fn read_ranges(entry: &Value, code_section_offset: u64) -> Vec<Range<u64>> {
+    let mut ranges = Vec::new();
+    if let (Some(low_pc), Some(high_pc)) = (entry["low_pc"].as_u64(), entry["high_pc"].as_u64()) {
+        ranges.push(low_pc..high_pc);
+    }
+    if let Some(list) = entry["ranges"].as_array() {
+        for range in list {
+            if let (Some(begin), Some(end)) = (range[0].as_u64(), range[1].as_u64()) {
+                ranges.push(begin..end);
+            }
+        }
+    }
+    ranges
+        .into_iter()
+        .filter(|range| range.start < range.end)
+        .filter_map(|range| {
+            // The ranges that overflow the address space are dropped.
+            let start = range.start.checked_add(code_section_offset)?;
+            let end = range.end.checked_add(code_section_offset)?;
+            Some(start..end)
+        }).collect()
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}::{}", scope, name)
+    }
+}
+
+fn read_source(value: &Value, sources: &[String]) -> Option<String> {
+    value.as_u64().and_then(|id| sources.get(id as usize)).cloned()
+}
+
+fn read_string(value: &Value) -> Option<String> {
+    value.as_str().map(|s| s.to_string())
+}
+
+/// Returns the display name of the entry's type from the type table, or the
+/// name of the referenced entry without it.
+fn read_type_name(entry: &Value, types: &Value) -> Option<String> {
+    entry["type_id"]
+        .as_u64()
+        .and_then(|id| types[id as usize]["display_name"].as_str())
+        .or_else(|| entry["type"]["name"].as_str())
+        .map(|name| name.to_string())
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn read_location(entry: &Value, code_section_offset: u64) -> VariableLocation {
+    let location = &entry["location"];
+    if let Some(expr) = location.as_str().and_then(parse_hex) {
+        return VariableLocation::Expression(expr);
+    }
+    let list = match location.as_array() {
+        Some(list) => list,
+        None => return VariableLocation::Missing,
+    };
+    VariableLocation::List(
+        list.iter()
+            .filter_map(|item| {
+                let begin = item["range"][0].as_u64()?.checked_add(code_section_offset)?;
+                let end = item["range"][1].as_u64()?.checked_add(code_section_offset)?;
+                Some((begin..end, item["expr"].as_str().and_then(parse_hex)?))
+            }).collect(),
+    )
+}
+
+/// Where the entries visited by `read_functions` are.
+struct Parent<'v> {
+    entries: &'v Value,
+    /// The qualified name of the enclosing namespaces and types.
+    scope: String,
+    /// The index of the enclosing function and the inlining depth.
+    function: Option<(usize, usize)>,
+    /// The index of the innermost enclosing scope of the function.
+    block: usize,
+}
+
+/// Collects the subprograms with code, with their inlined calls, lexical
+/// scopes and variables, from the `x-scopes` tree, which is walked with an explicit
+/// stack as it may be deep.
+fn read_functions(scopes: &Value, sources: &[String]) -> Vec<Function> {
+    let code_section_offset = scopes["code_section_offset"].as_u64().unwrap_or(0);
+    let mut functions: Vec<Function> = Vec::new();
+    let mut pending = vec![Parent {
+        entries: &scopes["debug_info"],
+        scope: String::new(),
+        function: None,
+        block: 0,
+    }];
+    while let Some(parent) = pending.pop() {
+        for entry in parent.entries.as_array().map_or(&[][..], |entries| entries.as_slice()) {
+            // The functions in removed code have the addresses of live ones.
+            if entry["dead"] == true {
+                continue;
+            }
+            // The entries of inlined code refer to their abstract entry.
+            let name = entry["name"].as_str().or_else(|| entry["abstract_origin"]["name"].as_str());
+            let qualified_name = name.map(|name| qualify(&parent.scope, name));
+            let tag = entry["tag"].as_str().unwrap_or("");
+            let ranges = read_ranges(entry, code_section_offset);
+            let mut function = parent.function;
+            let mut block = parent.block;
+            match (tag, parent.function) {
+                ("subprogram", _) => {
+                    if !ranges.is_empty() {
+                        function = Some((functions.len(), 0));
+                        block = 0;
+                        functions.push(Function {
+                            name: name.map(|name| name.to_string()),
+                            qualified_name: qualified_name.clone(),
+                            decl_file: read_source(&entry["decl_file"], sources),
+                            decl_line: entry["decl_line"].as_u64().map(|line| line as u32),
+                            ranges: ranges.clone(),
+                            inlined_calls: Vec::new(),
+                            scopes: vec![LexicalScope {
+                                kind: ScopeKind::Function,
+                                name: None,
+                                parent: None,
+                                ranges,
+                            }],
+                            variables: Vec::new(),
+                        });
+                    }
+                }
+                ("inlined_subroutine", Some((index, depth))) => {
+                    function = Some((index, depth + 1));
+                    let name = read_string(&entry["abstract_origin"]["name"]);
+                    if !ranges.is_empty() {
+                        block = functions[index].scopes.len();
+                        functions[index].scopes.push(LexicalScope {
+                            kind: ScopeKind::InlinedCall,
+                            name: name.clone(),
+                            parent: Some(parent.block),
+                            ranges: ranges.clone(),
+                        });
+                    }
+                    functions[index].inlined_calls.push(InlinedCall {
+                        depth: depth + 1,
+                        name,
+                        ranges,
+                        call_file: read_source(&entry["call_file"], sources),
+                        call_line: entry["call_line"].as_u64().unwrap_or(0) as u32,
+                        call_column: entry["call_column"].as_u64().unwrap_or(0) as u32,
+                    });
+                }
+                ("lexical_block", Some((index, _))) => {
+                    if !ranges.is_empty() {
+                        block = functions[index].scopes.len();
+                        functions[index].scopes.push(LexicalScope {
+                            kind: ScopeKind::Block,
+                            name: None,
+                            parent: Some(parent.block),
+                            ranges,
+                        });
+                    }
+                }
+                ("variable", Some((index, _))) | ("formal_parameter", Some((index, _))) => {
+                    functions[index].variables.push(ScopedVariable {
+                        name: name.map(|name| name.to_string()),
+                        is_parameter: tag == "formal_parameter",
+                        type_name: read_type_name(entry, &scopes["types"]),
+                        decl_file: read_source(&entry["decl_file"], sources),
+                        decl_line: entry["decl_line"].as_u64().map(|line| line as u32),
+                        scope: parent.block,
+                        location: read_location(entry, code_section_offset),
+                    });
+                }
+                _ => (),
+            }
+            if entry["children"].is_array() {
+                let scope = if SCOPE_TAGS.contains(&tag) {
+                    qualified_name.unwrap_or_else(|| qualify(&parent.scope, "(anonymous)"))
+                } else {
+                    parent.scope.clone()
+                };
+                pending.push(Parent {
+                    entries: &entry["children"],
+                    scope,
+                    function,
+                    block,
+                });
+            }
+        }
+    }
+    functions
+}
+
+fn matches_source(path: &str, source: &str) -> bool {
+    path == source || (path.ends_with(source) && path[..path.len() - source.len()].ends_with('/'))
+}
+
+/// Decodes the segments of the single generated line, whose columns are the
+/// code offsets, with their end columns from the `x-column-spans`.
+fn decode_mappings(
+    mappings: &str,
+    source_count: usize,
+    spans: Option<&[Value]>,
+) -> Result<Vec<Segment>, Error> {
+    let mut segments = Vec::new();
+    let mut fields = [0i64; 4];
+    for segment in mappings.split(',').filter(|segment| !segment.is_empty()) {
+        let mut bytes = segment.bytes();
+        let mut count = 0;
+        while bytes.len() != 0 {
+            // The optional name index is not used.
+            let delta = decode(&mut bytes).map_err(|_| Error::InvalidMappings)?;
+            if count < fields.len() {
+                fields[count] = fields[count].checked_add(delta).ok_or(Error::InvalidMappings)?;
+            }
+            count += 1;
+        }
+        // Segments with the offset only do not map to the sources.
+        if count < 4 {
+            continue;
+        }
+        let [offset, source, line, column] = fields;
+        if offset < 0 || source < 0 || source as usize >= source_count || line < 0 || column < 0 {
+            return Err(Error::InvalidMappings);
+        }
+        segments.push(Segment {
+            offset: offset as u64,
+            source: source as u32,
+            line: line as u32,
+            column: column as u32,
+            end_column: spans
+                .and_then(|spans| spans.get(segments.len()))
+                .and_then(|end| end.as_u64())
+                .map(|end| end as u32),
+        });
+    }
+    segments.sort_by_key(|segment| segment.offset);
+    Ok(segments)
+}
@@ -14,22 +14,282 @@
  */
 
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::process;
 
-use crate::convert::convert;
-
-extern crate gimli;
+extern crate clap;
+extern crate dwarf_to_json;
+extern crate memmap2;
 #[macro_use]
 extern crate serde_json;
-extern crate vlq;
-extern crate clap;
 
 use clap::{Arg, App};
+use dwarf_to_json::convert::{
+    convert, read_build_id, read_source_mapping_url, replace_source_mapping_url,
+    separate_debug_info, ConvertOptions, DuplicateSectionPolicy, Error, OutputFormat,
+    OUTPUT_SCHEMA,
+};
+use dwarf_to_json::lookup::{ScopeKind, SourceMapping, SourcePosition, Variable};
+use memmap2::Mmap;
+use serde_json::Value;
+
+// Counts the heap usage for `--memory-budget`, unless the library already
+// installs the allocator.
+#[cfg(not(feature = "counting-allocator"))]
+#[global_allocator]
+static ALLOCATOR: dwarf_to_json::budget::CountingAllocator = dwarf_to_json::budget::CountingAllocator;
+
+/// Inputs smaller than this are read: mapping them saves little, and an
+/// empty file cannot be mapped.
+const MIN_MAPPED_SIZE: u64 = 1 << 20;
+
+/// The input file, mapped when it is large. A mapped file that is truncated
+/// while it is converted, e.g. by a concurrent relink, makes the process
+/// crash with SIGBUS; rebuild loops should convert a module once it is
+/// linked.
+enum Input {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Input {
+    fn open(path: &str) -> io::Result<Input> {
+        let file = fs::File::open(path)?;
+        if file.metadata()?.len() < MIN_MAPPED_SIZE {
+            return fs::read(path).map(Input::Read);
+        }
+        // The converter only keeps references to the sections of the input.
+        unsafe { Mmap::map(&file) }.map(Input::Mapped)
+    }
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Input::Mapped(map) => map,
+            Input::Read(data) => data,
+        }
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal offset.
+fn parse_offset(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Reads the profiler samples of a file with one `OFFSET [COUNT]` per line;
+/// the count defaults to 1, and `#` starts a comment.
+fn read_samples(path: &str) -> Vec<(u64, u64)> {
+    let text = fs::read_to_string(path).expect("failed to read the samples");
+    let mut samples = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap();
+        let mut fields = line.split_whitespace();
+        let offset = match fields.next() {
+            Some(offset) => parse_offset(offset).expect("invalid sample offset"),
+            None => continue,
+        };
+        let count = fields.next().map_or(1, |count| count.parse().expect("invalid sample count"));
+        samples.push((offset, count));
+    }
+    samples
+}
+
+/// Reads the executed code of a file with one `START END` range of offsets
+/// per line; `#` starts a comment.
+fn read_executed_ranges(path: &str) -> Vec<Range<u64>> {
+    let text = fs::read_to_string(path).expect("failed to read the executed ranges");
+    let mut ranges = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap();
+        let mut fields = line
+            .split_whitespace()
+            .map(|field| parse_offset(field).expect("invalid range offset"));
+        match (fields.next(), fields.next()) {
+            (Some(start), Some(end)) => ranges.push(start..end),
+            (None, _) => (),
+            (Some(_), None) => panic!("the executed ranges must be given as START END"),
+        }
+    }
+    ranges
+}
+
+fn position_to_json(position: Option<SourcePosition>) -> Value {
+    match position {
+        Some(p) => json!({ "source": p.source, "line": p.line, "column": p.column }),
+        None => Value::Null,
+    }
+}
+
+fn variable_to_json(variable: Variable) -> Value {
+    json!({
+        "name": variable.name,
+        "isParameter": variable.is_parameter,
+        "type": variable.type_name,
+        "declFile": variable.decl_file,
+        "declLine": variable.decl_line,
+        "operations": variable.operations,
+        "optimizedOut": variable.expression.is_none(),
+    })
+}
+
+fn scope_kind_name(kind: ScopeKind) -> &'static str {
+    match kind {
+        ScopeKind::Function => "function",
+        ScopeKind::Block => "block",
+        ScopeKind::InlinedCall => "inlined",
+    }
+}
+
+/// Reads an offset of a query, given as a number or as a string.
+fn query_offset(value: &Value) -> Result<u64, String> {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(parse_offset))
+        .ok_or_else(|| "invalid or missing offset".to_string())
+}
+
+/// Answers a query of the server mode: the `method` is the name of the
+/// `SourceMapping` lookup, and the other fields are its arguments.
+fn answer(mapping: &SourceMapping, query: &Value) -> Result<Value, String> {
+    match query["method"].as_str().unwrap_or("") {
+        "lookup" => Ok(position_to_json(mapping.lookup(query_offset(&query["offset"])?))),
+        "offsetsFor" => {
+            let source = query["source"].as_str().ok_or("missing source")?;
+            let line = query["line"].as_u64().ok_or("missing line")?;
+            Ok(json!(mapping.offsets_for(source, line as u32)))
+        }
+        "functionAt" => Ok(match mapping.function_at(query_offset(&query["offset"])?) {
+            Some(function) => json!({
+                "name": function.qualified_name,
+                "declFile": function.decl_file,
+                "declLine": function.decl_line,
+                "ranges": function.ranges.iter().map(|range| [range.start, range.end]).collect::<Vec<_>>(),
+            }),
+            None => Value::Null,
+        }),
+        "inlineStack" => {
+            let frames = mapping.inline_stack(query_offset(&query["offset"])?);
+            Ok(Value::Array(
+                frames
+                    .into_iter()
+                    .map(|frame| json!({ "function": frame.function, "position": position_to_json(frame.position) }))
+                    .collect(),
+            ))
+        }
+        "variablesAt" => {
+            let variables = mapping.variables_at(query_offset(&query["offset"])?);
+            Ok(Value::Array(variables.into_iter().map(variable_to_json).collect()))
+        }
+        "scopeChain" => {
+            let chain = mapping.scope_chain(query_offset(&query["offset"])?);
+            Ok(Value::Array(
+                chain
+                    .into_iter()
+                    .map(|scope| {
+                        json!({
+                            "kind": scope_kind_name(scope.kind),
+                            "name": scope.name,
+                            "variables": scope.variables.into_iter().map(variable_to_json).collect::<Vec<_>>(),
+                        })
+                    }).collect(),
+            ))
+        }
+        "symbolicate" => {
+            let samples = query["samples"]
+                .as_array()
+                .ok_or("missing samples")?
+                .iter()
+                .map(|sample| match sample.as_array() {
+                    Some(pair) => {
+                        let offset = query_offset(pair.first().ok_or("empty sample")?)?;
+                        Ok((offset, pair.get(1).and_then(Value::as_u64).unwrap_or(1)))
+                    }
+                    None => Ok((query_offset(sample)?, 1)),
+                }).collect::<Result<Vec<_>, String>>()?;
+            let attribution = mapping.symbolicate(samples);
+            let lines: Vec<Value> = attribution
+                .lines
+                .into_iter()
+                .map(|(line, count)| match line {
+                    Some((source, line)) => json!({ "source": source, "line": line, "count": count }),
+                    None => json!({ "source": null, "line": null, "count": count }),
+                }).collect();
+            let functions: Vec<Value> = attribution
+                .functions
+                .into_iter()
+                .map(|(function, count)| json!({ "function": function, "count": count }))
+                .collect();
+            Ok(json!({ "lines": lines, "functions": functions }))
+        }
+        "overlapping" => {
+            let start = query_offset(&query["start"])?;
+            let end = query_offset(&query["end"])?;
+            let overlap = mapping.overlapping(start..end);
+            let named = |items: Vec<(Option<&str>, u64)>| -> Vec<Value> {
+                items
+                    .into_iter()
+                    .map(|(name, size)| json!({ "name": name, "size": size }))
+                    .collect()
+            };
+            let lines: Vec<Value> = overlap
+                .lines
+                .into_iter()
+                .map(|((source, line), size)| json!({ "source": source, "line": line, "size": size }))
+                .collect();
+            Ok(json!({
+                "functions": named(overlap.functions),
+                "inlined": named(overlap.inlined),
+                "lines": lines,
+            }))
+        }
+        "rewriteStackTrace" => {
+            let trace = query["trace"].as_str().ok_or("missing trace")?;
+            Ok(json!(mapping.rewrite_stack_trace(trace)))
+        }
+        method => Err(format!("unknown method {:?}", method)),
+    }
+}
 
-mod convert;
-mod dwarf;
-mod to_json;
-mod wasm;
+/// Answers the queries read from stdin, one JSON object per line, with one
+/// `{"id": ..., "result": ...}` or `{"id": ..., "error": ...}` line each on
+/// stdout, until stdin is closed.
+fn serve(mapping: &SourceMapping) {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for line in stdin.lock().lines() {
+        let line = line.expect("failed to read the query");
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(query) => match answer(mapping, &query) {
+                Ok(result) => json!({ "id": query["id"], "result": result }),
+                Err(message) => json!({ "id": query["id"], "error": message }),
+            },
+            Err(err) => json!({ "id": null, "error": format!("invalid query: {}", err) }),
+        };
+        writeln!(out, "{}", response).expect("failed to write the response");
+        out.flush().expect("failed to write the response");
+    }
+}
+
+/// Returns the result's value, or reports the error and exits.
+fn or_exit<T>(result: Result<T, Error>, action: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: failed to {}: {}", action, err);
+        process::exit(1);
+    })
+}
 
 fn main() {
     let matches = App::new("dwarf-to-json")
@@ -38,14 +298,431 @@ fn main() {
                           .arg(Arg::with_name("output")
                                .short("o")
                                .takes_value(true))
+                          .arg(Arg::with_name("format")
+                               .long("format")
+                               .takes_value(true)
+                               .possible_values(&["pretty", "compact"])
+                               .default_value("pretty"))
+                          .arg(Arg::with_name("memory-budget")
+                               .long("memory-budget")
+                               .takes_value(true)
+                               .value_name("BYTES"))
+                          .arg(Arg::with_name("duplicate-sections")
+                               .long("duplicate-sections")
+                               .takes_value(true)
+                               .possible_values(&["first", "last", "error"])
+                               .default_value("last")
+                               .help("Which copy of a repeated debug section to use"))
+                          .arg(Arg::with_name("code-section-offset")
+                               .long("code-section-offset")
+                               .takes_value(true)
+                               .value_name("OFFSET")
+                               .help("Code section payload offset in the original module, when converting a DWARF sidecar"))
+                          .arg(Arg::with_name("split-module")
+                               .long("split-module")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Module produced by wasm-split from the input, to map the addresses to"))
+                          .arg(Arg::with_name("cache-dir")
+                               .long("cache-dir")
+                               .takes_value(true)
+                               .value_name("DIR")
+                               .help("Reuses the output of earlier conversions of the same debug information"))
+                          .arg(Arg::with_name("attributes")
+                               .long("attributes")
+                               .takes_value(true)
+                               .value_name("NAMES")
+                               .use_delimiter(true)
+                               .help("Comma-separated x-scopes attributes to keep, e.g. name,linkage_name"))
+                          .arg(Arg::with_name("function-offsets")
+                               .long("function-offsets")
+                               .help("Emit the function body offsets as x-function-offsets"))
+                          .arg(Arg::with_name("types")
+                               .long("types")
+                               .help("Emit the type table in x-scopes, referenced by the type_id of the entries"))
+                          .arg(Arg::with_name("globals")
+                               .long("globals")
+                               .help("Emit the memory addresses of the global variables in x-scopes, with the type table"))
+                          .arg(Arg::with_name("collapse-typedefs")
+                               .long("collapse-typedefs")
+                               .help("Makes the typedefs of the type table refer to the type their chain of typedefs ends with"))
+                          .arg(Arg::with_name("qualified-names")
+                               .long("qualified-names")
+                               .help("Qualifies the names of the type table with their namespaces, e.g. std::string"))
+                          .arg(Arg::with_name("verify")
+                               .long("verify")
+                               .help("Checks the order and extent of the mappings and the nesting of the x-scopes ranges"))
+                          .arg(Arg::with_name("keep-dead-functions")
+                               .long("keep-dead-functions")
+                               .help("Keeps the functions in removed code in x-scopes, marked as dead"))
+                          .arg(Arg::with_name("sources-content")
+                               .long("sources-content")
+                               .help("Embeds the source files as sourcesContent, with null for the missing ones"))
+                          .arg(Arg::with_name("column-spans")
+                               .long("column-spans")
+                               .help("Emit the end column of each mapping as x-column-spans"))
+                          .arg(Arg::with_name("external-debug-info")
+                               .long("external-debug-info")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Companion DWARF file, when the input has an external_debug_info section"))
+                          .arg(Arg::with_name("source-map-url")
+                               .long("source-map-url")
+                               .takes_value(true)
+                               .value_name("URL")
+                               .requires("patched-wasm")
+                               .help("sourceMappingURL to store in the patched module"))
+                          .arg(Arg::with_name("patched-wasm")
+                               .long("patched-wasm")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .requires("source-map-url")
+                               .help("Writes a copy of the input with its sourceMappingURL replaced"))
+                          .arg(Arg::with_name("separate-dwarf")
+                               .long("separate-dwarf")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .requires("stripped-wasm")
+                               .help("Writes the debug sections of the input to a companion module"))
+                          .arg(Arg::with_name("stripped-wasm")
+                               .long("stripped-wasm")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .requires("separate-dwarf")
+                               .help("Writes a copy of the input without debug sections that refers to the companion module"))
+                          .arg(Arg::with_name("external-debug-url")
+                               .long("external-debug-url")
+                               .takes_value(true)
+                               .value_name("URL")
+                               .requires("separate-dwarf")
+                               .help("Companion module location stored in the stripped module, the file name by default"))
+                          .arg(Arg::with_name("lookup-line")
+                               .long("lookup-line")
+                               .takes_value(true)
+                               .value_name("FILE:LINE")
+                               .help("Prints the code offsets of a source line instead of the JSON"))
+                          .arg(Arg::with_name("lookup-function")
+                               .long("lookup-function")
+                               .takes_value(true)
+                               .value_name("OFFSET")
+                               .help("Prints the function containing a code offset instead of the JSON"))
+                          .arg(Arg::with_name("inline-stack")
+                               .long("inline-stack")
+                               .takes_value(true)
+                               .value_name("OFFSET")
+                               .help("Prints the logical frames of a code offset, innermost first, instead of the JSON"))
+                          .arg(Arg::with_name("variables")
+                               .long("variables")
+                               .takes_value(true)
+                               .value_name("OFFSET")
+                               .help("Prints the variables in scope at a code offset instead of the JSON"))
+                          .arg(Arg::with_name("scope-chain")
+                               .long("scope-chain")
+                               .takes_value(true)
+                               .value_name("OFFSET")
+                               .help("Prints the lexical scopes at a code offset, innermost first, with their variables instead of the JSON"))
+                          .arg(Arg::with_name("overlapping")
+                               .long("overlapping")
+                               .takes_value(true)
+                               .value_name("START-END")
+                               .help("Prints the functions, inlined functions and source lines with code in a range of offsets instead of the JSON"))
+                          .arg(Arg::with_name("export")
+                               .long("export")
+                               .takes_value(true)
+                               .possible_values(&["csv", "tsv"])
+                               .help("Prints the line table, one row per mapping, instead of the JSON"))
+                          .arg(Arg::with_name("size-report")
+                               .long("size-report")
+                               .help("Prints the code size per function, with its inlined functions, and per source file instead of the JSON"))
+                          .arg(Arg::with_name("symbolicate")
+                               .long("symbolicate")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Prints the profiler samples of FILE (lines of OFFSET [COUNT]) per source line and function instead of the JSON"))
+                          .arg(Arg::with_name("rewrite-stack")
+                               .long("rewrite-stack")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Prints the browser stack trace of FILE with its wasm frames symbolicated instead of the JSON"))
+                          .arg(Arg::with_name("coverage")
+                               .long("coverage")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Prints the source lines covered by the executed code of FILE (lines of START END) instead of the JSON"))
+                          .arg(Arg::with_name("coverage-format")
+                               .long("coverage-format")
+                               .takes_value(true)
+                               .possible_values(&["lcov", "istanbul"])
+                               .default_value("lcov"))
+                          .arg(Arg::with_name("sidecar")
+                               .long("sidecar")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Also writes the binary lookup tables of the map, read by dwarf_to_json::sidecar"))
+                          .arg(Arg::with_name("serve")
+                               .long("serve")
+                               .help("Answers lookup queries read from stdin as JSON lines instead of writing the JSON"))
+                          .arg(Arg::with_name("build-id")
+                               .long("build-id")
+                               .help("Prints the build id of the input instead of converting it"))
+                          .arg(Arg::with_name("schema")
+                               .long("schema")
+                               .help("Prints the JSON Schema of the output instead of converting the input"))
                           .arg(Arg::with_name("INPUT")
-                               .required(true))
+                               .required_unless("schema"))
                           .get_matches();
 
+    if matches.is_present("schema") {
+        print!("{}", OUTPUT_SCHEMA);
+        return;
+    }
+
     let input_path = matches.value_of("INPUT").unwrap();
-    let wasm = fs::read(input_path).expect("failed to read wasm input");
+    // Debug builds can be very large: the input is mapped instead of read.
+    let wasm = Input::open(input_path).expect("failed to open wasm input");
+
+    if matches.is_present("build-id") {
+        if let Some(id) = or_exit(read_build_id(&wasm), "read the build id") {
+            println!("{}", id);
+        }
+        return;
+    }
+
+    // The companion file is given explicitly or resolved relative to the
+    // input file.
+    let load_external = |url: &str| {
+        let path = match matches.value_of("external-debug-info") {
+            Some(path) => Path::new(path).to_path_buf(),
+            None => Path::new(input_path).with_file_name(url),
+        };
+        fs::read(path).ok()
+    };
+    let load_source = |path: &str| fs::read(path).ok();
+    let warning = |message: &str| eprintln!("warning: {}", message);
+    let split_module = matches
+        .value_of("split-module")
+        .map(|path| fs::read(path).expect("failed to read split module"));
+    let attributes: Option<Vec<&str>> = matches.values_of("attributes").map(|names| names.collect());
+    let options = ConvertOptions {
+        x_scopes: true,
+        attributes: attributes.as_ref().map(|names| names.as_slice()),
+        function_offsets: matches.is_present("function-offsets"),
+        column_spans: matches.is_present("column-spans"),
+        types: matches.is_present("types"),
+        globals: matches.is_present("globals"),
+        collapse_aliases: matches.is_present("collapse-typedefs"),
+        qualified_names: matches.is_present("qualified-names"),
+        verify: matches.is_present("verify"),
+        keep_dead_functions: matches.is_present("keep-dead-functions"),
+        format: match matches.value_of("format") {
+            Some("compact") => OutputFormat::Compact,
+            _ => OutputFormat::Pretty,
+        },
+        external_debug_info_loader: Some(&load_external),
+        source_loader: if matches.is_present("sources-content") {
+            Some(&load_source)
+        } else {
+            None
+        },
+        memory_budget: matches
+            .value_of("memory-budget")
+            .map(|s| s.parse().expect("invalid memory budget")),
+        duplicate_sections: match matches.value_of("duplicate-sections") {
+            Some("first") => DuplicateSectionPolicy::First,
+            Some("error") => DuplicateSectionPolicy::Reject,
+            _ => DuplicateSectionPolicy::Last,
+        },
+        code_section_offset: matches
+            .value_of("code-section-offset")
+            .map(|s| parse_offset(s).expect("invalid code section offset")),
+        split_module: split_module.as_ref().map(|module| module.as_slice()),
+        warning: Some(&warning),
+        cache_dir: matches.value_of("cache-dir").map(Path::new),
+        ..Default::default()
+    };
+    let json = match convert(&wasm, &options) {
+        Err(Error::ExternalDebugInfo(url)) => {
+            eprintln!("DWARF is stored in the external file {}; use --external-debug-info", url);
+            process::exit(1);
+        }
+        result => or_exit(result, "convert the input"),
+    };
 
-    let json = convert(&wasm, true).expect("json");
+    if let Some(companion_path) = matches.value_of("separate-dwarf") {
+        let url = match matches.value_of("external-debug-url") {
+            Some(url) => url.to_string(),
+            None => Path::new(companion_path)
+                .file_name()
+                .expect("invalid companion file name")
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let (stripped, companion) =
+            or_exit(separate_debug_info(&wasm, &url), "separate the debug sections");
+        fs::write(companion_path, &companion).expect("failed to write the companion module");
+        let stripped_path = matches.value_of("stripped-wasm").unwrap();
+        fs::write(stripped_path, &stripped).expect("failed to write the stripped module");
+    }
+
+    if let Some(patched_path) = matches.value_of("patched-wasm") {
+        let url = matches.value_of("source-map-url").unwrap();
+        let patched = or_exit(replace_source_mapping_url(&wasm, url), "patch the input");
+        fs::write(patched_path, &patched).expect("failed to write patched wasm");
+    } else if let Ok(Some(url)) = read_source_mapping_url(&wasm) {
+        eprintln!("warning: the input already references the source map {}", url);
+    }
+
+    let read_mapping = || {
+        SourceMapping::from_json(&json).unwrap_or_else(|err| {
+            eprintln!("error: failed to read the source map: {}", err);
+            process::exit(1);
+        })
+    };
+    if let Some(sidecar_path) = matches.value_of("sidecar") {
+        fs::write(sidecar_path, read_mapping().to_sidecar()).expect("failed to write the sidecar");
+    }
+    if matches.is_present("serve") {
+        serve(&read_mapping());
+        return;
+    }
+    if let Some(query) = matches.value_of("lookup-line") {
+        let i = query.rfind(':').expect("the source line must be given as FILE:LINE");
+        let line = query[i + 1..].parse().expect("invalid line number");
+        for offset in read_mapping().offsets_for(&query[..i], line) {
+            println!("0x{:x}", offset);
+        }
+        return;
+    }
+    if let Some(offset) = matches.value_of("inline-stack") {
+        let offset = parse_offset(offset).expect("invalid code offset");
+        let mapping = read_mapping();
+        for frame in mapping.inline_stack(offset) {
+            let function = frame.function.unwrap_or("<unnamed>");
+            match frame.position {
+                Some(p) => println!("{} at {}:{}:{}", function, p.source, p.line, p.column),
+                None => println!("{}", function),
+            }
+        }
+        return;
+    }
+    if let Some(range) = matches.value_of("overlapping") {
+        let i = range.find('-').expect("the range must be given as START-END");
+        let start = parse_offset(&range[..i]).expect("invalid range start");
+        let end = parse_offset(&range[i + 1..]).expect("invalid range end");
+        let mapping = read_mapping();
+        let overlap = mapping.overlapping(start..end);
+        println!("# functions");
+        for (name, size) in overlap.functions {
+            println!("{} {}", size, name.unwrap_or("<unnamed>"));
+        }
+        println!("# inlined functions");
+        for (name, size) in overlap.inlined {
+            println!("{} {}", size, name.unwrap_or("<unnamed>"));
+        }
+        println!("# lines");
+        for ((source, line), size) in overlap.lines {
+            println!("{} {}:{}", size, source, line);
+        }
+        return;
+    }
+    if let Some(format) = matches.value_of("export") {
+        let separator = if format == "tsv" { '\t' } else { ',' };
+        print!("{}", read_mapping().to_table(separator));
+        return;
+    }
+    if matches.is_present("size-report") {
+        let mapping = read_mapping();
+        let report = mapping.size_report();
+        println!("# code size per function");
+        for function in report.functions {
+            println!(
+                "{} {} {}",
+                function.size,
+                function.name.unwrap_or("<unnamed>"),
+                function.decl_file.unwrap_or("<unknown>")
+            );
+            for (name, size) in function.inlined {
+                println!("  {} inlined {}", size, name.unwrap_or("<unnamed>"));
+            }
+        }
+        println!("# code size per source file");
+        for (source, size) in report.files {
+            println!("{} {}", size, source);
+        }
+        return;
+    }
+    if let Some(path) = matches.value_of("symbolicate") {
+        let mapping = read_mapping();
+        let attribution = mapping.symbolicate(read_samples(path));
+        println!("# samples per line");
+        for (line, count) in attribution.lines {
+            match line {
+                Some((source, line)) => println!("{} {}:{}", count, source, line),
+                None => println!("{} <unknown>", count),
+            }
+        }
+        println!("# samples per function");
+        for (function, count) in attribution.functions {
+            println!("{} {}", count, function.unwrap_or("<unknown>"));
+        }
+        return;
+    }
+    if let Some(path) = matches.value_of("rewrite-stack") {
+        let trace = fs::read_to_string(path).expect("failed to read the stack trace");
+        print!("{}", read_mapping().rewrite_stack_trace(&trace));
+        return;
+    }
+    if let Some(path) = matches.value_of("coverage") {
+        let mapping = read_mapping();
+        let coverage = mapping.coverage(read_executed_ranges(path));
+        match matches.value_of("coverage-format") {
+            Some("istanbul") => println!("{}", coverage.to_istanbul()),
+            _ => print!("{}", coverage.to_lcov()),
+        }
+        return;
+    }
+    if let Some(offset) = matches.value_of("variables") {
+        let offset = parse_offset(offset).expect("invalid code offset");
+        let mapping = read_mapping();
+        for variable in mapping.variables_at(offset) {
+            let location = match (variable.expression, variable.operations) {
+                (Some(_), Some(operations)) => operations.join(", "),
+                (Some(_), None) => "<unsupported expression>".to_string(),
+                (None, _) => "<optimized out>".to_string(),
+            };
+            println!(
+                "{}{}: {}",
+                if variable.is_parameter { "parameter " } else { "" },
+                variable.name.unwrap_or("<unnamed>"),
+                location
+            );
+        }
+        return;
+    }
+    if let Some(offset) = matches.value_of("scope-chain") {
+        let offset = parse_offset(offset).expect("invalid code offset");
+        let mapping = read_mapping();
+        for scope in mapping.scope_chain(offset) {
+            println!("{} {}", scope_kind_name(scope.kind), scope.name.unwrap_or("<unnamed>"));
+            for variable in scope.variables {
+                println!("  {}", variable.name.unwrap_or("<unnamed>"));
+            }
+        }
+        return;
+    }
+    if let Some(offset) = matches.value_of("lookup-function") {
+        let offset = parse_offset(offset).expect("invalid code offset");
+        if let Some(function) = read_mapping().function_at(offset) {
+            println!("{}", function.qualified_name.as_ref().map_or("<unnamed>", |name| name.as_str()));
+            if let (Some(file), Some(line)) = (&function.decl_file, function.decl_line) {
+                println!("{}:{}", file, line);
+            }
+            for range in function.ranges.iter() {
+                println!("0x{:x}-0x{:x}", range.start, range.end);
+            }
+        }
+        return;
+    }
 
     match matches.value_of("output") {
         Some(output_path) => fs::write(output_path, &json).expect("failed to write JSON"),
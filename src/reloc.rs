@@ -0,0 +1,320 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Relocation of the debug sections of unlinked object files, see
+// https://github.com/WebAssembly/tool-conventions/blob/master/Linking.md
+
+use crate::convert::WasmSections;
+use crate::wasm::{Context, WasmDecoder, WasmFormatError, WasmFormatErrorKind};
+
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+const WASM_SYMBOL_FUNCTION: u8 = 0;
+const WASM_SYMBOL_DATA: u8 = 1;
+const WASM_SYMBOL_GLOBAL: u8 = 2;
+const WASM_SYMBOL_SECTION: u8 = 3;
+const WASM_SYMBOL_EVENT: u8 = 4;
+const WASM_SYMBOL_TABLE_KIND: u8 = 5;
+
+const WASM_SYM_UNDEFINED: u32 = 0x10;
+const WASM_SYM_EXPLICIT_NAME: u32 = 0x40;
+
+const R_WASM_MEMORY_ADDR_LEB: u8 = 3;
+const R_WASM_MEMORY_ADDR_SLEB: u8 = 4;
+const R_WASM_MEMORY_ADDR_I32: u8 = 5;
+const R_WASM_FUNCTION_OFFSET_I32: u8 = 8;
+const R_WASM_SECTION_OFFSET_I32: u8 = 9;
+const R_WASM_MEMORY_ADDR_REL_SLEB: u8 = 11;
+const R_WASM_GLOBAL_INDEX_I32: u8 = 13;
+const R_WASM_MEMORY_ADDR_LEB64: u8 = 14;
+const R_WASM_MEMORY_ADDR_SLEB64: u8 = 15;
+const R_WASM_MEMORY_ADDR_I64: u8 = 16;
+const R_WASM_MEMORY_ADDR_REL_SLEB64: u8 = 17;
+const R_WASM_MEMORY_ADDR_TLS_SLEB: u8 = 21;
+const R_WASM_FUNCTION_OFFSET_I64: u8 = 22;
+const R_WASM_MEMORY_ADDR_LOCREL_I32: u8 = 23;
+const R_WASM_MEMORY_ADDR_TLS_SLEB64: u8 = 25;
+const R_WASM_FUNCTION_INDEX_I32: u8 = 26;
+
+const WASM_EXTERNAL_FUNCTION: u8 = 0;
+const WASM_EXTERNAL_TABLE: u8 = 1;
+const WASM_EXTERNAL_MEMORY: u8 = 2;
+const WASM_EXTERNAL_GLOBAL: u8 = 3;
+const WASM_EXTERNAL_EVENT: u8 = 4;
+
+enum Symbol {
+    /// Function, global, event or table index.
+    Indexed(u32),
+    /// Offset of a data symbol within its segment.
+    Data(u64),
+    /// Start of a section.
+    Section,
+    Undefined,
+}
+
+fn has_addend(reloc_type: u8) -> bool {
+    match reloc_type {
+        R_WASM_MEMORY_ADDR_LEB
+        | R_WASM_MEMORY_ADDR_SLEB
+        | R_WASM_MEMORY_ADDR_I32
+        | R_WASM_FUNCTION_OFFSET_I32
+        | R_WASM_SECTION_OFFSET_I32
+        | R_WASM_MEMORY_ADDR_REL_SLEB
+        | R_WASM_MEMORY_ADDR_LEB64
+        | R_WASM_MEMORY_ADDR_SLEB64
+        | R_WASM_MEMORY_ADDR_I64
+        | R_WASM_MEMORY_ADDR_REL_SLEB64
+        | R_WASM_MEMORY_ADDR_TLS_SLEB
+        | R_WASM_FUNCTION_OFFSET_I64
+        | R_WASM_MEMORY_ADDR_LOCREL_I32
+        | R_WASM_MEMORY_ADDR_TLS_SLEB64 => true,
+        _ => false,
+    }
+}
+
+fn read_symbol_table(linking: &[u8]) -> Result<Vec<Symbol>, WasmFormatError> {
+    let mut decoder = WasmDecoder::new(linking);
+    let _version = decoder.u32()?;
+    let mut symbols = Vec::new();
+    while !decoder.eof() {
+        let subsection_type = decoder.u8()?;
+        let len = decoder.u32()?;
+        let payload_offset = decoder.offset();
+        let payload = decoder.skip(len as usize)?;
+        if subsection_type != WASM_SYMBOL_TABLE {
+            continue;
+        }
+        let mut decoder = WasmDecoder::at(payload, payload_offset);
+        let count = decoder.u32()?;
+        for _ in 0..count {
+            let kind_offset = decoder.offset();
+            let kind = decoder.u8()?;
+            let flags = decoder.u32()?;
+            let undefined = (flags & WASM_SYM_UNDEFINED) != 0;
+            let symbol = match kind {
+                WASM_SYMBOL_FUNCTION | WASM_SYMBOL_GLOBAL | WASM_SYMBOL_EVENT
+                | WASM_SYMBOL_TABLE_KIND => {
+                    let index = decoder.u32()?;
+                    if !undefined || (flags & WASM_SYM_EXPLICIT_NAME) != 0 {
+                        decoder.str()?;
+                    }
+                    Symbol::Indexed(index)
+                }
+                WASM_SYMBOL_DATA => {
+                    decoder.str()?;
+                    if undefined {
+                        Symbol::Undefined
+                    } else {
+                        // The offset and size are u64 in wasm64 objects.
+                        let _segment = decoder.u32()?;
+                        let offset = decoder.u64()?;
+                        let _size = decoder.u64()?;
+                        Symbol::Data(offset)
+                    }
+                }
+                WASM_SYMBOL_SECTION => {
+                    decoder.u32()?;
+                    Symbol::Section
+                }
+                _ => return Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, kind_offset)),
+            };
+            symbols.push(symbol);
+        }
+    }
+    Ok(symbols)
+}
+
+const WASM_LIMITS_HAS_MAX: u8 = 0x01;
+const WASM_LIMITS_IS_64: u8 = 0x04;
+
+fn skip_limits(decoder: &mut WasmDecoder) -> Result<(), WasmFormatError> {
+    let flags = decoder.u8()?;
+    // The limits of 64-bit memories are u64.
+    let count = if (flags & WASM_LIMITS_HAS_MAX) != 0 { 2 } else { 1 };
+    for _ in 0..count {
+        if (flags & WASM_LIMITS_IS_64) != 0 {
+            decoder.u64()?;
+        } else {
+            decoder.u32()?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the number of imported functions, which precede the defined
+/// ones in the function index space.
+pub fn read_imported_function_count(imports: &[u8]) -> Result<u32, WasmFormatError> {
+    let mut decoder = WasmDecoder::new(imports);
+    let count = decoder.u32()?;
+    let mut functions = 0;
+    for _ in 0..count {
+        decoder.str()?;
+        decoder.str()?;
+        let kind_offset = decoder.offset();
+        match decoder.u8()? {
+            WASM_EXTERNAL_FUNCTION => {
+                decoder.u32()?;
+                functions += 1;
+            }
+            WASM_EXTERNAL_TABLE => {
+                decoder.u8()?;
+                skip_limits(&mut decoder)?;
+            }
+            WASM_EXTERNAL_MEMORY => skip_limits(&mut decoder)?,
+            WASM_EXTERNAL_GLOBAL => {
+                decoder.u8()?;
+                decoder.u8()?;
+            }
+            WASM_EXTERNAL_EVENT => {
+                decoder.u8()?;
+                decoder.u32()?;
+            }
+            _ => return Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, kind_offset)),
+        }
+    }
+    Ok(functions)
+}
+
+/// A function body, with offsets relative to the start of the code section
+/// payload.
+pub struct FunctionBody {
+    /// Offset of the body size field.
+    pub offset: usize,
+    /// Offset of the body itself, after its size field.
+    pub body_offset: usize,
+    pub end: usize,
+}
+
+pub fn read_function_bodies(code: &[u8]) -> Result<Vec<FunctionBody>, WasmFormatError> {
+    let mut decoder = WasmDecoder::new(code);
+    let count = decoder.u32()?;
+    let mut bodies = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let offset = decoder.offset();
+        let size = decoder.u32()?;
+        let body_offset = decoder.offset();
+        decoder.skip(size as usize)?;
+        bodies.push(FunctionBody {
+            offset,
+            body_offset,
+            end: body_offset + size as usize,
+        });
+    }
+    Ok(bodies)
+}
+
+fn write_value(data: &mut [u8], offset: usize, value: u64, size: usize) -> Option<()> {
+    let bytes = data.get_mut(offset..offset.checked_add(size)?)?;
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (i * 8)) as u8;
+    }
+    Some(())
+}
+
+/// Reads the type, offset, symbol index and addend of a relocation.
+fn read_relocation(decoder: &mut WasmDecoder) -> Result<(u8, usize, usize, i64), WasmFormatError> {
+    let reloc_type = decoder.u8()?;
+    let offset = decoder.u32()? as usize;
+    let symbol_index = decoder.u32()? as usize;
+    let addend = if has_addend(reloc_type) { decoder.i64()? } else { 0 };
+    Ok((reloc_type, offset, symbol_index, addend))
+}
+
+/// Returns copies of the debug sections with the `reloc.*` relocations
+/// applied. Function offsets resolve to code section offsets, section
+/// offsets to their addend, and data addresses to segment-relative
+/// offsets, since the final memory layout is only known after linking.
+pub fn apply_relocations<'a>(
+    module: &WasmSections<'a>,
+) -> Result<Vec<(&'a str, Vec<u8>)>, WasmFormatError> {
+    let symbols = match module.sections.get("linking") {
+        Some(linking) => read_symbol_table(linking).context("linking section")?,
+        None => return Ok(Vec::new()),
+    };
+    let imported_functions = match module.import_section {
+        Some(imports) => read_imported_function_count(imports).context("import section")?,
+        None => 0,
+    };
+    let bodies = match module.code_section {
+        Some(code) => read_function_bodies(code).context("code section")?,
+        None => Vec::new(),
+    };
+
+    let mut result = Vec::new();
+    for (name, reloc_bytes) in module.sections.iter() {
+        if !name.starts_with("reloc.") {
+            continue;
+        }
+        let mut decoder = WasmDecoder::new(reloc_bytes);
+        let target_index = decoder.u32().context("relocation section")?;
+        let (target_name, name_len) = match module.custom_sections.get(&target_index) {
+            Some(&(target_name, name_len)) if target_name.starts_with(".debug_") => {
+                (target_name, name_len)
+            }
+            _ => continue,
+        };
+        let mut data = module.sections[target_name].to_vec();
+        let count = decoder.u32().context("relocation section")?;
+        for _ in 0..count {
+            let entry_offset = decoder.offset();
+            let invalid = || {
+                Err(WasmFormatError::new(WasmFormatErrorKind::InvalidValue, entry_offset))
+                    .context("relocation entry")
+            };
+            let (reloc_type, offset, symbol_index, addend) =
+                read_relocation(&mut decoder).context("relocation entry")?;
+            let offset = match offset.checked_sub(name_len) {
+                Some(offset) => offset,
+                None => return invalid(),
+            };
+            let symbol = match symbols.get(symbol_index) {
+                Some(symbol) => symbol,
+                None => return invalid(),
+            };
+            let value = match (reloc_type, symbol) {
+                (R_WASM_FUNCTION_OFFSET_I32, &Symbol::Indexed(index))
+                | (R_WASM_FUNCTION_OFFSET_I64, &Symbol::Indexed(index)) => {
+                    let body = index
+                        .checked_sub(imported_functions)
+                        .and_then(|body| bodies.get(body as usize));
+                    match body {
+                        Some(body) => (body.body_offset as i64).wrapping_add(addend) as u64,
+                        None => return invalid(),
+                    }
+                }
+                (R_WASM_SECTION_OFFSET_I32, &Symbol::Section) => addend as u64,
+                (R_WASM_MEMORY_ADDR_I32, &Symbol::Data(offset))
+                | (R_WASM_MEMORY_ADDR_I64, &Symbol::Data(offset)) => {
+                    (offset as i64).wrapping_add(addend) as u64
+                }
+                (R_WASM_MEMORY_ADDR_I32, &Symbol::Undefined)
+                | (R_WASM_MEMORY_ADDR_I64, &Symbol::Undefined) => 0,
+                (R_WASM_GLOBAL_INDEX_I32, &Symbol::Indexed(index))
+                | (R_WASM_FUNCTION_INDEX_I32, &Symbol::Indexed(index)) => u64::from(index),
+                // Other relocation kinds do not occur in debug sections.
+                _ => continue,
+            };
+            let size = match reloc_type {
+                R_WASM_FUNCTION_OFFSET_I64 | R_WASM_MEMORY_ADDR_I64 => 8,
+                _ => 4,
+            };
+            if write_value(&mut data, offset, value, size).is_none() {
+                return invalid();
+            }
+        }
+        result.push((target_name, data));
+    }
+    Ok(result)
+}
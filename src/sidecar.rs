@@ -0,0 +1,273 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// A compact binary form of the lookup tables of a source map, for the
+// symbolication services that look up many offsets: the file can be mapped
+// into memory and searched in place, without parsing.
+//
+// All the numbers are little-endian:
+//
+//   header:    "DJSC", version (u32), string count (u32), segment count
+//              (u32), function count (u32)
+//   strings:   string count * (data offset (u32), length (u32))
+//   segments:  segment count * (offset (u64), source (u32), line (u32),
+//              column (u32)), sorted by offset
+//   functions: function count * (start (u64), end (u64), name (u32)),
+//              sorted by start
+//   data:      the UTF-8 bytes of the strings
+//
+// The sources and names are indices in the strings; a function without name
+// has `NO_NAME`. The lines and columns are 1-based.
+
+use crate::lookup::SourcePosition;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::str;
+
+const MAGIC: &[u8; 4] = b"DJSC";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 20;
+const STRING_SIZE: usize = 8;
+const SEGMENT_SIZE: usize = 20;
+const FUNCTION_SIZE: usize = 20;
+const NO_NAME: u32 = u32::max_value();
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input does not start with the sidecar header.
+    InvalidHeader,
+    /// The version of the sidecar is not supported.
+    UnsupportedVersion(u32),
+    /// The tables do not fit in the input, or refer to missing strings.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidHeader => write!(f, "the input is not a lookup sidecar"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported lookup sidecar version {}", version)
+            }
+            Error::Truncated => write!(f, "truncated lookup sidecar"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Collects the strings of a sidecar, each stored once.
+struct Strings<'s> {
+    indices: HashMap<&'s str, u32>,
+    strings: Vec<&'s str>,
+}
+
+impl<'s> Strings<'s> {
+    fn index(&mut self, s: &'s str) -> u32 {
+        let strings = &mut self.strings;
+        *self.indices.entry(s).or_insert_with(|| {
+            strings.push(s);
+            (strings.len() - 1) as u32
+        })
+    }
+}
+
+/// Writes the sidecar of the `(offset, source, position)` segments, sorted by
+/// offset, and of the `(range, name)` functions, sorted by start.
+pub(crate) fn write_sidecar<'s, S, F>(segments: S, functions: F) -> Vec<u8>
+where
+    S: Iterator<Item = (u64, SourcePosition<'s>)>,
+    F: Iterator<Item = (Range<u64>, Option<&'s str>)>,
+{
+    let mut strings = Strings {
+        indices: HashMap::new(),
+        strings: Vec::new(),
+    };
+    let mut segment_table = Vec::new();
+    let mut segment_count = 0u32;
+    for (offset, position) in segments {
+        segment_table.extend_from_slice(&offset.to_le_bytes());
+        segment_table.extend_from_slice(&strings.index(position.source).to_le_bytes());
+        segment_table.extend_from_slice(&position.line.to_le_bytes());
+        segment_table.extend_from_slice(&position.column.to_le_bytes());
+        segment_count += 1;
+    }
+    let mut function_table = Vec::new();
+    let mut function_count = 0u32;
+    for (range, name) in functions {
+        let name = name.map_or(NO_NAME, |name| strings.index(name));
+        function_table.extend_from_slice(&range.start.to_le_bytes());
+        function_table.extend_from_slice(&range.end.to_le_bytes());
+        function_table.extend_from_slice(&name.to_le_bytes());
+        function_count += 1;
+    }
+
+    let string_count = strings.strings.len() as u32;
+    let mut result = Vec::with_capacity(
+        HEADER_SIZE
+            + strings.strings.len() * STRING_SIZE
+            + segment_table.len()
+            + function_table.len(),
+    );
+    result.extend_from_slice(MAGIC);
+    for n in &[VERSION, string_count, segment_count, function_count] {
+        result.extend_from_slice(&n.to_le_bytes());
+    }
+    let mut data_offset = 0u32;
+    for s in &strings.strings {
+        result.extend_from_slice(&data_offset.to_le_bytes());
+        result.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        data_offset += s.len() as u32;
+    }
+    result.extend_from_slice(&segment_table);
+    result.extend_from_slice(&function_table);
+    for s in &strings.strings {
+        result.extend_from_slice(s.as_bytes());
+    }
+    result
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+/// The first of `count` records for which `is_after` is true, the records
+/// being ordered.
+fn partition_point(count: usize, is_after: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if is_after(middle) {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    low
+}
+
+/// A sidecar read in place, e.g. from a memory-mapped file.
+pub struct Sidecar<'a> {
+    strings: &'a [u8],
+    segments: &'a [u8],
+    functions: &'a [u8],
+    data: &'a str,
+}
+
+impl<'a> Sidecar<'a> {
+    /// Checks the header and the size of the tables of `bytes`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Sidecar<'a>, Error> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let string_count = read_u32(bytes, 8) as usize;
+        let segment_count = read_u32(bytes, 12) as usize;
+        let function_count = read_u32(bytes, 16) as usize;
+        // The counts may not fit in the address space of 32-bit targets.
+        let table_end = |start: usize, count: usize, size: usize| {
+            count
+                .checked_mul(size)
+                .and_then(|len| start.checked_add(len))
+                .ok_or(Error::Truncated)
+        };
+        let strings_end = table_end(HEADER_SIZE, string_count, STRING_SIZE)?;
+        let segments_end = table_end(strings_end, segment_count, SEGMENT_SIZE)?;
+        let functions_end = table_end(segments_end, function_count, FUNCTION_SIZE)?;
+        if bytes.len() < functions_end {
+            return Err(Error::Truncated);
+        }
+        let data = str::from_utf8(&bytes[functions_end..]).map_err(|_| Error::Truncated)?;
+        let sidecar = Sidecar {
+            strings: &bytes[HEADER_SIZE..strings_end],
+            segments: &bytes[strings_end..segments_end],
+            functions: &bytes[segments_end..functions_end],
+            data,
+        };
+        // The strings are checked once, so that the lookups cannot fail.
+        for i in 0..string_count {
+            let start = read_u32(sidecar.strings, i * STRING_SIZE) as usize;
+            let len = read_u32(sidecar.strings, i * STRING_SIZE + 4) as usize;
+            let end = start.checked_add(len).ok_or(Error::Truncated)?;
+            if sidecar.data.get(start..end).is_none() {
+                return Err(Error::Truncated);
+            }
+        }
+        for i in 0..segment_count {
+            if read_u32(sidecar.segments, i * SEGMENT_SIZE + 8) as usize >= string_count {
+                return Err(Error::Truncated);
+            }
+        }
+        for i in 0..function_count {
+            let name = read_u32(sidecar.functions, i * FUNCTION_SIZE + 16);
+            if name != NO_NAME && name as usize >= string_count {
+                return Err(Error::Truncated);
+            }
+        }
+        Ok(sidecar)
+    }
+
+    fn string(&self, index: u32) -> &'a str {
+        let start = read_u32(self.strings, index as usize * STRING_SIZE) as usize;
+        let len = read_u32(self.strings, index as usize * STRING_SIZE + 4) as usize;
+        &self.data[start..start + len]
+    }
+
+    /// Returns the source position of the code at `offset` in the module,
+    /// like `SourceMapping::lookup`.
+    pub fn lookup(&self, offset: u64) -> Option<SourcePosition<'a>> {
+        let count = self.segments.len() / SEGMENT_SIZE;
+        let i = partition_point(count, |i| read_u64(self.segments, i * SEGMENT_SIZE) > offset);
+        if i == 0 {
+            return None;
+        }
+        let record = (i - 1) * SEGMENT_SIZE;
+        Some(SourcePosition {
+            source: self.string(read_u32(self.segments, record + 8)),
+            line: read_u32(self.segments, record + 12),
+            column: read_u32(self.segments, record + 16),
+        })
+    }
+
+    /// Returns the code range and qualified name of the function with code
+    /// at `offset` in the module; the name is `None` for the functions
+    /// without one.
+    pub fn function_at(&self, offset: u64) -> Option<(Range<u64>, Option<&'a str>)> {
+        let count = self.functions.len() / FUNCTION_SIZE;
+        let i = partition_point(count, |i| read_u64(self.functions, i * FUNCTION_SIZE) > offset);
+        if i == 0 {
+            return None;
+        }
+        let record = (i - 1) * FUNCTION_SIZE;
+        let range = read_u64(self.functions, record)..read_u64(self.functions, record + 8);
+        if offset >= range.end {
+            return None;
+        }
+        let name = read_u32(self.functions, record + 16);
+        Some((range, if name == NO_NAME { None } else { Some(self.string(name)) }))
+    }
+}
@@ -0,0 +1,266 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Support for the modules produced by wasm-split. Their DWARF still
+// describes the original module, so the addresses are moved to the split
+// module's code section by matching the functions by name.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::convert::WasmSections;
+use crate::dwarf::{AttrKey, DebugAttrValue, DebugInfoObj, LocationInfo};
+use crate::reloc::{read_function_bodies, read_imported_function_count};
+use crate::wasm::{Context, WasmDecoder, WasmFormatError};
+
+const NAME_SUBSECTION_FUNCTIONS: u8 = 1;
+
+/// Returns the names of the defined functions in body order.
+fn read_function_names<'a>(module: &WasmSections<'a>) -> Result<Vec<Option<&'a str>>, WasmFormatError> {
+    let code = match module.code_section {
+        Some(code) => code,
+        None => return Ok(Vec::new()),
+    };
+    let first_index = match module.import_section {
+        Some(imports) => read_imported_function_count(imports).context("import section")?,
+        None => 0,
+    };
+    let count = WasmDecoder::new(code).u32().context("code section")? as usize;
+    let mut names = vec![None; count];
+    let name_section = match module.sections.get("name") {
+        Some(name_section) => name_section,
+        None => return Ok(names),
+    };
+    let mut decoder = WasmDecoder::new(name_section);
+    while !decoder.eof() {
+        let subsection_id = decoder.u8()?;
+        let len = decoder.u32()?;
+        let payload_offset = decoder.offset();
+        let payload = decoder.skip(len as usize)?;
+        if subsection_id != NAME_SUBSECTION_FUNCTIONS {
+            continue;
+        }
+        let mut decoder = WasmDecoder::at(payload, payload_offset);
+        let count = decoder.u32()?;
+        for _ in 0..count {
+            let index = decoder.u32()?;
+            let name = decoder.str()?;
+            if let Some(body) = index.checked_sub(first_index) {
+                if let Some(slot) = names.get_mut(body as usize) {
+                    *slot = Some(name);
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Maps the code addresses of the original module to the split one.
+pub struct AddressMap {
+    /// Original function extents with the corresponding start in the split
+    /// module, sorted.
+    functions: Vec<(Range<u64>, u64)>,
+}
+
+impl AddressMap {
+    /// Matches the functions of the `original` and `split` modules by their
+    /// names from the `name` section. The functions are anchored past their
+    /// size fields, whose width may differ between the modules. The names
+    /// of several functions of either module are skipped with a warning,
+    /// since they do not tell which function is which.
+    pub fn new(
+        original: &WasmSections,
+        split: &WasmSections,
+        warnings: &mut Vec<String>,
+    ) -> Result<AddressMap, WasmFormatError> {
+        let split_names = read_function_names(split).context("split module name section")?;
+        let split_bodies = match split.code_section {
+            Some(code) => read_function_bodies(code).context("split module code section")?,
+            None => Vec::new(),
+        };
+        let mut split_starts = HashMap::new();
+        let mut ambiguous = HashSet::new();
+        for (name, body) in split_names.iter().zip(split_bodies.iter()) {
+            if let Some(name) = name {
+                if split_starts.insert(*name, body.body_offset as u64).is_some() {
+                    ambiguous.insert(*name);
+                }
+            }
+        }
+
+        let original_names = read_function_names(original).context("name section")?;
+        let original_bodies = match original.code_section {
+            Some(code) => read_function_bodies(code).context("code section")?,
+            None => Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        for name in original_names.iter().filter_map(|name| *name) {
+            if !seen.insert(name) && split_starts.contains_key(name) {
+                ambiguous.insert(name);
+            }
+        }
+        let mut ambiguous: Vec<_> = ambiguous.into_iter().collect();
+        ambiguous.sort();
+        for name in ambiguous.iter() {
+            split_starts.remove(name);
+            warnings.push(format!(
+                "several functions are named {:?}, their addresses are not moved to the split module",
+                name
+            ));
+        }
+        let mut functions = Vec::new();
+        for (name, body) in original_names.iter().zip(original_bodies.iter()) {
+            if let Some(&start) = name.and_then(|name| split_starts.get(name)) {
+                functions.push((body.body_offset as u64..body.end as u64, start));
+            }
+        }
+        Ok(AddressMap { functions })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// Returns the address in the split module, or `None` if the function
+    /// containing `address` was moved to another module.
+    pub fn map(&self, address: u64) -> Option<u64> {
+        let i = match self.functions.binary_search_by(|(extent, _)| extent.end.cmp(&address)) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        match self.functions.get(i) {
+            Some((extent, start)) if extent.start <= address => Some(start + (address - extent.start)),
+            _ => None,
+        }
+    }
+
+    /// Moves a range, split at the function boundaries since the functions
+    /// are placed independently in the split module. The parts in the
+    /// functions of the other modules are dropped.
+    fn map_ranges(&self, begin: i64, end: i64) -> Vec<(i64, i64)> {
+        let (begin, end) = (begin as u64, end as u64);
+        if begin >= end {
+            // Empty or inverted ranges are moved as they are.
+            let len = end.wrapping_sub(begin);
+            return self
+                .map(begin)
+                .map(|new_begin| (new_begin as i64, new_begin.wrapping_add(len) as i64))
+                .into_iter()
+                .collect();
+        }
+        let first = match self.functions.binary_search_by(|(extent, _)| extent.end.cmp(&begin)) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        self.functions[first..]
+            .iter()
+            .take_while(|(extent, _)| extent.start < end)
+            .map(|(extent, start)| {
+                let part_begin = cmp::max(begin, extent.start) - extent.start;
+                let part_end = cmp::min(end, extent.end) - extent.start;
+                ((start + part_begin) as i64, (start + part_end) as i64)
+            })
+            .collect()
+    }
+
+    /// Moves the line records, dropping the ones of the other modules.
+    pub fn remap_locations(&self, info: &mut LocationInfo) {
+        info.locations.retain(|loc| self.map(loc.address).is_some());
+        for loc in info.locations.iter_mut() {
+            loc.address = self.map(loc.address).unwrap();
+        }
+        info.locations.sort_by(|a, b| a.address.cmp(&b.address));
+    }
+
+    /// Moves the scope addresses, dropping the entries and ranges of the
+    /// functions that are in the other modules.
+    pub fn remap_scopes(&self, items: &mut Vec<DebugInfoObj>) {
+        let mut pending = vec![items];
+        while let Some(items) = pending.pop() {
+            self.remap_level(items);
+            pending.extend(items.iter_mut().map(|item| &mut item.children));
+        }
+    }
+
+    fn remap_level(&self, items: &mut Vec<DebugInfoObj>) {
+        items.retain(|item| match (item.attrs.get(&AttrKey::LOW_PC), item.attrs.get(&AttrKey::HIGH_PC)) {
+            (Some(&DebugAttrValue::I64(low_pc)), Some(&DebugAttrValue::I64(high_pc))) => {
+                !self.map_ranges(low_pc, high_pc).is_empty()
+            }
+            // A label, or an entry whose low_pc is only the base of its
+            // ranges, which are moved on their own.
+            (Some(&DebugAttrValue::I64(low_pc)), None) if !item.attrs.contains_key(&AttrKey::RANGES) => {
+                self.map(low_pc as u64).is_some()
+            }
+            (Some(&DebugAttrValue::I64(low_pc)), Some(_)) => self.map(low_pc as u64).is_some(),
+            _ => true,
+        });
+        for item in items.iter_mut() {
+            for attr in item.attrs.values_mut() {
+                match attr {
+                    DebugAttrValue::Ranges(ranges) => {
+                        *ranges = ranges
+                            .iter()
+                            .flat_map(|&(begin, end)| self.map_ranges(begin, end))
+                            .collect();
+                    }
+                    DebugAttrValue::LocationList(list) => {
+                        *list = list
+                            .iter()
+                            .flat_map(|&(begin, end, expr)| {
+                                self.map_ranges(begin, end)
+                                    .into_iter()
+                                    .map(move |(begin, end)| (begin, end, expr))
+                            })
+                            .collect();
+                    }
+                    _ => (),
+                }
+            }
+            if let (Some(&DebugAttrValue::I64(low_pc)), None) =
+                (item.attrs.get(&AttrKey::LOW_PC), item.attrs.get(&AttrKey::HIGH_PC))
+            {
+                match self.map(low_pc as u64) {
+                    Some(low_pc) => {
+                        item.attrs.insert(AttrKey::LOW_PC, DebugAttrValue::I64(low_pc as i64));
+                    }
+                    None => {
+                        item.attrs.remove(&AttrKey::LOW_PC);
+                    }
+                }
+            }
+            if let (Some(&DebugAttrValue::I64(low_pc)), Some(&DebugAttrValue::I64(high_pc))) =
+                (item.attrs.get(&AttrKey::LOW_PC), item.attrs.get(&AttrKey::HIGH_PC))
+            {
+                let mut ranges = self.map_ranges(low_pc, high_pc);
+                if ranges.len() == 1 {
+                    let (low_pc, high_pc) = ranges[0];
+                    item.attrs.insert(AttrKey::LOW_PC, DebugAttrValue::I64(low_pc));
+                    item.attrs.insert(AttrKey::HIGH_PC, DebugAttrValue::I64(high_pc));
+                } else if !ranges.is_empty() {
+                    // A range over several functions, e.g. of a unit, is no
+                    // longer contiguous.
+                    item.attrs.remove(&AttrKey::LOW_PC);
+                    item.attrs.remove(&AttrKey::HIGH_PC);
+                    if let Some(DebugAttrValue::Ranges(existing)) = item.attrs.remove(&AttrKey::RANGES) {
+                        ranges.extend(existing);
+                    }
+                    item.attrs.insert(AttrKey::RANGES, DebugAttrValue::Ranges(ranges));
+                }
+            }
+        }
+    }
+}
@@ -14,127 +14,547 @@
  */
 
 use crate::dwarf::{DebugAttrValue, DebugInfoObj, LocationInfo};
-use serde_json::{to_vec_pretty, Map, Value};
+use crate::expr::decode_expression;
+use serde_json::ser::{CharEscape, CompactFormatter, Formatter, PrettyFormatter};
+use serde_json::{Map, Number, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt::Error;
-use std::fmt::Write as FmtWrite;
-use std::str;
+use std::io;
+use std::sync::Arc;
 use vlq::encode;
 
-fn convert_expr(a: &[u8]) -> Result<Value, Error> {
-    let mut result = String::new();
-    for i in a {
-        write!(&mut result, "{:02X}", i)?;
-    }
-    Ok(json!(result))
-}
-
-pub fn convert_scopes(infos: &[DebugInfoObj]) -> Result<Value, Error> {
-    let mut result = Vec::new();
-    for entry in infos {
-        let mut dict = Map::new();
-        dict.insert("tag".to_string(), json!(entry.tag));
-        for (attr_name, attr_value) in entry.attrs.iter() {
-            let value = match attr_value {
-                DebugAttrValue::I64(i) => json!(i),
-                DebugAttrValue::Bool(b) => json!(b),
-                DebugAttrValue::String(s) => json!(s),
-                DebugAttrValue::Ranges(ranges) => {
-                    let mut r = Vec::new();
-                    for range in ranges {
-                        r.push(vec![json!(range.0), json!(range.1)]);
-                    }
-                    json!(r)
+/// Serialization style of the produced JSON.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Indented JSON, easy to inspect.
+    Pretty,
+    /// JSON without insignificant whitespace, cheapest to produce and parse.
+    Compact,
+}
+
+/// The JSON Schema (draft-07) of the produced maps, with their `x-` fields,
+/// to validate them or to generate bindings in other languages.
+pub const OUTPUT_SCHEMA: &str = include_str!("schema.json");
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+fn convert_expr(a: &[u8]) -> String {
+    let mut result = String::with_capacity(a.len() * 2);
+    for &i in a {
+        result.push(HEX_DIGITS[usize::from(i >> 4)] as char);
+        result.push(HEX_DIGITS[usize::from(i & 0xF)] as char);
+    }
+    result
+}
+
+// The JSON is written straight from the DWARF structures, without an
+// intermediate `serde_json::Value` tree, through the serde_json formatters so
+// that the layout stays the same as `to_writer_pretty` and `to_writer`. The
+// object keys are written in sorted order, like the ones of a
+// `serde_json::Map`.
+
+#[derive(Clone, Copy)]
+enum EntryField<'b, 'a> {
+    Tag(&'static str),
+    Attr(&'b DebugAttrValue<'a>),
+    Children(&'b [DebugInfoObj<'a>]),
+}
+
+fn entry_fields<'b, 'a>(item: &'b DebugInfoObj<'a>) -> Vec<(Cow<'static, str>, EntryField<'b, 'a>)> {
+    let mut fields = Vec::with_capacity(item.attrs.len() + 2);
+    fields.push((Cow::Borrowed("tag"), EntryField::Tag(item.tag)));
+    for (key, value) in item.attrs.iter() {
+        fields.push((key.name(), EntryField::Attr(value)));
+    }
+    if !item.children.is_empty() {
+        fields.push((Cow::Borrowed("children"), EntryField::Children(&item.children)));
+    }
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+    fields
+}
+
+/// A partially written array or object of the scopes tree.
+enum Frame<'b, 'a> {
+    Entries(&'b [DebugInfoObj<'a>], usize),
+    Fields(Vec<(Cow<'static, str>, EntryField<'b, 'a>)>, usize),
+}
+
+/// A top-level field of the produced JSON.
+enum Field<'a> {
+    Value(&'a Value),
+    Sources(&'a [Arc<str>]),
+    Mappings(&'a LocationInfo, i64),
+    /// The `debug_info` array, already serialized by a `ScopesWriter`, and
+    /// the tables next to it, e.g. `types`.
+    Scopes(&'a [u8], &'a Map<String, Value>, i64),
+}
+
+struct JsonWriter<'o, F> {
+    out: &'o mut Vec<u8>,
+    formatter: &'o mut F,
+}
+
+impl<'o, F: Formatter> JsonWriter<'o, F> {
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.formatter.begin_string(self.out)?;
+        let bytes = s.as_bytes();
+        let mut start = 0;
+        for (i, &byte) in bytes.iter().enumerate() {
+            let escape = match byte {
+                b'"' => CharEscape::Quote,
+                b'\\' => CharEscape::ReverseSolidus,
+                b'\n' => CharEscape::LineFeed,
+                b'\r' => CharEscape::CarriageReturn,
+                b'\t' => CharEscape::Tab,
+                0x08 => CharEscape::Backspace,
+                0x0C => CharEscape::FormFeed,
+                0x00..=0x1F => CharEscape::AsciiControl(byte),
+                _ => continue,
+            };
+            if start < i {
+                self.formatter.write_string_fragment(self.out, &s[start..i])?;
+            }
+            self.formatter.write_char_escape(self.out, escape)?;
+            start = i + 1;
+        }
+        if start < bytes.len() {
+            self.formatter.write_string_fragment(self.out, &s[start..])?;
+        }
+        self.formatter.end_string(self.out)
+    }
+
+    fn write_number(&mut self, n: &Number) -> io::Result<()> {
+        if let Some(n) = n.as_u64() {
+            self.formatter.write_u64(self.out, n)
+        } else if let Some(n) = n.as_i64() {
+            self.formatter.write_i64(self.out, n)
+        } else {
+            self.formatter.write_f64(self.out, n.as_f64().unwrap())
+        }
+    }
+
+    fn write_array<T, I, G>(&mut self, items: I, mut write_item: G) -> io::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        G: FnMut(&mut Self, T) -> io::Result<()>,
+    {
+        self.formatter.begin_array(self.out)?;
+        for (i, item) in items.into_iter().enumerate() {
+            self.formatter.begin_array_value(self.out, i == 0)?;
+            write_item(self, item)?;
+            self.formatter.end_array_value(self.out)?;
+        }
+        self.formatter.end_array(self.out)
+    }
+
+    fn write_key(&mut self, key: &str, first: bool) -> io::Result<()> {
+        self.formatter.begin_object_key(self.out, first)?;
+        self.write_str(key)?;
+        self.formatter.end_object_key(self.out)?;
+        self.formatter.begin_object_value(self.out)
+    }
+
+    fn write_object<'k, T, I, G>(&mut self, fields: I, mut write_field: G) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'k str, T)>,
+        G: FnMut(&mut Self, T) -> io::Result<()>,
+    {
+        self.formatter.begin_object(self.out)?;
+        for (i, (key, value)) in fields.into_iter().enumerate() {
+            self.write_key(key, i == 0)?;
+            write_field(self, value)?;
+            self.formatter.end_object_value(self.out)?;
+        }
+        self.formatter.end_object(self.out)
+    }
+
+    fn write_value(&mut self, value: &Value) -> io::Result<()> {
+        // The metadata is shallow, unlike the scopes.
+        match *value {
+            Value::Null => self.formatter.write_null(self.out),
+            Value::Bool(b) => self.formatter.write_bool(self.out, b),
+            Value::Number(ref n) => self.write_number(n),
+            Value::String(ref s) => self.write_str(s),
+            Value::Array(ref items) => self.write_array(items, Self::write_value),
+            Value::Object(ref map) => {
+                self.write_object(map.iter().map(|(key, value)| (key.as_str(), value)), Self::write_value)
+            }
+        }
+    }
+
+    fn write_locations(&mut self, list: &[(Option<(i64, i64)>, &[u8])]) -> io::Result<()> {
+        self.write_array(list, |w, &(range, expr)| {
+            w.formatter.begin_object(w.out)?;
+            w.write_key("ops", true)?;
+            match decode_expression(expr) {
+                Some(ops) => w.write_array(&ops, |w, op| w.write_str(op))?,
+                None => w.formatter.write_null(w.out)?,
+            }
+            w.formatter.end_object_value(w.out)?;
+            w.write_key("range", false)?;
+            match range {
+                Some((begin, end)) => {
+                    w.write_array(&[begin, end], |w, &i| w.formatter.write_i64(w.out, i))?
+                }
+                None => w.formatter.write_null(w.out)?,
+            }
+            w.formatter.end_object_value(w.out)?;
+            w.formatter.end_object(w.out)
+        })
+    }
+
+    fn write_attr(&mut self, value: &DebugAttrValue) -> io::Result<()> {
+        match *value {
+            DebugAttrValue::I64(i) => self.formatter.write_i64(self.out, i),
+            DebugAttrValue::Bool(b) => self.formatter.write_bool(self.out, b),
+            DebugAttrValue::String(s) => self.write_str(s),
+            DebugAttrValue::Ranges(ref ranges) => self.write_array(ranges, |w, &(begin, end)| {
+                w.write_array(&[begin, end], |w, &i| w.formatter.write_i64(w.out, i))
+            }),
+            DebugAttrValue::LocationList(ref list) => self.write_array(list, |w, &(begin, end, expr)| {
+                w.formatter.begin_object(w.out)?;
+                w.write_key("expr", true)?;
+                w.write_str(&convert_expr(expr))?;
+                w.formatter.end_object_value(w.out)?;
+                w.write_key("range", false)?;
+                w.write_array(&[begin, end], |w, &i| w.formatter.write_i64(w.out, i))?;
+                w.formatter.end_object_value(w.out)?;
+                w.formatter.end_object(w.out)
+            }),
+            DebugAttrValue::Expression(expr) => self.write_str(&convert_expr(expr)),
+            DebugAttrValue::Locations(ref list) => self.write_locations(list),
+            DebugAttrValue::Parameters(ref parameters) => self.write_array(parameters, |w, parameter| {
+                w.formatter.begin_object(w.out)?;
+                w.write_key("locations", true)?;
+                match parameter.locations {
+                    Some(ref list) => w.write_locations(list)?,
+                    None => w.formatter.write_null(w.out)?,
+                }
+                w.formatter.end_object_value(w.out)?;
+                w.write_key("name", false)?;
+                match parameter.name {
+                    Some(name) => w.write_str(name)?,
+                    None => w.formatter.write_null(w.out)?,
+                }
+                w.formatter.end_object_value(w.out)?;
+                w.write_key("type", false)?;
+                match parameter.type_ref {
+                    Some((uid, name)) => w.write_attr(&DebugAttrValue::UIDRef(uid, name))?,
+                    None => w.formatter.write_null(w.out)?,
+                }
+                w.formatter.end_object_value(w.out)?;
+                w.formatter.end_object(w.out)
+            }),
+            DebugAttrValue::TemplateParameters(ref parameters) => self.write_array(parameters, |w, parameter| {
+                w.formatter.begin_object(w.out)?;
+                w.write_key("kind", true)?;
+                w.write_str(if parameter.is_value { "value" } else { "type" })?;
+                w.formatter.end_object_value(w.out)?;
+                w.write_key("name", false)?;
+                match parameter.name {
+                    Some(name) => w.write_str(name)?,
+                    None => w.formatter.write_null(w.out)?,
                 }
-                DebugAttrValue::LocationList(list) => {
-                    let mut r = Vec::new();
-                    for item in list {
-                        let mut dict = Map::new();
-                        dict.insert(
-                            "range".to_string(),
-                            json!(vec![json!(item.0), json!(item.1)]),
-                        );
-                        dict.insert("expr".to_string(), convert_expr(item.2)?);
-                        r.push(dict);
+                w.formatter.end_object_value(w.out)?;
+                w.write_key("type", false)?;
+                match parameter.type_ref {
+                    Some((uid, name)) => w.write_attr(&DebugAttrValue::UIDRef(uid, name))?,
+                    None => w.formatter.write_null(w.out)?,
+                }
+                w.formatter.end_object_value(w.out)?;
+                if let Some(value) = parameter.value {
+                    w.write_key("value", false)?;
+                    w.formatter.write_i64(w.out, value)?;
+                    w.formatter.end_object_value(w.out)?;
+                }
+                w.formatter.end_object(w.out)
+            }),
+            DebugAttrValue::UID(uid) => self.formatter.write_u64(self.out, uid as u64),
+            DebugAttrValue::UIDRef(uid, name) => {
+                self.formatter.begin_object(self.out)?;
+                if let Some(name) = name {
+                    self.write_key("name", true)?;
+                    self.write_str(name)?;
+                    self.formatter.end_object_value(self.out)?;
+                }
+                self.write_key("uid", name.is_none())?;
+                self.formatter.write_u64(self.out, uid as u64)?;
+                self.formatter.end_object_value(self.out)?;
+                self.formatter.end_object(self.out)
+            }
+            DebugAttrValue::Ignored => self.write_str("<ignored>"),
+            DebugAttrValue::Unknown => self.write_str("???"),
+        }
+    }
+
+    /// Writes the entries as elements of the `debug_info` array, which
+    /// already has elements unless `first` is set.
+    fn write_scope_entries(&mut self, infos: &[DebugInfoObj], first: bool) -> io::Result<()> {
+        for (i, item) in infos.iter().enumerate() {
+            self.formatter.begin_array_value(self.out, first && i == 0)?;
+            self.write_scope(item)?;
+            self.formatter.end_array_value(self.out)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an entry with its subtree. Generated code may nest lexical
+    /// blocks very deeply, so the tree is walked with an explicit stack
+    /// rather than by recursion.
+    fn write_scope(&mut self, item: &DebugInfoObj) -> io::Result<()> {
+        self.formatter.begin_object(self.out)?;
+        let mut stack = vec![Frame::Fields(entry_fields(item), 0)];
+        while let Some(frame) = stack.last_mut() {
+            match *frame {
+                Frame::Entries(ref entries, ref mut next) => {
+                    let entries = *entries;
+                    if *next < entries.len() {
+                        let first = *next == 0;
+                        let item = &entries[*next];
+                        *next += 1;
+                        self.formatter.begin_array_value(self.out, first)?;
+                        self.formatter.begin_object(self.out)?;
+                        stack.push(Frame::Fields(entry_fields(item), 0));
+                        continue;
                     }
-                    json!(r)
+                    self.formatter.end_array(self.out)?;
                 }
-                DebugAttrValue::Expression(expr) => convert_expr(expr)?,
-                DebugAttrValue::UID(uid) => json!(uid),
-                DebugAttrValue::UIDRef(uid, name) => {
-                    let mut dict = Map::new();
-                    dict.insert("uid".to_string(), json!(uid));
-                    if let Some(s) = name {
-                        dict.insert("name".to_string(), json!(s));
+                Frame::Fields(ref fields, ref mut next) => {
+                    if *next < fields.len() {
+                        let (ref key, field) = fields[*next];
+                        self.write_key(key, *next == 0)?;
+                        *next += 1;
+                        match field {
+                            EntryField::Children(children) => {
+                                self.formatter.begin_array(self.out)?;
+                                stack.push(Frame::Entries(children, 0));
+                            }
+                            EntryField::Tag(tag) => {
+                                self.write_str(tag)?;
+                                self.formatter.end_object_value(self.out)?;
+                            }
+                            EntryField::Attr(value) => {
+                                self.write_attr(value)?;
+                                self.formatter.end_object_value(self.out)?;
+                            }
+                        }
+                        continue;
                     }
-                    json!(dict)
+                    self.formatter.end_object(self.out)?;
                 }
-                DebugAttrValue::Ignored => json!("<ignored>"),
-                DebugAttrValue::Unknown => json!("???"),
-            };
-            dict.insert(attr_name.to_string(), value);
+            }
+            // The frame is complete: close the value it is nested in.
+            stack.pop();
+            match stack.last() {
+                Some(Frame::Entries(..)) => self.formatter.end_array_value(self.out)?,
+                Some(Frame::Fields(..)) => self.formatter.end_object_value(self.out)?,
+                None => (),
+            }
         }
-        if !entry.children.is_empty() {
-            dict.insert("children".to_string(), convert_scopes(&entry.children)?);
+        Ok(())
+    }
+
+    fn write_field(&mut self, field: &Field) -> io::Result<()> {
+        match *field {
+            Field::Value(value) => self.write_value(value),
+            Field::Sources(sources) => self.write_array(sources, |w, source| w.write_str(source)),
+            Field::Mappings(di, code_section_offset) => {
+                // The mappings are encoded straight into the output rather
+                // than into a string value; they never need escaping.
+                self.formatter.begin_string(self.out)?;
+                encode_mappings(di, code_section_offset, self.out);
+                self.formatter.end_string(self.out)
+            }
+            Field::Scopes(debug_info, tables, code_section_offset) => {
+                self.formatter.begin_object(self.out)?;
+                self.write_key("code_section_offset", true)?;
+                self.formatter.write_i64(self.out, code_section_offset)?;
+                self.formatter.end_object_value(self.out)?;
+                self.write_key("debug_info", false)?;
+                self.out.extend_from_slice(debug_info);
+                self.formatter.end_object_value(self.out)?;
+                for (key, table) in tables {
+                    self.write_key(key, false)?;
+                    self.write_value(table)?;
+                    self.formatter.end_object_value(self.out)?;
+                }
+                self.formatter.end_object(self.out)
+            }
         }
-        result.push(json!(dict));
     }
-    Ok(json!(result))
 }
 
-pub fn convert_debug_info_to_json(
-    di: &LocationInfo,
-    infos: Option<Vec<DebugInfoObj>>,
-    code_section_offset: i64,
-) -> Result<Vec<u8>, Error> {
-    let mut buffer = Vec::new();
+/// Serializes the `debug_info` array of `x-scopes` a unit at a time, so that
+/// the scopes of all units are not held in memory together.
+pub struct ScopesWriter {
+    out: Vec<u8>,
+    formatter: ScopesFormatter,
+    empty: bool,
+}
+
+enum ScopesFormatter {
+    Pretty(PrettyFormatter<'static>),
+    Compact(CompactFormatter),
+}
+
+// The writes go to memory and cannot fail.
+const WRITE_FAILED: &str = "failed to write the scopes";
+
+impl ScopesWriter {
+    pub fn new(format: OutputFormat) -> Self {
+        let mut out = Vec::new();
+        let formatter = match format {
+            OutputFormat::Pretty => {
+                // The array is nested in the root and `x-scopes` objects.
+                let mut formatter = PrettyFormatter::new();
+                formatter.begin_object(&mut io::sink()).expect(WRITE_FAILED);
+                formatter.begin_object(&mut io::sink()).expect(WRITE_FAILED);
+                formatter.begin_array(&mut out).expect(WRITE_FAILED);
+                ScopesFormatter::Pretty(formatter)
+            }
+            OutputFormat::Compact => {
+                let mut formatter = CompactFormatter;
+                formatter.begin_array(&mut out).expect(WRITE_FAILED);
+                ScopesFormatter::Compact(formatter)
+            }
+        };
+        ScopesWriter {
+            out,
+            formatter,
+            empty: true,
+        }
+    }
+
+    /// Appends the top-level entries of a unit.
+    pub fn write(&mut self, infos: &[DebugInfoObj]) {
+        let first = self.empty;
+        self.empty = self.empty && infos.is_empty();
+        let out = &mut self.out;
+        match self.formatter {
+            ScopesFormatter::Pretty(ref mut formatter) => {
+                JsonWriter { out, formatter }.write_scope_entries(infos, first)
+            }
+            ScopesFormatter::Compact(ref mut formatter) => {
+                JsonWriter { out, formatter }.write_scope_entries(infos, first)
+            }
+        }.expect(WRITE_FAILED);
+    }
+
+    /// Returns the serialized array.
+    pub fn finish(mut self) -> Vec<u8> {
+        match self.formatter {
+            ScopesFormatter::Pretty(ref mut formatter) => formatter.end_array(&mut self.out),
+            ScopesFormatter::Compact(ref mut formatter) => formatter.end_array(&mut self.out),
+        }.expect(WRITE_FAILED);
+        self.out
+    }
+}
+
+/// Estimates the size of the output from the number of line rows, so that
+/// the buffer of a large module is allocated once rather than grown by
+/// copying.
+fn estimate_size(root: &BTreeMap<&str, Field>) -> usize {
+    root.values()
+        .map(|field| match *field {
+            Field::Value(_) => 0,
+            Field::Sources(sources) => sources.iter().map(|source| source.len() + 8).sum(),
+            // Four short VLQ values and a comma per row.
+            Field::Mappings(di, _) => di.locations.len() * 8,
+            Field::Scopes(debug_info, _, _) => debug_info.len() + 64,
+        }).sum()
+}
+
+fn write_root(root: &BTreeMap<&str, Field>, format: OutputFormat) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(estimate_size(root));
+    let fields = root.iter().map(|(key, field)| (*key, field));
+    match format {
+        OutputFormat::Pretty => JsonWriter {
+            out: &mut out,
+            formatter: &mut PrettyFormatter::new(),
+        }.write_object(fields, JsonWriter::write_field),
+        OutputFormat::Compact => JsonWriter {
+            out: &mut out,
+            formatter: &mut CompactFormatter,
+        }.write_object(fields, JsonWriter::write_field),
+    }.map_err(|_| Error)?;
+    Ok(out)
+}
+
+/// Writes the VLQ `mappings` of the locations to `out`.
+fn encode_mappings(di: &LocationInfo, code_section_offset: i64, out: &mut Vec<u8>) {
     let mut last_address = 0;
     let mut last_source_id = 0;
     let mut last_line = 0;
     let mut last_column = 0;
+    let mut first = true;
     for loc in di.locations.iter() {
         if loc.line == 0 {
             continue;
         }
-        let address = loc.address as i64 + code_section_offset;
-        let address_delta = address - last_address;
-        encode(address_delta, &mut buffer).unwrap();
+        if !first {
+            out.push(b',');
+        }
+        first = false;
+        // Malformed line programs may have addresses past 2^63.
+        let address = (loc.address as i64).wrapping_add(code_section_offset);
+        let address_delta = address.wrapping_sub(last_address);
+        encode(address_delta, out).unwrap();
         let source_id = i64::from(loc.source_id);
         let source_id_delta = source_id - last_source_id;
-        encode(source_id_delta, &mut buffer).unwrap();
+        encode(source_id_delta, out).unwrap();
         let line = i64::from(loc.line) - 1;
         let line_delta = line - last_line;
-        encode(line_delta, &mut buffer).unwrap();
+        encode(line_delta, out).unwrap();
         let column = i64::from(if loc.column == 0 { 0 } else { loc.column - 1 });
         let column_delta = column - last_column;
-        encode(column_delta, &mut buffer).unwrap();
-        buffer.push(b',');
+        encode(column_delta, out).unwrap();
 
         last_address = address;
         last_source_id = source_id;
         last_line = line;
         last_column = column;
     }
+}
 
-    if !di.locations.is_empty() {
-        buffer.pop();
-    }
+/// Serializes scopes produced separately from the source map, with the
+/// `sources` their file ids refer to. The `debug_info` array is the result
+/// of a `ScopesWriter`; the `tables` are written next to it, e.g. the
+/// `types` their `type_id`s refer to.
+pub fn convert_scopes_to_json(
+    sources: &[Arc<str>],
+    debug_info: &[u8],
+    tables: &Map<String, Value>,
+    code_section_offset: i64,
+    format: OutputFormat,
+) -> Result<Vec<u8>, Error> {
+    let mut root = BTreeMap::new();
+    root.insert("sources", Field::Sources(sources));
+    root.insert("x-scopes", Field::Scopes(debug_info, tables, code_section_offset));
+    write_root(&root, format)
+}
 
-    let mappings = str::from_utf8(&buffer).unwrap();
-    let names: Vec<String> = Vec::new();
+pub fn convert_debug_info_to_json(
+    di: &LocationInfo,
+    debug_info: Option<&[u8]>,
+    tables: &Map<String, Value>,
+    code_section_offset: i64,
+    metadata: Map<String, Value>,
+    format: OutputFormat,
+) -> Result<Vec<u8>, Error> {
+    let version = json!(3);
+    let names = json!(Vec::<String>::new());
 
-    let mut root = Map::new();
-    root.insert("version".to_string(), json!(3));
-    root.insert("sources".to_string(), json!(di.sources));
-    root.insert("names".to_string(), json!(names));
-    root.insert("mappings".to_string(), json!(mappings));
-    if infos.is_some() {
-        let mut x_scopes = Map::new();
-        x_scopes.insert("debug_info".to_string(), convert_scopes(&infos.unwrap())?);
-        x_scopes.insert(
-            "code_section_offset".to_string(),
-            json!(code_section_offset),
-        );
-        root.insert("x-scopes".to_string(), json!(x_scopes));
+    let mut root = BTreeMap::new();
+    root.insert("version", Field::Value(&version));
+    root.insert("sources", Field::Sources(&di.sources));
+    root.insert("names", Field::Value(&names));
+    root.insert("mappings", Field::Mappings(di, code_section_offset));
+    if let Some(debug_info) = debug_info {
+        root.insert("x-scopes", Field::Scopes(debug_info, tables, code_section_offset));
+    }
+    for (key, value) in metadata.iter() {
+        root.insert(key, Field::Value(value));
     }
-    to_vec_pretty(&json!(root)).map_err(|_| Error)
+    write_root(&root, format)
 }
@@ -0,0 +1,839 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The table of the types the entries of `x-scopes` refer to, so that
+// debuggers can format values without looking the types up in the tree.
+// The types of all the units are in a single table: a named aggregate
+// defined in several units, or the same pointer or qualified type, gets a
+// single entry.
+
+use crate::dwarf::{AttrKey, DebugAttrValue, DebugInfoObj};
+use crate::wasm::WasmDecoder;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The tags of the entries that describe types.
+const TYPE_TAGS: &[&str] = &[
+    "array_type",
+    "atomic_type",
+    "base_type",
+    "class_type",
+    "const_type",
+    "enumeration_type",
+    "pointer_type",
+    "ptr_to_member_type",
+    "reference_type",
+    "restrict_type",
+    "rvalue_reference_type",
+    "structure_type",
+    "subroutine_type",
+    "typedef",
+    "union_type",
+    "unspecified_type",
+    "volatile_type",
+];
+
+/// The tags of the types that get their entry before their members are
+/// read, as the members may refer back to them. The named ones are the
+/// same type in all the units.
+const AGGREGATE_TAGS: &[&str] = &["class_type", "enumeration_type", "structure_type", "union_type"];
+
+/// The tags of the entries whose name qualifies the names of the types they
+/// contain.
+const SCOPE_TAGS: &[&str] =
+    &["class_type", "enumeration_type", "namespace", "structure_type", "union_type"];
+
+/// The tags of the children that are listed as the members of a type.
+const MEMBER_TAGS: &[&str] = &["member", "inheritance", "enumerator", "formal_parameter"];
+
+/// The attributes the table is built from, which are read even when they
+/// are not among the requested ones.
+pub const TYPE_ATTRS: &[&str] = &[
+    "name",
+    "byte_size",
+    "type",
+    "count",
+    "upper_bound",
+    "lower_bound",
+    "language",
+    "data_member_location",
+    "data_bit_offset",
+    "bit_offset",
+    "bit_size",
+    "const_value",
+    "virtuality",
+    "vtable_elem_location",
+    "containing_type",
+];
+
+/// The depth past which the display names are abbreviated, for the types
+/// that nest absurdly.
+const MAX_NAME_DEPTH: usize = 32;
+
+/// A type entry of a unit, with the uids of the types it refers to.
+struct UnitType<'a> {
+    tag: &'static str,
+    /// The name, qualified when the table has the qualified names.
+    name: Option<Cow<'a, str>>,
+    size: Option<i64>,
+    target: Option<usize>,
+    members: Vec<(Option<&'a str>, Option<usize>, MemberInfo)>,
+    counts: Vec<Option<i64>>,
+    methods: Vec<VirtualMethod<&'a str>>,
+    containing_type: Option<usize>,
+}
+
+/// A virtual method of a class.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct VirtualMethod<S> {
+    name: Option<S>,
+    pure: bool,
+    /// The index of the method in the virtual function table.
+    slot: Option<i64>,
+}
+
+/// Where a member of an aggregate is, or the value of an enumerator.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+struct MemberInfo {
+    /// The offset in the aggregate, in bytes.
+    offset: Option<i64>,
+    /// The size given by the member; the size of its type otherwise.
+    byte_size: Option<i64>,
+    /// The size of bit fields, in bits.
+    bit_size: Option<i64>,
+    /// The offset of bit fields from the start of the aggregate, in bits.
+    bit_offset: Option<i64>,
+    /// The offset of bit fields in DWARF 2, from the most significant bit
+    /// of their storage unit, which starts at `offset` and has the size of
+    /// the member.
+    msb_bit_offset: Option<i64>,
+    /// Whether the member is a base class.
+    inherited: bool,
+    /// Whether the member is a virtual base class.
+    virtual_base: bool,
+    /// The value of an enumerator.
+    value: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TypeRecord {
+    kind: &'static str,
+    name: Option<Arc<str>>,
+    size: Option<i64>,
+    /// The referenced type: the pointee, the element, the aliased type, etc.
+    target: Option<usize>,
+    /// The members of aggregates, the enumerators of enumerations, or the
+    /// parameters of subroutine types.
+    members: Vec<(Option<Arc<str>>, Option<usize>, MemberInfo)>,
+    /// The element counts of the dimensions of arrays, when known.
+    counts: Vec<Option<i64>>,
+    /// The virtual methods of classes.
+    methods: Vec<VirtualMethod<Arc<str>>>,
+    /// The class with the virtual function table pointer of a class.
+    containing_type: Option<usize>,
+    /// Whether the type is described by a Rust unit, whose names use the
+    /// Rust syntax.
+    rust: bool,
+    /// The name composed from the referenced types, e.g. `const char **`.
+    display_name: String,
+}
+
+/// The types of the unit being added to a `TypeTable`.
+struct UnitState<'u, 'a: 'u> {
+    types: &'u HashMap<usize, UnitType<'a>>,
+    /// The ids of the unit's types by uid; `None` while a type is being
+    /// resolved, or when it cannot be.
+    ids: HashMap<usize, Option<usize>>,
+    rust: bool,
+}
+
+/// A step of `TypeTable::resolve` for the type with a uid.
+enum Visit {
+    Enter(usize),
+    Complete(usize),
+}
+
+/// The id of a type of the unit that was resolved, `None` if it is being
+/// resolved (the references that form a cycle through derived types) or
+/// cannot be.
+fn resolved(unit: &UnitState, uid: usize) -> Option<usize> {
+    unit.ids.get(&uid).cloned().unwrap_or(None)
+}
+
+#[derive(Default)]
+pub struct TypeTable {
+    types: Vec<TypeRecord>,
+    /// The named aggregates by kind, name and size.
+    aggregates: HashMap<(&'static str, Arc<str>, Option<i64>), usize>,
+    /// The names of the types and of their members, each stored once.
+    names: HashSet<Arc<str>>,
+    /// The other types by content.
+    derived: HashMap<TypeRecord, usize>,
+    /// Whether the names are qualified with the enclosing namespaces and
+    /// types.
+    qualified_names: bool,
+}
+
+fn string_attr<'a>(item: &DebugInfoObj<'a>, name: gimli::DwAt) -> Option<&'a str> {
+    match item.attrs.get(&AttrKey::Dwarf(name)) {
+        Some(DebugAttrValue::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn int_attr(item: &DebugInfoObj, name: gimli::DwAt) -> Option<i64> {
+    match item.attrs.get(&AttrKey::Dwarf(name)) {
+        Some(DebugAttrValue::I64(i)) => Some(*i),
+        _ => None,
+    }
+}
+
+fn type_attr(item: &DebugInfoObj) -> Option<usize> {
+    match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_type)) {
+        Some(DebugAttrValue::UIDRef(uid, _)) => Some(*uid),
+        _ => None,
+    }
+}
+
+fn uid(item: &DebugInfoObj) -> Option<usize> {
+    match item.attrs.get(&AttrKey::Uid) {
+        Some(DebugAttrValue::UID(uid)) => Some(*uid),
+        _ => None,
+    }
+}
+
+/// The display name of an unnamed type, e.g. `(anonymous struct)`.
+fn anonymous_name(tag: &str) -> String {
+    let kind = match tag {
+        "structure_type" => "struct",
+        "enumeration_type" => "enum",
+        tag => tag.trim_end_matches("_type"),
+    };
+    format!("(anonymous {})", kind)
+}
+
+/// Places a declarator after the name of a type, e.g. `*` after `char` or
+/// `[4]` after `int`.
+fn with_declarator(name: String, inner: String) -> String {
+    if inner.is_empty() {
+        name
+    } else if inner.starts_with('[') {
+        name + &inner
+    } else {
+        format!("{} {}", name, inner)
+    }
+}
+
+/// Reads the layout of a member, or the value of an enumerator. The location is a constant, or an
+/// expression adding it to the address of the aggregate in older DWARF.
+fn member_info(item: &DebugInfoObj) -> MemberInfo {
+    let offset = match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_data_member_location)) {
+        Some(DebugAttrValue::I64(offset)) => Some(*offset),
+        // DW_OP_plus_uconst
+        Some(DebugAttrValue::Expression(expr)) if expr.first() == Some(&0x23) => {
+            WasmDecoder::new(&expr[1..]).u32().ok().map(i64::from)
+        }
+        _ => None,
+    };
+    MemberInfo {
+        offset,
+        byte_size: int_attr(item, gimli::DW_AT_byte_size),
+        bit_size: int_attr(item, gimli::DW_AT_bit_size),
+        bit_offset: int_attr(item, gimli::DW_AT_data_bit_offset),
+        msb_bit_offset: int_attr(item, gimli::DW_AT_bit_offset),
+        inherited: item.tag == "inheritance",
+        virtual_base: item.tag == "inheritance" && virtuality(item).is_some(),
+        value: int_attr(item, gimli::DW_AT_const_value),
+    }
+}
+
+/// Returns the virtuality of a method or base class, when it is virtual:
+/// `virtual` or `pure_virtual`.
+fn virtuality<'a>(item: &DebugInfoObj<'a>) -> Option<&'a str> {
+    string_attr(item, gimli::DW_AT_virtuality).filter(|&virtuality| virtuality != "none")
+}
+
+/// Reads a virtual method declaration.
+fn virtual_method<'a>(item: &DebugInfoObj<'a>) -> Option<VirtualMethod<&'a str>> {
+    let virtuality = virtuality(item)?;
+    let slot = match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_vtable_elem_location)) {
+        // DW_OP_constu
+        Some(DebugAttrValue::Expression(expr)) if expr.first() == Some(&0x10) => {
+            WasmDecoder::new(&expr[1..]).u32().ok().map(i64::from)
+        }
+        _ => None,
+    };
+    Some(VirtualMethod {
+        name: string_attr(item, gimli::DW_AT_name),
+        pure: virtuality == "pure_virtual",
+        slot,
+    })
+}
+
+/// Collects the constant values of the entries of a unit by uid, for the
+/// bounds that refer to a constant or a variable with a known value.
+fn unit_constants(items: &[DebugInfoObj]) -> HashMap<usize, i64> {
+    let mut constants = HashMap::new();
+    let mut pending: Vec<&DebugInfoObj> = items.iter().collect();
+    while let Some(item) = pending.pop() {
+        pending.extend(item.children.iter());
+        if let (Some(uid), Some(value)) = (uid(item), int_attr(item, gimli::DW_AT_const_value)) {
+            constants.insert(uid, value);
+        }
+    }
+    constants
+}
+
+/// Returns the element count of an array dimension, from its count or its
+/// bounds, which are constants or refer to an entry with a constant value.
+/// The count of variable length and flexible arrays is unknown.
+fn subrange_count(item: &DebugInfoObj, constants: &HashMap<usize, i64>) -> Option<i64> {
+    let bound = |name| match item.attrs.get(&AttrKey::Dwarf(name)) {
+        Some(&DebugAttrValue::I64(value)) => Some(value),
+        Some(DebugAttrValue::UIDRef(uid, _)) => constants.get(uid).cloned(),
+        _ => None,
+    };
+    let count = bound(gimli::DW_AT_count).or_else(|| {
+        let lower_bound = bound(gimli::DW_AT_lower_bound).unwrap_or(0);
+        bound(gimli::DW_AT_upper_bound)?.checked_sub(lower_bound)?.checked_add(1)
+    })?;
+    if count >= 0 {
+        Some(count)
+    } else {
+        None
+    }
+}
+
+/// Collects the type entries of a unit by uid. With a `separator`, their
+/// names are qualified with the names of the enclosing namespaces and
+/// types.
+fn unit_types<'a>(
+    items: &[DebugInfoObj<'a>],
+    separator: Option<&str>,
+) -> HashMap<usize, UnitType<'a>> {
+    let constants = unit_constants(items);
+    let mut types = HashMap::new();
+    // The entries with the qualified name of their scope.
+    let mut pending: Vec<(&DebugInfoObj<'a>, Option<Rc<str>>)> =
+        items.iter().map(|item| (item, None)).collect();
+    while let Some((item, scope)) = pending.pop() {
+        let qualify = |name: &'a str| match (&scope, separator) {
+            (Some(scope), Some(separator)) => Cow::Owned(format!("{}{}{}", scope, separator, name)),
+            _ => Cow::Borrowed(name),
+        };
+        let name = match string_attr(item, gimli::DW_AT_name) {
+            Some(name) => Some(qualify(name)),
+            None if item.tag == "namespace" => Some(qualify("(anonymous namespace)")),
+            None => None,
+        };
+        let child_scope = match name {
+            Some(ref name) if separator.is_some() && SCOPE_TAGS.contains(&item.tag) => {
+                Some(Rc::from(name.as_ref()))
+            }
+            _ => scope.clone(),
+        };
+        pending.extend(item.children.iter().map(|child| (child, child_scope.clone())));
+        if !TYPE_TAGS.contains(&item.tag) {
+            continue;
+        }
+        let uid = match uid(item) {
+            Some(uid) => uid,
+            None => continue,
+        };
+        let members = item
+            .children
+            .iter()
+            .filter(|child| MEMBER_TAGS.contains(&child.tag))
+            .map(|child| {
+                let name = string_attr(child, gimli::DW_AT_name);
+                (name, type_attr(child), member_info(child))
+            }).collect();
+        let methods = item
+            .children
+            .iter()
+            .filter(|child| child.tag == "subprogram")
+            .filter_map(virtual_method)
+            .collect();
+        let counts = item
+            .children
+            .iter()
+            .filter(|child| child.tag == "subrange_type")
+            .map(|child| subrange_count(child, &constants))
+            .collect();
+        let containing_type = match item.attrs.get(&AttrKey::Dwarf(gimli::DW_AT_containing_type)) {
+            Some(DebugAttrValue::UIDRef(uid, _)) => Some(*uid),
+            _ => None,
+        };
+        types.insert(
+            uid,
+            UnitType {
+                tag: item.tag,
+                name,
+                size: int_attr(item, gimli::DW_AT_byte_size),
+                target: type_attr(item),
+                members,
+                counts,
+                methods,
+                containing_type,
+            },
+        );
+    }
+    types
+}
+
+impl TypeTable {
+    /// With `qualified_names`, the names of the types are qualified with
+    /// the enclosing namespaces and types, with the separator of the
+    /// language of their unit, e.g. `std::string` in C++.
+    pub fn new(qualified_names: bool) -> Self {
+        TypeTable {
+            qualified_names,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the types of the entries of a unit to the table, and sets the
+    /// `type_id` of the entries that have a type.
+    pub fn add_unit(&mut self, items: &mut Vec<DebugInfoObj>) {
+        let language = items
+            .iter()
+            .find(|item| item.tag == "compile_unit")
+            .and_then(|item| string_attr(item, gimli::DW_AT_language));
+        let rust = language == Some("Rust");
+        let separator = match language {
+            _ if !self.qualified_names => None,
+            Some(language) if rust || language.starts_with("C_plus_plus") => Some("::"),
+            _ => Some("."),
+        };
+        let types = unit_types(items, separator);
+        let mut unit = UnitState {
+            types: &types,
+            ids: HashMap::new(),
+            rust,
+        };
+        let mut pending: Vec<&mut DebugInfoObj> = items.iter_mut().collect();
+        while let Some(item) = pending.pop() {
+            if let Some(target) = type_attr(item) {
+                if let Some(id) = self.resolve(&mut unit, target) {
+                    item.attrs.insert(AttrKey::TypeId, DebugAttrValue::I64(id as i64));
+                }
+            }
+            pending.extend(item.children.iter_mut());
+        }
+    }
+
+    /// Returns the id of the type with the `uid` in the unit, adding it and
+    /// the types it refers to when they are not in the table yet. Generated
+    /// code may chain type references very deeply, so they are followed with
+    /// an explicit stack rather than by recursion: each type is entered, then
+    /// the types it refers to are resolved, then it is completed. The
+    /// aggregates get their id when they are entered, so that the types
+    /// referring back to them resolve.
+    fn resolve(&mut self, unit: &mut UnitState, uid: usize) -> Option<usize> {
+        let types = unit.types;
+        let mut pending = vec![Visit::Enter(uid)];
+        while let Some(visit) = pending.pop() {
+            match visit {
+                Visit::Enter(uid) => {
+                    if unit.ids.contains_key(&uid) {
+                        continue;
+                    }
+                    let ty = match types.get(&uid) {
+                        Some(ty) => ty,
+                        None => continue,
+                    };
+                    pending.push(Visit::Complete(uid));
+                    let mut references = Vec::new();
+                    if AGGREGATE_TAGS.contains(&ty.tag) {
+                        let id = self.enter_aggregate(unit, ty);
+                        unit.ids.insert(uid, Some(id));
+                        let record = &self.types[id];
+                        // The first unit with the members provides them.
+                        if record.members.is_empty() {
+                            references.extend(ty.members.iter().filter_map(|member| member.1));
+                        }
+                        if record.target.is_none() {
+                            references.extend(ty.target);
+                        }
+                        if record.containing_type.is_none() {
+                            references.extend(ty.containing_type);
+                        }
+                    } else {
+                        unit.ids.insert(uid, None);
+                        references.extend(ty.target);
+                        references.extend(ty.members.iter().filter_map(|member| member.1));
+                    }
+                    pending.extend(references.into_iter().rev().map(Visit::Enter));
+                }
+                Visit::Complete(uid) => {
+                    let ty = &types[&uid];
+                    if AGGREGATE_TAGS.contains(&ty.tag) {
+                        self.complete_aggregate(unit, uid, ty);
+                    } else {
+                        let id = self.complete_derived(unit, ty);
+                        unit.ids.insert(uid, Some(id));
+                    }
+                }
+            }
+        }
+        resolved(unit, uid)
+    }
+
+    /// Returns the id of an aggregate type, adding it without its references
+    /// when it is not in the table yet.
+    fn enter_aggregate(&mut self, unit: &UnitState, ty: &UnitType) -> usize {
+        let name = ty.name.as_ref().map(|name| self.intern(name));
+        let key = name.clone().map(|name| (ty.tag, name, ty.size));
+        if let Some(&id) = key.as_ref().and_then(|key| self.aggregates.get(key)) {
+            return id;
+        }
+        let id = self.types.len();
+        let display_name = match name {
+            Some(ref name) => name.to_string(),
+            None => anonymous_name(ty.tag),
+        };
+        self.types.push(TypeRecord {
+            kind: ty.tag,
+            name,
+            size: ty.size,
+            target: None,
+            members: Vec::new(),
+            counts: Vec::new(),
+            methods: Vec::new(),
+            containing_type: None,
+            rust: unit.rust,
+            display_name,
+        });
+        if let Some(key) = key {
+            self.aggregates.insert(key, id);
+        }
+        id
+    }
+
+    /// Adds the references of an aggregate type once they are resolved.
+    fn complete_aggregate(&mut self, unit: &UnitState, uid: usize, ty: &UnitType) {
+        let id = match resolved(unit, uid) {
+            Some(id) => id,
+            None => return,
+        };
+        if self.types[id].members.is_empty() && !ty.members.is_empty() {
+            self.types[id].members = self.resolved_members(unit, &ty.members);
+        }
+        if self.types[id].methods.is_empty() && !ty.methods.is_empty() {
+            self.types[id].methods = ty
+                .methods
+                .iter()
+                .map(|method| VirtualMethod {
+                    name: method.name.map(|name| self.intern(name)),
+                    pure: method.pure,
+                    slot: method.slot,
+                }).collect();
+        }
+        if self.types[id].target.is_none() {
+            self.types[id].target = ty.target.and_then(|target| resolved(unit, target));
+        }
+        if self.types[id].containing_type.is_none() {
+            self.types[id].containing_type = ty.containing_type.and_then(|uid| resolved(unit, uid));
+        }
+    }
+
+    /// Returns the id of a derived type once its references are resolved,
+    /// adding it when the table has no identical type yet.
+    fn complete_derived(&mut self, unit: &UnitState, ty: &UnitType) -> usize {
+        let mut record = TypeRecord {
+            kind: ty.tag,
+            name: ty.name.as_ref().map(|name| self.intern(name)),
+            size: ty.size,
+            target: ty.target.and_then(|target| resolved(unit, target)),
+            members: self.resolved_members(unit, &ty.members),
+            counts: ty.counts.clone(),
+            methods: Vec::new(),
+            containing_type: None,
+            rust: unit.rust,
+            display_name: String::new(),
+        };
+        record.display_name = self.declare(&record, String::new(), 0);
+        match self.derived.get(&record) {
+            Some(&id) => id,
+            None => {
+                let id = self.types.len();
+                self.types.push(record.clone());
+                self.derived.insert(record, id);
+                id
+            }
+        }
+    }
+
+    fn resolved_members(
+        &mut self,
+        unit: &UnitState,
+        members: &[(Option<&str>, Option<usize>, MemberInfo)],
+    ) -> Vec<(Option<Arc<str>>, Option<usize>, MemberInfo)> {
+        members
+            .iter()
+            .map(|&(name, target, info)| {
+                let id = target.and_then(|target| resolved(unit, target));
+                (name.map(|name| self.intern(name)), id, info)
+            }).collect()
+    }
+
+    fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(name) = self.names.get(name) {
+            return name.clone();
+        }
+        let name: Arc<str> = name.into();
+        self.names.insert(name.clone());
+        name
+    }
+
+    /// Returns the size of the type, looking through typedefs and
+    /// qualifiers, and multiplying the element size of arrays.
+    pub fn type_size(&self, id: usize) -> Option<i64> {
+        let mut id = id;
+        // The product of the counts of the enclosing arrays.
+        let mut count = 1i64;
+        // The table has no cycles through these kinds.
+        loop {
+            let record = &self.types[id];
+            if let Some(size) = record.size {
+                return Some(size.saturating_mul(count));
+            }
+            match record.kind {
+                "typedef" | "const_type" | "volatile_type" | "restrict_type" | "atomic_type" => {
+                    id = record.target?;
+                }
+                "array_type" => {
+                    count = record.counts.iter().try_fold(count, |product, count| {
+                        count.map(|count| product.saturating_mul(count))
+                    })?;
+                    id = record.target?;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Composes the declaration of the type around the `inner` declarator,
+    /// in the C syntax unless the type is described by a Rust unit, e.g.
+    /// `char` around `*const` is `char *const`. The named types keep their
+    /// name.
+    fn declare(&self, record: &TypeRecord, inner: String, depth: usize) -> String {
+        if depth > MAX_NAME_DEPTH {
+            return with_declarator("...".to_string(), inner);
+        }
+        if let Some(ref name) = record.name {
+            return with_declarator(name.to_string(), inner);
+        }
+        let target_kind = record.target.map(|target| self.types[target].kind);
+        match record.kind {
+            "pointer_type" | "reference_type" | "rvalue_reference_type" | "ptr_to_member_type" => {
+                let sigil = match record.kind {
+                    "reference_type" => "&",
+                    "rvalue_reference_type" => "&&",
+                    _ => "*",
+                };
+                if record.rust {
+                    let pointee = self.declare_target(record.target, String::new(), depth);
+                    let prefix = if sigil == "*" { "*const " } else { sigil };
+                    return with_declarator(format!("{}{}", prefix, pointee), inner);
+                }
+                let inner = format!("{}{}", sigil, inner);
+                // The pointers to arrays and functions are parenthesized.
+                let inner = match target_kind {
+                    Some("array_type") | Some("subroutine_type") => format!("({})", inner),
+                    _ => inner,
+                };
+                self.declare_target(record.target, inner, depth)
+            }
+            "const_type" | "volatile_type" | "restrict_type" => {
+                let qualifier = &record.kind[..record.kind.len() - "_type".len()];
+                match target_kind {
+                    // The qualifiers of pointers follow the `*`.
+                    Some("pointer_type") | Some("reference_type") | Some("rvalue_reference_type") => {
+                        let inner = if inner.is_empty() {
+                            qualifier.to_string()
+                        } else {
+                            format!("{} {}", qualifier, inner)
+                        };
+                        self.declare_target(record.target, inner, depth)
+                    }
+                    _ => format!("{} {}", qualifier, self.declare_target(record.target, inner, depth)),
+                }
+            }
+            "atomic_type" => {
+                let name = format!("_Atomic({})", self.declare_target(record.target, String::new(), depth));
+                with_declarator(name, inner)
+            }
+            "array_type" => {
+                let count = |count: &Option<i64>| count.map_or(String::new(), |count| count.to_string());
+                if record.rust {
+                    let mut name = self.declare_target(record.target, String::new(), depth);
+                    for dimension in record.counts.iter().rev() {
+                        name = format!("[{}; {}]", name, count(dimension));
+                    }
+                    return with_declarator(name, inner);
+                }
+                let dimensions: String = record
+                    .counts
+                    .iter()
+                    .map(|dimension| format!("[{}]", count(dimension)))
+                    .collect();
+                self.declare_target(record.target, inner + &dimensions, depth)
+            }
+            "subroutine_type" => {
+                let parameters: Vec<String> = record
+                    .members
+                    .iter()
+                    .map(|&(_, target, _)| self.declare_target(target, String::new(), depth))
+                    .collect();
+                let inner = format!("{}({})", inner, parameters.join(", "));
+                self.declare_target(record.target, inner, depth)
+            }
+            kind => with_declarator(anonymous_name(kind), inner),
+        }
+    }
+
+    /// Declares the referenced type, which is `void` when there is none.
+    fn declare_target(&self, target: Option<usize>, inner: String, depth: usize) -> String {
+        match target {
+            Some(target) => self.declare(&self.types[target], inner, depth + 1),
+            None => with_declarator("void".to_string(), inner),
+        }
+    }
+
+    fn member_to_json(
+        &self,
+        name: Option<&str>,
+        target: Option<usize>,
+        info: MemberInfo,
+    ) -> Value {
+        let mut member = Map::new();
+        member.insert("name".to_string(), json!(name));
+        member.insert("type".to_string(), json!(target));
+        if let Some(offset) = info.offset {
+            member.insert("offset".to_string(), json!(offset));
+        }
+        let size = info.byte_size.or_else(|| target.and_then(|id| self.type_size(id)));
+        if let Some(size) = size {
+            member.insert("byte_size".to_string(), json!(size));
+        }
+        if let Some(bit_size) = info.bit_size {
+            // The offset is from the start of the aggregate, in the little
+            // endian order of wasm.
+            let bit_offset = info.bit_offset.or_else(|| {
+                let storage_bits = size?.checked_mul(8)?;
+                info.offset
+                    .unwrap_or(0)
+                    .checked_mul(8)?
+                    .checked_add(storage_bits)?
+                    .checked_sub(info.msb_bit_offset?)?
+                    .checked_sub(bit_size)
+            });
+            member.insert("bit_size".to_string(), json!(bit_size));
+            member.insert("bit_offset".to_string(), json!(bit_offset));
+        }
+        if info.inherited {
+            member.insert("inherited".to_string(), json!(true));
+        }
+        if info.virtual_base {
+            member.insert("virtual".to_string(), json!(true));
+        }
+        Value::Object(member)
+    }
+
+    pub fn display_name(&self, id: usize) -> &str {
+        &self.types[id].display_name
+    }
+
+    /// Follows a chain of typedefs to the type they name.
+    fn underlying_type(&self, id: usize) -> usize {
+        let mut id = id;
+        while let ("typedef", Some(target)) = (self.types[id].kind, self.types[id].target) {
+            id = target;
+        }
+        id
+    }
+
+    /// Returns the `types` array of `x-scopes`, indexed by the `type_id`s.
+    /// With `collapse_aliases`, the typedefs refer to the type at the end of
+    /// their chain of typedefs rather than to the next one.
+    pub fn to_json(&self, collapse_aliases: bool) -> Value {
+        let types = self
+            .types
+            .iter()
+            .map(|record| {
+                let mut fields = Map::new();
+                fields.insert("kind".to_string(), json!(record.kind));
+                if let Some(ref name) = record.name {
+                    fields.insert("name".to_string(), json!(&**name));
+                }
+                if let Some(size) = record.size {
+                    fields.insert("byte_size".to_string(), json!(size));
+                }
+                if let Some(mut target) = record.target {
+                    if record.kind == "typedef" {
+                        let underlying = self.underlying_type(target);
+                        fields.insert("underlying_type".to_string(), json!(underlying));
+                        if collapse_aliases {
+                            target = underlying;
+                        }
+                    }
+                    fields.insert("type".to_string(), json!(target));
+                }
+                if record.kind == "enumeration_type" {
+                    let enumerators = record
+                        .members
+                        .iter()
+                        .map(|(name, _, info)| {
+                            json!({ "name": name.as_deref(), "value": info.value })
+                        }).collect::<Vec<_>>();
+                    fields.insert("enumerators".to_string(), json!(enumerators));
+                } else if !record.members.is_empty() {
+                    let members = record
+                        .members
+                        .iter()
+                        .map(|&(ref name, target, info)| {
+                            self.member_to_json(name.as_deref(), target, info)
+                        }).collect::<Vec<_>>();
+                    fields.insert("members".to_string(), json!(members));
+                }
+                if !record.counts.is_empty() {
+                    fields.insert("counts".to_string(), json!(record.counts));
+                }
+                if !record.methods.is_empty() {
+                    let methods = record
+                        .methods
+                        .iter()
+                        .map(|method| {
+                            json!({
+                                "name": method.name.as_deref(),
+                                "pure": method.pure,
+                                "vtable_slot": method.slot,
+                            })
+                        }).collect::<Vec<_>>();
+                    fields.insert("virtual_methods".to_string(), json!(methods));
+                }
+                if let Some(containing_type) = record.containing_type {
+                    fields.insert("containing_type".to_string(), json!(containing_type));
+                }
+                fields.insert("display_name".to_string(), json!(record.display_name));
+                Value::Object(fields)
+            }).collect();
+        Value::Array(types)
+    }
+}
@@ -0,0 +1,161 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// An optional check of the produced map, for diagnosing the debug
+// information written by linkers and other tools: the mappings must have
+// increasing addresses within the code section, and the ranges of the
+// entries of `x-scopes` must be within the ranges of their parent.
+
+use crate::dwarf::{AttrKey, DebugAttrValue, DebugInfoObj, LocationInfo};
+
+/// The number of addresses listed with each kind of violation.
+const MAX_EXAMPLES: usize = 5;
+
+/// The occurrences of one kind of violation.
+struct Violations {
+    description: &'static str,
+    count: usize,
+    examples: Vec<String>,
+}
+
+impl Violations {
+    fn new(description: &'static str) -> Self {
+        Violations {
+            description,
+            count: 0,
+            examples: Vec::new(),
+        }
+    }
+
+    fn add<F: FnOnce() -> String>(&mut self, example: F) {
+        self.count += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(example());
+        }
+    }
+
+    fn report(&self, messages: &mut Vec<String>) {
+        if self.count > 0 {
+            let more = if self.count > self.examples.len() { ", ..." } else { "" };
+            messages.push(format!(
+                "verification: {} {} ({}{})",
+                self.count,
+                self.description,
+                self.examples.join(", "),
+                more
+            ));
+        }
+    }
+}
+
+/// Checks the mappings: their addresses, which are module offsets, must
+/// increase and be before `code_end`, the end of the code in the DWARF
+/// address space, when it is known.
+pub fn verify_locations(
+    info: &LocationInfo,
+    code_section_offset: u64,
+    code_end: Option<u64>,
+) -> Vec<String> {
+    let mut repeated = Violations::new("mappings have the address of the previous one");
+    let mut outside = Violations::new("mappings are past the end of the code section");
+    let mut last_address = None;
+    for loc in info.locations.iter().filter(|loc| loc.line != 0) {
+        let address = loc.address.wrapping_add(code_section_offset);
+        if last_address.map_or(false, |last| address <= last) {
+            repeated.add(|| format!("0x{:x}", address));
+        }
+        if code_end.map_or(false, |end| loc.address >= end) {
+            outside.add(|| format!("0x{:x}", address));
+        }
+        last_address = Some(address);
+    }
+    let mut messages = Vec::new();
+    repeated.report(&mut messages);
+    outside.report(&mut messages);
+    messages
+}
+
+fn entry_ranges(item: &DebugInfoObj) -> Option<Vec<(i64, i64)>> {
+    match (item.attrs.get(&AttrKey::LOW_PC), item.attrs.get(&AttrKey::HIGH_PC)) {
+        (Some(&DebugAttrValue::I64(low_pc)), Some(&DebugAttrValue::I64(high_pc))) => {
+            return Some(vec![(low_pc, high_pc)]);
+        }
+        _ => (),
+    }
+    match item.attrs.get(&AttrKey::RANGES) {
+        Some(DebugAttrValue::Ranges(ranges)) => Some(ranges.clone()),
+        _ => None,
+    }
+}
+
+fn uid(item: &DebugInfoObj) -> usize {
+    match item.attrs.get(&AttrKey::Uid) {
+        Some(&DebugAttrValue::UID(uid)) => uid,
+        _ => 0,
+    }
+}
+
+/// Checks the ranges of the entries of `x-scopes`, unit by unit.
+pub struct ScopesVerifier {
+    inverted: Violations,
+    outside: Violations,
+}
+
+impl ScopesVerifier {
+    pub fn new() -> Self {
+        ScopesVerifier {
+            inverted: Violations::new("ranges of x-scopes entries end before they start"),
+            outside: Violations::new("ranges of x-scopes entries are not within their parent"),
+        }
+    }
+
+    pub fn add_unit(&mut self, items: &[DebugInfoObj]) {
+        // The entries with the ranges of their closest ancestor that has
+        // some.
+        let mut pending: Vec<(&DebugInfoObj, Option<Vec<(i64, i64)>>)> =
+            items.iter().map(|item| (item, None)).collect();
+        while let Some((item, parent_ranges)) = pending.pop() {
+            if item.attrs.contains_key(&AttrKey::Dead) {
+                continue;
+            }
+            let ranges = entry_ranges(item);
+            if let Some(ref ranges) = ranges {
+                for &(begin, end) in ranges.iter() {
+                    let example = || format!("uid {} [0x{:x}, 0x{:x})", uid(item), begin, end);
+                    if end < begin {
+                        self.inverted.add(example);
+                    } else if let Some(ref parent_ranges) = parent_ranges {
+                        let within = parent_ranges.iter().any(|&(parent_begin, parent_end)| {
+                            parent_begin <= begin && end <= parent_end
+                        });
+                        if begin < end && !within {
+                            self.outside.add(example);
+                        }
+                    }
+                }
+            }
+            let ranges = ranges.or(parent_ranges);
+            pending.extend(item.children.iter().map(|child| (child, ranges.clone())));
+        }
+    }
+
+    /// Returns the descriptions of the violations.
+    pub fn finish(self) -> Vec<String> {
+        let mut messages = Vec::new();
+        self.inverted.report(&mut messages);
+        self.outside.report(&mut messages);
+        messages
+    }
+}
@@ -13,69 +13,384 @@
  * limitations under the License.
  */
 
+use std::fmt;
 use std::result;
 use std::str;
 
-pub struct WasmFormatError;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WasmFormatErrorKind {
+    /// Wrong magic number or version.
+    InvalidHeader,
+    UnexpectedEof,
+    TruncatedLeb128,
+    Leb128Overflow,
+    InvalidUtf8,
+    /// A well-formed value that is out of range or unknown.
+    InvalidValue,
+}
+
+#[derive(Debug)]
+pub struct WasmFormatError {
+    pub kind: WasmFormatErrorKind,
+    /// Byte offset of the failing value from the start of the decoded data.
+    pub offset: usize,
+    /// What was being decoded, e.g. "section size".
+    pub context: Option<&'static str>,
+}
+
+impl WasmFormatError {
+    pub fn new(kind: WasmFormatErrorKind, offset: usize) -> WasmFormatError {
+        WasmFormatError {
+            kind,
+            offset,
+            context: None,
+        }
+    }
+}
+
+impl fmt::Display for WasmFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self.kind {
+            WasmFormatErrorKind::InvalidHeader => "bad magic number or version",
+            WasmFormatErrorKind::UnexpectedEof => "unexpected end of data",
+            WasmFormatErrorKind::TruncatedLeb128 => "truncated LEB128",
+            WasmFormatErrorKind::Leb128Overflow => "LEB128 value out of range",
+            WasmFormatErrorKind::InvalidUtf8 => "invalid UTF-8 string",
+            WasmFormatErrorKind::InvalidValue => "invalid value",
+        };
+        write!(f, "{} at offset {:#x}", description, self.offset)?;
+        if let Some(context) = self.context {
+            write!(f, " while reading {}", context)?;
+        }
+        Ok(())
+    }
+}
 
 pub type Result<T> = result::Result<T, WasmFormatError>;
 
-fn read_u32_leb128(slice: &[u8]) -> Result<(u32, usize)> {
-    let mut result: u32 = 0;
-    let mut shift = 0;
+/// Describes what was being decoded when an error occurred. The innermost
+/// context is kept.
+pub trait Context<T> {
+    fn context(self, context: &'static str) -> Result<T>;
+}
+
+impl<T> Context<T> for Result<T> {
+    fn context(self, context: &'static str) -> Result<T> {
+        self.map_err(|mut err| {
+            err.context = err.context.or(Some(context));
+            err
+        })
+    }
+}
+
+type LebResult<T> = result::Result<(T, usize), WasmFormatErrorKind>;
+
+// Some tools pad LEB128 values with redundant continuation bytes to patch
+// them later, so any length is accepted as long as the value fits.
+
+// The section scans decode a lot of short values, so the encodings that
+// cannot overflow are decoded first without the checks, in loops of a fixed
+// bound that the compiler unrolls.
+
+fn read_u64_leb128(slice: &[u8]) -> LebResult<u64> {
+    let mut result: u64 = 0;
+    for (i, &byte) in slice.iter().take(9).enumerate() {
+        result |= u64::from(byte & 0x7F) << (7 * i);
+        if (byte & 0x80) == 0 {
+            return Ok((result, i + 1));
+        }
+    }
+    read_long_u64_leb128(slice)
+}
+
+fn read_long_u64_leb128(slice: &[u8]) -> LebResult<u64> {
+    let mut result: u64 = 0;
+    let mut shift: u32 = 0;
     let mut position = 0;
 
-    for _ in 0..5 {
-        let byte = unsafe { *slice.get_unchecked(position) };
+    loop {
+        let byte = *slice.get(position).ok_or(WasmFormatErrorKind::TruncatedLeb128)?;
         position += 1;
-        result |= u32::from(byte & 0x7F) << shift;
+        let payload = u64::from(byte & 0x7F);
+        if shift >= 64 || (shift > 57 && (payload >> (64 - shift)) != 0) {
+            if payload != 0 {
+                return Err(WasmFormatErrorKind::Leb128Overflow);
+            }
+        } else {
+            result |= payload << shift;
+        }
         if (byte & 0x80) == 0 {
             break;
         }
-        shift += 7;
+        shift = shift.saturating_add(7);
+    }
+    Ok((result, position))
+}
+
+fn read_u32_leb128(slice: &[u8]) -> LebResult<u32> {
+    let mut result: u32 = 0;
+    for (i, &byte) in slice.iter().take(4).enumerate() {
+        result |= u32::from(byte & 0x7F) << (7 * i);
+        if (byte & 0x80) == 0 {
+            return Ok((result, i + 1));
+        }
     }
+    let (result, position) = read_u64_leb128(slice)?;
+    if result > u64::from(u32::max_value()) {
+        return Err(WasmFormatErrorKind::Leb128Overflow);
+    }
+    Ok((result as u32, position))
+}
+
+fn read_i64_sleb128(slice: &[u8]) -> LebResult<i64> {
+    let mut result: i64 = 0;
+    let mut shift: u32 = 0;
+    let mut position = 0;
 
-    // Do a single bounds check at the end instead of for every byte.
-    if position > slice.len() {
-        return Err(WasmFormatError);
+    loop {
+        let byte = *slice.get(position).ok_or(WasmFormatErrorKind::TruncatedLeb128)?;
+        position += 1;
+        let payload = i64::from(byte & 0x7F);
+        if shift < 63 {
+            result |= payload << shift;
+        } else {
+            // Only the lowest bit of the 10th byte fits, the remaining bits
+            // and any padding must repeat the sign.
+            let sign = if shift == 63 { payload & 1 } else { (result >> 63) & 1 };
+            if payload != sign * 0x7F {
+                return Err(WasmFormatErrorKind::Leb128Overflow);
+            }
+            result |= payload << 63;
+        }
+        shift = shift.saturating_add(7);
+        if (byte & 0x80) == 0 {
+            if shift < 64 && (byte & 0x40) != 0 {
+                result |= -1 << shift;
+            }
+            break;
+        }
     }
     Ok((result, position))
 }
 
 pub struct WasmDecoder<'a> {
     data: &'a [u8],
+    /// Offset of `data` from the start of the decoded input, for errors.
+    offset: usize,
 }
 
 impl<'a> WasmDecoder<'a> {
     pub fn new(data: &'a [u8]) -> WasmDecoder<'a> {
-        WasmDecoder { data }
+        WasmDecoder::at(data, 0)
     }
 
-    pub fn len(&self) -> usize {
-        self.data.len()
+    /// Creates a decoder for `data` found at `offset` in the input.
+    pub fn at(data: &'a [u8], offset: usize) -> WasmDecoder<'a> {
+        WasmDecoder { data, offset }
     }
 
     pub fn eof(&self) -> bool {
         self.data.is_empty()
     }
 
-    pub fn u32(&mut self) -> Result<u32> {
-        let (n, l1) = read_u32_leb128(self.data)?;
-        self.data = &self.data[l1..];
+    /// Returns the current position in the input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns an error of the `kind` at the current position.
+    pub fn error(&self, kind: WasmFormatErrorKind) -> WasmFormatError {
+        WasmFormatError::new(kind, self.offset)
+    }
+
+    fn advance(&mut self, amt: usize) -> &'a [u8] {
+        let (data, rest) = self.data.split_at(amt);
+        self.data = rest;
+        self.offset += amt;
+        data
+    }
+
+    fn leb<T>(&mut self, result: LebResult<T>) -> Result<T> {
+        let (n, len) = result.map_err(|kind| self.error(kind))?;
+        self.advance(len);
         Ok(n)
     }
 
+    pub fn u32(&mut self) -> Result<u32> {
+        let result = read_u32_leb128(self.data);
+        self.leb(result)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        if self.data.is_empty() {
+            return Err(self.error(WasmFormatErrorKind::UnexpectedEof));
+        }
+        Ok(self.advance(1)[0])
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let result = read_u64_leb128(self.data);
+        self.leb(result)
+    }
+
+    pub fn i64(&mut self) -> Result<i64> {
+        let result = read_i64_sleb128(self.data);
+        self.leb(result)
+    }
+
     pub fn skip(&mut self, amt: usize) -> Result<&'a [u8]> {
         if amt > self.data.len() {
-            return Err(WasmFormatError);
+            return Err(self.error(WasmFormatErrorKind::UnexpectedEof));
         }
-        let (data, rest) = self.data.split_at(amt);
-        self.data = rest;
-        Ok(data)
+        Ok(self.advance(amt))
     }
 
     pub fn str(&mut self) -> Result<&'a str> {
         let len = self.u32()?;
-        str::from_utf8(self.skip(len as usize)?).map_err(|_| WasmFormatError)
+        let offset = self.offset;
+        str::from_utf8(self.skip(len as usize)?)
+            .map_err(|_| WasmFormatError::new(WasmFormatErrorKind::InvalidUtf8, offset))
+    }
+}
+
+pub fn write_u32_leb128(output: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+pub fn write_u64_leb128(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+pub fn write_str(output: &mut Vec<u8>, s: &str) {
+    write_u32_leb128(output, s.len() as u32);
+    output.extend_from_slice(s.as_bytes());
+}
+
+pub fn write_custom_section(output: &mut Vec<u8>, name: &str, payload: &[u8]) {
+    let mut name_bytes = Vec::new();
+    write_str(&mut name_bytes, name);
+    output.push(0);
+    write_u32_leb128(output, (name_bytes.len() + payload.len()) as u32);
+    output.extend_from_slice(&name_bytes);
+    output.extend_from_slice(payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u32_leb128() {
+        assert_eq!(read_u32_leb128(&[0x00]), Ok((0, 1)));
+        assert_eq!(read_u32_leb128(&[0xe5, 0x8e, 0x26, 0xff]), Ok((624_485, 3)));
+        assert_eq!(read_u32_leb128(&[0xff, 0xff, 0xff, 0xff, 0x0f]), Ok((u32::max_value(), 5)));
+        assert_eq!(
+            read_u32_leb128(&[0x80, 0x80, 0x80, 0x80, 0x10]),
+            Err(WasmFormatErrorKind::Leb128Overflow)
+        );
+        assert_eq!(
+            read_u32_leb128(&[0xff, 0xff, 0xff, 0xff, 0x1f]),
+            Err(WasmFormatErrorKind::Leb128Overflow)
+        );
+    }
+
+    #[test]
+    fn padded_leb128() {
+        // A size padded to five bytes, as written by linkers to patch it.
+        assert_eq!(read_u32_leb128(&[0x84, 0x80, 0x80, 0x80, 0x00]), Ok((4, 5)));
+        // Padding past the width of the value is accepted while it is zero.
+        assert_eq!(read_u32_leb128(&[0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]), Ok((1, 7)));
+        let mut padded = vec![0x80; 12];
+        padded.push(0x00);
+        assert_eq!(read_u64_leb128(&padded), Ok((0, 13)));
+        assert_eq!(read_i64_sleb128(&padded), Ok((0, 13)));
+        let mut padded = vec![0xff; 12];
+        padded.push(0x7f);
+        assert_eq!(read_i64_sleb128(&padded), Ok((-1, 13)));
+        // Overlong encodings with bits past the width are rejected.
+        let mut overlong = vec![0x80; 11];
+        overlong.push(0x01);
+        assert_eq!(read_u64_leb128(&overlong), Err(WasmFormatErrorKind::Leb128Overflow));
+    }
+
+    #[test]
+    fn u64_leb128() {
+        let mut max = vec![0xff; 9];
+        max.push(0x01);
+        assert_eq!(read_u64_leb128(&max), Ok((u64::max_value(), 10)));
+        let mut past_max = vec![0xff; 9];
+        past_max.push(0x02);
+        assert_eq!(read_u64_leb128(&past_max), Err(WasmFormatErrorKind::Leb128Overflow));
+        let mut past_max = vec![0x80; 9];
+        past_max.extend_from_slice(&[0x80, 0x01]);
+        assert_eq!(read_u64_leb128(&past_max), Err(WasmFormatErrorKind::Leb128Overflow));
+        // The largest value of the fast path, then the smallest of the other.
+        let mut fast = vec![0xff; 8];
+        fast.push(0x7f);
+        assert_eq!(read_u64_leb128(&fast), Ok(((1 << 63) - 1, 9)));
+        let mut slow = vec![0x80; 9];
+        slow.push(0x01);
+        assert_eq!(read_u64_leb128(&slow), Ok((1 << 63, 10)));
+        let u32_max = u64::from(u32::max_value());
+        for &value in &[0, 1, 127, 128, u32_max, u32_max + 1, u64::max_value()] {
+            let mut encoded = Vec::new();
+            write_u64_leb128(&mut encoded, value);
+            assert_eq!(read_u64_leb128(&encoded), Ok((value, encoded.len())));
+        }
+    }
+
+    #[test]
+    fn i64_sleb128() {
+        assert_eq!(read_i64_sleb128(&[0x02]), Ok((2, 1)));
+        assert_eq!(read_i64_sleb128(&[0x7e]), Ok((-2, 1)));
+        assert_eq!(read_i64_sleb128(&[0xff, 0x00]), Ok((127, 2)));
+        assert_eq!(read_i64_sleb128(&[0x80, 0x7f]), Ok((-128, 2)));
+        // Only the lowest bit of the 10th byte is a value bit, and the other
+        // bits must repeat it.
+        let mut max = vec![0xff; 9];
+        max.push(0x00);
+        assert_eq!(read_i64_sleb128(&max), Ok((i64::max_value(), 10)));
+        let mut min = vec![0x80; 9];
+        min.push(0x7f);
+        assert_eq!(read_i64_sleb128(&min), Ok((i64::min_value(), 10)));
+        let mut past_max = vec![0xff; 9];
+        past_max.push(0x01);
+        assert_eq!(read_i64_sleb128(&past_max), Err(WasmFormatErrorKind::Leb128Overflow));
+        let mut past_min = vec![0x80; 9];
+        past_min.push(0x7e);
+        assert_eq!(read_i64_sleb128(&past_min), Err(WasmFormatErrorKind::Leb128Overflow));
+        // Padding past the 10th byte must repeat the sign as well.
+        let mut padded = vec![0x80; 9];
+        padded.extend_from_slice(&[0xff, 0x7f]);
+        assert_eq!(read_i64_sleb128(&padded), Ok((i64::min_value(), 11)));
+        let mut padded = vec![0x80; 9];
+        padded.extend_from_slice(&[0xff, 0x00]);
+        assert_eq!(read_i64_sleb128(&padded), Err(WasmFormatErrorKind::Leb128Overflow));
+    }
+
+    #[test]
+    fn truncated_leb128() {
+        assert_eq!(read_u32_leb128(&[]), Err(WasmFormatErrorKind::TruncatedLeb128));
+        assert_eq!(read_u32_leb128(&[0x80, 0x80]), Err(WasmFormatErrorKind::TruncatedLeb128));
+        assert_eq!(read_u64_leb128(&[0xff; 9]), Err(WasmFormatErrorKind::TruncatedLeb128));
+        assert_eq!(read_u64_leb128(&[0x80; 20]), Err(WasmFormatErrorKind::TruncatedLeb128));
+        assert_eq!(read_i64_sleb128(&[]), Err(WasmFormatErrorKind::TruncatedLeb128));
+        assert_eq!(read_i64_sleb128(&[0xff; 10]), Err(WasmFormatErrorKind::TruncatedLeb128));
     }
 }
@@ -0,0 +1,84 @@
+/* Copyright 2018 Mozilla Foundation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Checks the output of conversions against `OUTPUT_SCHEMA`.
+
+extern crate dwarf_to_json;
+extern crate jsonschema;
+extern crate serde_json;
+
+use dwarf_to_json::convert::{convert, ConvertOptions, OUTPUT_SCHEMA};
+use jsonschema::JSONSchema;
+use serde_json::Value;
+
+#[path = "../benches/generate/mod.rs"]
+mod generate;
+
+fn assert_matches_schema(output: &Value) {
+    let schema: Value = serde_json::from_str(OUTPUT_SCHEMA).expect("invalid schema JSON");
+    let schema = JSONSchema::compile(&schema).expect("invalid schema");
+    if let Err(errors) = schema.validate(output) {
+        let errors: Vec<String> = errors
+            .map(|error| format!("{} at {}", error, error.instance_path))
+            .collect();
+        panic!("the output does not match the schema:\n{}", errors.join("\n"));
+    };
+}
+
+fn convert_to_json(input: &[u8], options: &ConvertOptions) -> Value {
+    let json = convert(input, options).expect("failed to convert the module");
+    serde_json::from_slice(&json).expect("invalid output JSON")
+}
+
+#[test]
+fn mappings_match_schema() {
+    let module = generate::generate_module(2, 3);
+    let options = ConvertOptions {
+        function_offsets: true,
+        column_spans: true,
+        ..Default::default()
+    };
+    assert_matches_schema(&convert_to_json(&module, &options));
+}
+
+#[test]
+fn scopes_match_schema() {
+    let module = generate::generate_module(2, 3);
+    let options = ConvertOptions {
+        x_scopes: true,
+        types: true,
+        globals: true,
+        ..Default::default()
+    };
+    let output = convert_to_json(&module, &options);
+    assert!(!output["x-scopes"]["debug_info"].as_array().unwrap().is_empty());
+    assert_matches_schema(&output);
+}
+
+#[test]
+fn empty_attribute_arrays_match_schema() {
+    // The ranges and location lists of a split module are emptied when
+    // all their code moved to another module.
+    let module = generate::generate_module(1, 1);
+    let options = ConvertOptions {
+        x_scopes: true,
+        ..Default::default()
+    };
+    let mut output = convert_to_json(&module, &options);
+    let unit = &mut output["x-scopes"]["debug_info"][0];
+    unit["location"] = Value::Array(Vec::new());
+    unit["ranges"] = Value::Array(Vec::new());
+    assert_matches_schema(&output);
+}